@@ -0,0 +1,660 @@
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{mpsc::channel, Arc},
+    thread::available_parallelism,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::{
+    core::{
+        analyzer::{self, Analyzer, AnalyzerConfig, AnalyzerPool},
+        config::{absolute_root, Config, ConfigError},
+        interop::{
+            engine, rekordbox_device, rekordbox_xml, replace_folder, traktor_nml, PendingImports,
+        },
+        library::{Library, Scanner},
+        output::{OutputBackend, OutputError},
+    },
+    view::model::{
+        cue::{HotCue, Loop},
+        track::Track,
+    },
+};
+
+/// everything worked
+pub const EXIT_SUCCESS: i32 = 0;
+/// the command failed, e.g. the library could not be opened or an export could not be written
+pub const EXIT_FAILURE: i32 = 1;
+// 2 is used by clap for invalid arguments
+/// the command finished, but some tracks could not be analyzed or imported
+pub const EXIT_PARTIAL: i32 = 3;
+
+//------------------------------------------------------------------//
+//                            Arguments                             //
+//------------------------------------------------------------------//
+
+/// A terminal DJ player. Without a command, the player starts.
+#[derive(Parser)]
+#[clap(name = "flow", version)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    /// print JSON instead of tables
+    #[clap(long, global = true)]
+    pub json: bool,
+    /// config file, defaults to ~/.config/flow/config.toml
+    #[clap(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// directory with tracks, replaces the roots of the config file. Can be given multiple times.
+    #[clap(long = "root", global = true, value_name = "DIR")]
+    pub roots: Vec<PathBuf>,
+    /// only list tracks matching a glob pattern, e.g. '**/*.flac'. Can be given multiple times.
+    #[clap(long, global = true, value_name = "PATTERN")]
+    pub include: Vec<String>,
+    /// skip tracks and directories matching a glob pattern, e.g. '**/Samples/**'. Can be given
+    /// multiple times.
+    #[clap(long, global = true, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+    /// follow symbolic links to tracks and directories
+    #[clap(long, global = true)]
+    pub follow_symlinks: bool,
+    /// do not follow symbolic links, even if the config file says so
+    #[clap(long, global = true)]
+    pub no_follow_symlinks: bool,
+    /// fold detected tempos into a range, e.g. 160-180 for drum and bass
+    #[clap(long, global = true, value_name = "MIN-MAX", value_parser = parse_bpm_range)]
+    pub bpm_range: Option<Range<f64>>,
+    /// audio backend of the player: pulse, alsa, jack, null or file:<path>
+    #[clap(long, value_name = "BACKEND", value_parser = parse_backend)]
+    pub output: Option<OutputBackend>,
+    /// second audio backend for the headphone cue bus, e.g. jack
+    #[clap(long, value_name = "BACKEND", value_parser = parse_backend)]
+    pub cue_output: Option<OutputBackend>,
+    /// number of decks, 2 or 4
+    #[clap(long, value_parser = parse_decks)]
+    pub decks: Option<usize>,
+    /// seconds to skip with beat jumps on tracks without a beatgrid
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub skip: Option<f64>,
+    /// seconds to skip with phrase jumps on tracks without a beatgrid
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub long_skip: Option<f64>,
+    /// directory for exports and imports in the player, e.g. /media/usb
+    #[clap(long, value_name = "DIR")]
+    pub export_dir: Option<PathBuf>,
+}
+
+impl Cli {
+    /// the config file, that is read and written
+    pub fn config_path(&self) -> Option<PathBuf> {
+        self.config.clone().or_else(Config::default_path)
+    }
+
+    /// loads the config file and applies the options of the command line. Include and exclude
+    /// patterns are added to the ones of the config file.
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        let mut config = match self.config_path() {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        };
+        if !self.roots.is_empty() {
+            let current_dir = std::env::current_dir()?;
+            config.roots = self
+                .roots
+                .iter()
+                .map(|root| absolute_root(root, &current_dir))
+                .collect();
+        }
+        config.include.extend(self.include.iter().cloned());
+        config.exclude.extend(self.exclude.iter().cloned());
+        if self.follow_symlinks {
+            config.follow_symlinks = true;
+        }
+        if self.no_follow_symlinks {
+            config.follow_symlinks = false;
+        }
+        // reject invalid patterns before anything is scanned
+        Scanner::new(&config)?;
+        Ok(config)
+    }
+}
+
+/// parses a range like "70-140"
+fn parse_bpm_range(s: &str) -> Result<Range<f64>, String> {
+    let range = s.split_once('-').and_then(|(min, max)| {
+        let min: f64 = min.trim().parse().ok()?;
+        let max: f64 = max.trim().parse().ok()?;
+        (min > 0. && min < max).then(|| min..max)
+    });
+    range.ok_or_else(|| String::from("expected a range like 70-140"))
+}
+
+/// parses a backend name like "pulse" or "file:/tmp/out.wav"
+fn parse_backend(s: &str) -> Result<OutputBackend, String> {
+    s.parse().map_err(|err: OutputError| err.to_string())
+}
+
+/// parses a number of decks, that the ui can show
+fn parse_decks(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(decks @ (2 | 4)) => Ok(decks),
+        _ => Err(String::from("expected 2 or 4")),
+    }
+}
+
+/// parses a positive number of seconds like "20" or "0.5"
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(seconds) if seconds > 0. && seconds.is_finite() => Ok(seconds),
+        _ => Err(String::from("expected a positive number")),
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// analyze all tracks in a directory and store the results in the library
+    Analyze {
+        /// defaults to the library roots
+        dir: Option<PathBuf>,
+        /// analyze tracks again, even if the library has results for them
+        #[clap(long)]
+        force: bool,
+        /// number of tracks, that are analyzed at the same time, defaults to the number of cores
+        #[clap(long, short)]
+        jobs: Option<usize>,
+    },
+    /// print the analysis results of a track, it gets analyzed if the library has none
+    Info { file: PathBuf },
+    /// export all analyzed tracks and the playlists into a directory
+    Export {
+        #[clap(long, value_enum)]
+        format: ExportFormat,
+        dest: PathBuf,
+    },
+    /// import cues, beatgrids and playlists of the analyzed tracks from another DJ software's
+    /// collection
+    Import {
+        #[clap(long, value_enum)]
+        format: ImportFormat,
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// rekordbox.xml
+    RekordboxXml,
+    /// export.pdb and ANLZ files for Pioneer DJ players
+    Pdb,
+    /// Engine Library for Denon DJ players
+    Engine,
+    /// Traktor's collection.nml
+    TraktorNml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImportFormat {
+    /// rekordbox.xml
+    RekordboxXml,
+    /// Traktor's collection.nml
+    TraktorNml,
+}
+
+//------------------------------------------------------------------//
+//                              Output                              //
+//------------------------------------------------------------------//
+
+/// What the commands print about a track
+#[derive(Serialize)]
+struct TrackInfo {
+    file_path: String,
+    title: String,
+    artist: String,
+    album: String,
+    /// length in seconds
+    duration: Option<f64>,
+    bpm: Option<f64>,
+    /// e.g. "Am"
+    key: Option<String>,
+    /// e.g. "8A"
+    camelot: Option<String>,
+    /// position of the first downbeat of the beatgrid in seconds
+    first_downbeat: Option<f64>,
+    mem_cues: Vec<CueInfo>,
+    hot_cues: Vec<CueInfo>,
+    loops: Vec<Loop>,
+}
+
+#[derive(Serialize)]
+struct CueInfo {
+    /// the name of the hot cue slot, None for memory cues
+    slot: Option<String>,
+    /// position in seconds
+    position: f64,
+    label: String,
+}
+
+impl TrackInfo {
+    fn of(track: &Track) -> Self {
+        let meta = track.meta.read().unwrap();
+        Self {
+            file_path: track.file_path.clone(),
+            title: meta.title.clone(),
+            artist: meta.artist.clone(),
+            album: meta.album.clone(),
+            duration: track.duration(),
+            bpm: meta.bpm.map(|bpm| bpm.value),
+            key: meta.key.map(|key| key.classical()),
+            camelot: meta.key.map(|key| key.camelot()),
+            first_downbeat: track.beatgrid().map(|beatgrid| beatgrid.first_downbeat),
+            mem_cues: track
+                .mem_cues()
+                .into_iter()
+                .map(|cue| CueInfo {
+                    slot: None,
+                    position: cue.position,
+                    label: cue.label,
+                })
+                .collect(),
+            hot_cues: track
+                .hot_cues()
+                .into_iter()
+                .map(|(slot, cue)| CueInfo {
+                    slot: Some(HotCue::slot_name(slot)),
+                    position: cue.position,
+                    label: cue.label,
+                })
+                .collect(),
+            loops: track.loops(),
+        }
+    }
+}
+
+/// The result of analyzing one file
+#[derive(Serialize)]
+struct AnalysisResult {
+    file_path: String,
+    /// "analyzed", "cached" or "failed"
+    status: &'static str,
+    track: Option<TrackInfo>,
+}
+
+impl AnalysisResult {
+    /// a line of the analysis table
+    fn row(&self) -> String {
+        let bpm = self
+            .track
+            .as_ref()
+            .and_then(|track| track.bpm)
+            .map_or(String::from("-"), |bpm| format!("{:.2}", bpm));
+        let key = self
+            .track
+            .as_ref()
+            .and_then(|track| track.camelot.clone())
+            .unwrap_or_else(|| String::from("-"));
+        format!(
+            "{:<8} {:>7} {:>4}  {}",
+            self.status, bpm, key, self.file_path
+        )
+    }
+}
+
+/// prints a value as pretty JSON
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(err) => eprintln!("failed to serialize output: {}", err),
+    }
+}
+
+//------------------------------------------------------------------//
+//                             Commands                             //
+//------------------------------------------------------------------//
+
+/// runs a command without the terminal UI and returns the exit code
+pub fn run(command: Command, json: bool, config: &Config, analyzer_config: AnalyzerConfig) -> i32 {
+    let library = match Library::open_default() {
+        Ok(library) => library,
+        Err(err) => {
+            eprintln!("failed to open library: {:?}", err);
+            return EXIT_FAILURE;
+        }
+    };
+    match command {
+        Command::Analyze { dir, force, jobs } => {
+            let scanner = match Scanner::new(config) {
+                Ok(scanner) => scanner,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return EXIT_FAILURE;
+                }
+            };
+            let files = match dir {
+                Some(dir) => {
+                    if !dir.is_dir() {
+                        eprintln!("not a directory: {}", dir.display());
+                        return EXIT_FAILURE;
+                    }
+                    match scanner.scan(&dir) {
+                        Ok(files) => files,
+                        Err(err) => {
+                            eprintln!("failed to scan {}: {}", dir.display(), err);
+                            return EXIT_FAILURE;
+                        }
+                    }
+                }
+                None => {
+                    if config.roots.is_empty() {
+                        eprintln!("no library roots, add them with --root or to the config file");
+                        return EXIT_FAILURE;
+                    }
+                    scanner.scan_roots(&config.roots)
+                }
+            };
+            analyze(&library, files, force, jobs, analyzer_config, json)
+        }
+        Command::Info { file } => info(&library, &file, analyzer_config, json),
+        Command::Export { format, dest } => export(&library, format, &dest, json),
+        Command::Import { format, path } => import(&library, format, &path, json),
+    }
+}
+
+/// analyzes all new or changed tracks of a list of files with a number of workers. In table
+/// mode, every track is printed as soon as it is done, so long runs can be followed.
+fn analyze(
+    library: &Library,
+    files: Vec<String>,
+    force: bool,
+    jobs: Option<usize>,
+    config: AnalyzerConfig,
+    json: bool,
+) -> i32 {
+    let mut results = vec![];
+    let mut pending = vec![];
+    for file in files {
+        let track = if force { None } else { library.load(&file) };
+        match track {
+            Some(track) => results.push(AnalysisResult {
+                file_path: file,
+                status: "cached",
+                track: Some(TrackInfo::of(&track)),
+            }),
+            None => pending.push(file),
+        }
+    }
+    if !json {
+        for result in &results {
+            println!("{}", result.row());
+        }
+    }
+    let jobs = jobs
+        .or_else(|| available_parallelism().ok().map(|jobs| jobs.get()))
+        .unwrap_or(1)
+        .clamp(1, pending.len().max(1));
+    let (events_out, events_in) = channel::<analyzer::Event>();
+    let pool = AnalyzerPool::spawn(Some(jobs), config, events_out);
+    for file in pending {
+        pool.analyze(file);
+    }
+    // the events end, once the workers are done with all files
+    drop(pool);
+    // tracks, that are being analyzed
+    let mut analyzing: HashMap<String, Arc<Track>> = HashMap::new();
+    let mut failed = 0;
+    for event in events_in {
+        let (file_path, track) = match event {
+            analyzer::Event::NewTrack(track) => {
+                analyzing.insert(track.file_path.clone(), track);
+                continue;
+            }
+            analyzer::Event::DoneAnalyzing(file_path) => {
+                let track = analyzing.remove(&file_path);
+                (file_path, track)
+            }
+            analyzer::Event::Failed(file_path) => {
+                analyzing.remove(&file_path);
+                (file_path, None)
+            }
+        };
+        let stored = track.filter(|track| match library.store(track) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("failed to store {} in library: {:?}", track.file_path, err);
+                false
+            }
+        });
+        let result = match stored {
+            Some(track) => AnalysisResult {
+                file_path,
+                status: "analyzed",
+                track: Some(TrackInfo::of(&track)),
+            },
+            None => {
+                failed += 1;
+                AnalysisResult {
+                    file_path,
+                    status: "failed",
+                    track: None,
+                }
+            }
+        };
+        if !json {
+            println!("{}", result.row());
+        }
+        results.push(result);
+    }
+    if json {
+        print_json(&results);
+    }
+    if failed > 0 {
+        eprintln!("{} of {} tracks failed", failed, results.len());
+        EXIT_PARTIAL
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+/// analyzes a single file on the current thread. Returns None, if the file can not be decoded.
+fn analyze_file(file_path: &str, config: AnalyzerConfig) -> Option<Arc<Track>> {
+    let (events_out, events_in) = channel::<analyzer::Event>();
+    // the analyzer panics on files, it can not read
+    let finished = Analyzer::spawn(String::from(file_path), config, events_out)
+        .join()
+        .is_ok();
+    let track = events_in.try_iter().find_map(|event| match event {
+        analyzer::Event::NewTrack(track) => Some(track),
+        _ => None,
+    })?;
+    (finished && track.is_analyzed()).then(|| track)
+}
+
+/// prints the stored analysis of a file, or analyzes and stores it first
+fn info(library: &Library, file: &Path, config: AnalyzerConfig, json: bool) -> i32 {
+    let file_path = match file.canonicalize() {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(err) => {
+            eprintln!("failed to open {}: {}", file.display(), err);
+            return EXIT_FAILURE;
+        }
+    };
+    let track = match library.load(&file_path) {
+        Some(track) => Arc::new(track),
+        None => match analyze_file(&file_path, config) {
+            Some(track) => {
+                if let Err(err) = library.store(&track) {
+                    eprintln!("failed to store {} in library: {:?}", file_path, err);
+                }
+                track
+            }
+            None => {
+                eprintln!("failed to analyze {}", file_path);
+                return EXIT_FAILURE;
+            }
+        },
+    };
+    let info = TrackInfo::of(&track);
+    if json {
+        print_json(&info);
+        return EXIT_SUCCESS;
+    }
+    let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+    println!("File:      {}", info.file_path);
+    println!("Title:     {}", info.title);
+    println!("Artist:    {}", info.artist);
+    println!("Album:     {}", info.album);
+    println!(
+        "Duration:  {}",
+        optional(info.duration.map(|duration| format!(
+            "{}:{:02}",
+            (duration / 60.) as u64,
+            (duration % 60.) as u64
+        )))
+    );
+    println!(
+        "BPM:       {}",
+        optional(info.bpm.map(|bpm| format!("{:.2}", bpm)))
+    );
+    println!(
+        "Key:       {}",
+        optional(
+            info.key
+                .as_ref()
+                .zip(info.camelot.as_ref())
+                .map(|(key, camelot)| format!("{} ({})", key, camelot))
+        )
+    );
+    println!(
+        "Downbeat:  {}",
+        optional(
+            info.first_downbeat
+                .map(|position| format!("{:.3}s", position))
+        )
+    );
+    for cue in info.hot_cues.iter().chain(&info.mem_cues) {
+        let name = match &cue.slot {
+            Some(slot) => format!("Hot cue {}", slot),
+            None => String::from("Cue"),
+        };
+        println!("{:<10} {:>9.3}s  {}", name, cue.position, cue.label);
+    }
+    for saved_loop in &info.loops {
+        println!(
+            "{:<10} {:>9.3}s  {:.3}s",
+            "Loop", saved_loop.start, saved_loop.end
+        );
+    }
+    EXIT_SUCCESS
+}
+
+/// exports all analyzed tracks of the library and its playlists
+fn export(library: &Library, format: ExportFormat, dest: &Path, json: bool) -> i32 {
+    let tracks: Vec<Arc<Track>> = library.load_all().into_iter().map(Arc::new).collect();
+    let playlists = library.load_playlists();
+    if let Err(err) = fs::create_dir_all(dest) {
+        eprintln!("export failed: {}", err);
+        return EXIT_FAILURE;
+    }
+    let result = match format {
+        ExportFormat::RekordboxXml => {
+            rekordbox_xml::export(&tracks, &playlists, &dest.join("rekordbox.xml"))
+        }
+        ExportFormat::Pdb => rekordbox_device::export(&tracks, &playlists, dest),
+        ExportFormat::Engine => engine::export(&tracks, &playlists, dest),
+        ExportFormat::TraktorNml => {
+            traktor_nml::export(&tracks, &playlists, &dest.join("collection.nml"))
+        }
+    };
+    match result {
+        Ok(()) => {
+            if json {
+                print_json(&serde_json::json!({
+                    "tracks": tracks.len(),
+                    "dest": dest,
+                }));
+            } else {
+                println!("Exported {} tracks to {}", tracks.len(), dest.display());
+            }
+            EXIT_SUCCESS
+        }
+        Err(err) => {
+            eprintln!("export failed: {}", err);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// applies an imported collection to the analyzed tracks of the library and puts its playlists
+/// into a folder named after the format. Tracks, that were not analyzed yet, are skipped.
+fn import(library: &Library, format: ImportFormat, path: &Path, json: bool) -> i32 {
+    let (import, folder_name) = match format {
+        ImportFormat::RekordboxXml => (rekordbox_xml::import(path), "rekordbox"),
+        ImportFormat::TraktorNml => (traktor_nml::import(path), "traktor"),
+    };
+    let import = match import {
+        Ok(import) => import,
+        Err(err) => {
+            eprintln!("import failed: {}", err);
+            return EXIT_FAILURE;
+        }
+    };
+    // the same matching as in the player, so collections from other machines can be imported
+    let mut pending_imports = PendingImports::default();
+    for imported in import.tracks {
+        pending_imports.insert(imported);
+    }
+    let mut failed = vec![];
+    let mut applied = 0;
+    for track in library.load_all() {
+        let imported = match pending_imports.take(&track.file_path) {
+            Some(imported) => imported,
+            None => continue,
+        };
+        imported.apply_to(&track);
+        match library.store(&track) {
+            Ok(()) => applied += 1,
+            Err(err) => {
+                eprintln!("failed to store {}: {:?}", track.file_path, err);
+                failed.push(imported.file_path);
+            }
+        }
+    }
+    // imports without a track in the library and the ones, that could not be stored
+    let mut skipped = pending_imports.file_paths();
+    skipped.extend(failed);
+    let playlists = import.playlists.len();
+    if playlists > 0 {
+        let mut library_playlists = library.load_playlists();
+        replace_folder(&mut library_playlists, folder_name, import.playlists);
+        if let Err(err) = library.store_playlists(&library_playlists) {
+            eprintln!("failed to store playlists: {:?}", err);
+            return EXIT_FAILURE;
+        }
+    }
+    if json {
+        print_json(&serde_json::json!({
+            "applied": applied,
+            "skipped": skipped,
+            "playlists": playlists,
+        }));
+    } else {
+        println!(
+            "Imported {} tracks and {} playlists from {}",
+            applied,
+            playlists,
+            path.display()
+        );
+        for file_path in &skipped {
+            println!("skipped  {}", file_path);
+        }
+    }
+    if skipped.is_empty() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_PARTIAL
+    }
+}
@@ -1,11 +1,17 @@
 use crate::core::analyzer;
+use crate::core::interop::{serato, ImportedTrack};
 use crate::view::model;
+use crate::view::model::beatgrid::{Beatgrid, BARS_PER_PHRASE, BEATS_PER_BAR};
+use crate::view::model::cue::{CueKind, SuggestedCue};
+use crate::view::model::key::{Key, Mode};
+use crate::view::model::track::Bpm;
 use samplerate::{ConverterType, Samplerate};
 use std::{
+    f64::consts::TAU,
     iter::Sum,
     ops::Range,
-    sync::Arc,
-    thread::{spawn, JoinHandle},
+    sync::{mpsc::channel, Arc, Mutex},
+    thread::{available_parallelism, spawn, JoinHandle},
 };
 use synthrs::filter::{bandpass_filter, convolve, cutoff_from_frequency, lowpass_filter};
 use yata::methods::SMA;
@@ -31,6 +37,41 @@ use symphonia::core::{
 /// Determines the number of samples in the preview buffer per packet of the original source.
 /// Should be a multiple of number of channels
 pub const PREVIEW_SAMPLE_RATE: u32 = 2205;
+/// Beat intervals shorter than this (in seconds) are considered misdetections (> 240 BPM)
+const MIN_BEAT_INTERVAL: f64 = 0.25;
+/// Minimum number of detected beats for fitting a beatgrid
+const MIN_BEATS_FOR_BEATGRID: usize = 16;
+/// Number of detected beats, that are used for estimating the local tempo
+const TEMPO_CHANGE_WINDOW: usize = 64;
+/// Relative deviation of the local tempo from the grid, above which a tempo anchor is added
+const TEMPO_CHANGE_THRESHOLD: f64 = 0.02;
+/// Window and hop size of aubio's tempo detection in samples
+const TEMPO_BUF_SIZE: usize = 1024;
+const TEMPO_HOP_SIZE: usize = 512;
+/// Resolution of the energy envelope, that the downbeat is found with
+const ENERGY_BLOCKS_PER_SECOND: f64 = 100.;
+/// Sample rate, the signal is resampled to for key detection
+const KEY_SAMPLE_RATE: u32 = 11025;
+/// Number of samples per frame for key detection, gives a resolution of ~1.3Hz
+const KEY_FRAME_SIZE: usize = 8192;
+/// The notes (as midi numbers), that contribute to the chroma vector: C2 to B6
+const KEY_MIDI_NOTES: Range<u8> = 36..96;
+/// Krumhansl-Kessler key profiles, starting at the tonic
+const KEY_PROFILE_MAJOR: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const KEY_PROFILE_MINOR: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+/// Fraction of the loudest bar's energy, above which the music is considered to have started
+const INTRO_ENERGY: f64 = 0.05;
+/// Fraction of the loudest phrase's energy, above which a phrase belongs to the main part
+const MAIN_PART_ENERGY: f64 = 0.7;
+/// Factor, by which the low band energy has to rise from one phrase to the next for a drop
+const DROP_FACTOR: f64 = 1.5;
+/// Factor, below which the low band energy has to fall from one phrase to the next for a
+/// breakdown
+const BREAKDOWN_FACTOR: f64 = 0.5;
 
 /// This is a mono-summed, downsampled version of a number of decoded samples
 #[derive(Copy, Clone, Debug)]
@@ -61,10 +102,27 @@ pub enum AnalyzerError {
     NoTrackFound,
 }
 
+/// Settings, that influence the analysis results
+#[derive(Clone, Debug)]
+pub struct AnalyzerConfig {
+    /// detected tempos are folded into this range by halving or doubling them
+    pub bpm_range: Range<f64>,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        Self {
+            bpm_range: 70.0..140.0,
+        }
+    }
+}
+
 pub enum Event {
     /// This event fires, when a analyzer is done analyzing
     DoneAnalyzing(String),
     NewTrack(Arc<model::track::Track>),
+    /// This event fires, when a file of an AnalyzerPool can not be analyzed
+    Failed(String),
 }
 
 pub struct Analyzer {
@@ -78,8 +136,10 @@ pub struct Analyzer {
     reader: Box<dyn FormatReader>,
     /// Decoder
     decoder: Box<dyn Decoder>,
-    /// Local Cache for analyzed samples
-    sample_buf: Vec<f32>,
+    /// detects beats while the track is decoded, None if tempo and grid were imported
+    beat_tracker: Option<BeatTracker>,
+    /// accumulates the chroma while the track is decoded, None if the key was imported
+    key_detector: Option<KeyDetector>,
     /// Local Cache for downsampled samples
     preview_buf: Vec<f32>,
     /// a moving average filter over the analyzed data
@@ -90,7 +150,11 @@ pub struct Analyzer {
 }
 
 impl Analyzer {
-    pub fn spawn(file_path: String, analyzer_event_out: Sender<analyzer::Event>) -> JoinHandle<()> {
+    pub fn spawn(
+        file_path: String,
+        config: AnalyzerConfig,
+        analyzer_event_out: Sender<analyzer::Event>,
+    ) -> JoinHandle<()> {
         spawn(move || {
             let mut analyzer = Analyzer::new(file_path.clone(), analyzer_event_out);
             // messages
@@ -102,11 +166,14 @@ impl Analyzer {
                     Err(_) => {
                         // Error decoding
                         // this means the stream is done?
+                        analyzer.analyze_bpm(config.bpm_range);
+                        analyzer.analyze_key();
+                        analyzer.suggest_cues();
+                        analyzer.track.set_analyzed();
                         analyzer
                             .analyzer_event_out
                             .send(analyzer::Event::DoneAnalyzing(file_path))
                             .unwrap();
-                        analyzer.analyze_bpm(150..200);
                         break;
                     }
                 }
@@ -118,6 +185,7 @@ impl Analyzer {
         let reader_and_tags = Analyzer::get_reader(file_path.clone());
         let mut reader = reader_and_tags.0;
         let tags = reader_and_tags.1;
+        let serato = reader_and_tags.2;
         let default_track = reader.default_track().unwrap().clone();
         let decoder = Analyzer::get_decoder(&default_track.codec_params).unwrap();
         let track = Arc::new(model::track::Track::new(
@@ -127,13 +195,30 @@ impl Analyzer {
         if let Some(tags) = tags{
             track.meta.write().unwrap().parse_from(tags);
         }
+        // cues and beatgrid from Serato
+        if let Some(serato) = serato {
+            serato.apply_to(&track);
+        }
         analyzer_event_out
             .send(Event::NewTrack(Arc::clone(&track)))
             .unwrap();
+        // keep tempo and grid, that were imported from other DJ software
+        let imported_grid = track.beatgrid().is_some() && track.meta.read().unwrap().bpm.is_some();
+        let beat_tracker = match default_track.codec_params.sample_rate {
+            Some(sample_rate) if !imported_grid => BeatTracker::new(sample_rate),
+            _ => None,
+        };
+        let key_detector = match default_track.codec_params.sample_rate {
+            Some(sample_rate) if track.meta.read().unwrap().key.is_none() => {
+                KeyDetector::new(sample_rate)
+            }
+            _ => None,
+        };
         Self {
             reader,
             decoder,
-            sample_buf: vec![],
+            beat_tracker,
+            key_detector,
             preview_buf: vec![],
             track,
             analyzer_event_out,
@@ -172,8 +257,19 @@ impl Analyzer {
         }
     }
 
-    /// creates reader from a given path
-    fn get_reader(path: String) -> (Box<dyn FormatReader>, Option<Vec<Tag>>) {
+    /// creates reader from a given path and collects the tags, including Serato's GEOB frames
+    fn get_reader(
+        path: String,
+    ) -> (
+        Box<dyn FormatReader>,
+        Option<Vec<Tag>>,
+        Option<ImportedTrack>,
+    ) {
+        // symphonia skips GEOB frames, so they are read separately
+        let serato = serato::read(&path).unwrap_or_else(|err| {
+            warn!("failed to read Serato tags of {}: {}", path, err);
+            None
+        });
         let src = std::fs::File::open(path).expect("failed to open media");
         let mss = MediaSourceStream::new(Box::new(src), Default::default());
         let mut hint = Hint::new();
@@ -188,7 +284,7 @@ impl Analyzer {
             .get()
             .map(|meta| meta.current().map(|x| x.tags().to_vec()))
             .flatten();
-        (probed.format, tags)
+        (probed.format, tags, serato)
     }
 
     /// creates decoder from codec parameters
@@ -208,8 +304,20 @@ impl Analyzer {
         // this is the interleaved sample buffer, which means for each point in time there are n
         // samples where n is the number of channels in the track (for stereo that's 2)
         let samples = sample_buffer.samples();
-        // cache decoded frames
-        self.sample_buf.extend_from_slice(samples);
+        // tempo and key are detected packet by packet, so the track does not have to be kept in
+        // memory
+        if self.beat_tracker.is_some() || self.key_detector.is_some() {
+            let mono = self.sum_to_mono(samples);
+            if let Some(beat_tracker) = &mut self.beat_tracker {
+                beat_tracker.push(&mono);
+            }
+            if let Some(key_detector) = &mut self.key_detector {
+                if let Err(err) = key_detector.push(&mono) {
+                    warn!("failed to resample for key detection: {}", err);
+                    self.key_detector = None;
+                }
+            }
+        }
         // let mut samples =
         //     Analyzer::downsample_to_fixed_size(&samples, num_channels, PREVIEW_SAMPLE_RATE);
         self.preview_buf.extend_from_slice(samples);
@@ -237,49 +345,337 @@ impl Analyzer {
         }
     }
 
-    fn analyze_bpm(&mut self, bpm_range: Range<usize>) {
-        let samples = self
-            .sample_buf
-            // .to_vec()
+    fn analyze_bpm(&mut self, bpm_range: Range<f64>) {
+        let mut beat_tracker = match self.beat_tracker.take() {
+            Some(beat_tracker) => beat_tracker,
+            None => return,
+        };
+        beat_tracker.finish();
+        let beats = &beat_tracker.beats;
+        // the median beat interval is more robust than aubio's latest estimate
+        let detected_bpm = Analyzer::median_beat_interval(beats)
+            .map(|interval| 60. / interval)
+            .unwrap_or(beat_tracker.tempo.get_bpm() as f64);
+        if detected_bpm <= 0. {
+            return;
+        }
+        // a tempo from the tags is kept, the grid is only fitted around it
+        let tagged_bpm = self
+            .track
+            .meta
+            .read()
+            .unwrap()
+            .bpm
+            .filter(|bpm| bpm.confidence >= 1.);
+        let (detected_bpm, octave_factor) = match tagged_bpm {
+            Some(tagged_bpm) => {
+                let octave_factor = 2f64.powf((tagged_bpm.value / detected_bpm).log2().round());
+                (tagged_bpm.value / octave_factor, octave_factor)
+            }
+            None => {
+                let octave_factor = Analyzer::octave_correction(detected_bpm, &bpm_range);
+                let bpm = detected_bpm * octave_factor;
+                let confidence = Analyzer::beat_confidence(beats, detected_bpm);
+                self.track.change_bpm(Bpm::new(bpm, confidence));
+                (detected_bpm, octave_factor)
+            }
+        };
+        if let Some(beatgrid) =
+            Analyzer::detect_beatgrid(beats, detected_bpm, octave_factor, &beat_tracker.energy)
+        {
+            self.track.set_beatgrid(beatgrid);
+        }
+    }
+
+    /// returns the power of two, the tempo has to be multiplied with to lie in the given range.
+    /// Beat trackers often lock onto half or double the perceived tempo (octave errors). If no
+    /// multiple lies in the range, the one closest to the range is chosen.
+    fn octave_correction(bpm: f64, bpm_range: &Range<f64>) -> f64 {
+        let distance = |bpm: f64| {
+            if bpm_range.contains(&bpm) {
+                0.
+            } else {
+                (bpm - bpm_range.start)
+                    .abs()
+                    .min((bpm - bpm_range.end).abs())
+            }
+        };
+        // prefer the smallest correction among equally good candidates
+        [1., 2., 0.5, 4., 0.25]
             .iter()
-            .map(|s| *s as f64)
+            .copied()
+            .min_by(|a, b| distance(bpm * a).partial_cmp(&distance(bpm * b)).unwrap())
+            .unwrap_or(1.)
+    }
+
+    /// fraction of beat intervals, that match the tempo (or a multiple of it) within 5%
+    fn beat_confidence(beats: &[f64], bpm: f64) -> f32 {
+        let period = 60. / bpm;
+        let intervals = beats.windows(2).map(|w| w[1] - w[0]).collect_vec();
+        if intervals.is_empty() {
+            return 0.;
+        }
+        let matching = intervals
+            .iter()
+            .filter(|interval| {
+                let multiple = (*interval / period).round().max(1.);
+                ((*interval / multiple) - period).abs() / period < 0.05
+            })
+            .count();
+        matching as f32 / intervals.len() as f32
+    }
+
+    /// median of the intervals between consecutive beats in seconds, ignoring intervals, that
+    /// are too short to be a beat
+    fn median_beat_interval(beats: &[f64]) -> Option<f64> {
+        let mut intervals = beats
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .filter(|interval| *interval >= MIN_BEAT_INTERVAL)
             .collect_vec();
-        let sample_rate = self.track.codec_params.sample_rate.unwrap();
-        let low_crossover = cutoff_from_frequency(200., sample_rate as usize);
-        let high_crossover = cutoff_from_frequency(400., sample_rate as usize);
-        let low_band_filter = bandpass_filter(low_crossover, high_crossover, 0.01);
-        // let samples = convolve(&low_band_filter, &samples);
-        // let samples: Vec<f32> = samples.iter().map(|s| *s as f32).collect();
-        // analyze bpm
-        let buf_s = 2 << 14;
-        let hop_s = 256;
-        let tempo = std::panic::catch_unwind(|| {
-            aubio::Tempo::new(aubio::OnsetMode::Phase, buf_s, hop_s, sample_rate).unwrap()
+        if intervals.is_empty() {
+            return None;
+        }
+        intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Some(intervals[intervals.len() / 2])
+    }
+
+    /// fits a beatgrid through the detected beat positions
+    /// The phase is determined with the detected tempo, the grid itself uses the octave corrected
+    /// one. The downbeat is found in the energy envelope of the track.
+    fn detect_beatgrid(
+        beats: &[f64],
+        detected_bpm: f64,
+        octave_factor: f64,
+        energy: &[f32],
+    ) -> Option<Beatgrid> {
+        if beats.len() < MIN_BEATS_FOR_BEATGRID || detected_bpm <= 0. {
+            return None;
+        }
+        let period = 60. / detected_bpm;
+        // the phase of the grid is the circular mean of all beat positions modulo the period,
+        // so single misdetected beats don't shift the grid
+        let (sin, cos) = beats.iter().fold((0., 0.), |(sin, cos): (f64, f64), beat| {
+            let angle = beat / period * TAU;
+            (sin + angle.sin(), cos + angle.cos())
         });
-        match tempo {
-            Ok(mut tempo) => {
-                for chunk in samples.into_iter().chunks(buf_s).into_iter() {
-                    let chunk: Vec<f64> = chunk.collect();
-                    // let chunk = convolve(&low_band_filter, &chunk);
-                    let chunk = chunk.iter().map(|s| *s as f32).collect_vec();
-                    match tempo.do_result(chunk) {
-                        Ok(_) => {}
-                        Err(_) => {}
-                    };
+        let first_beat = sin.atan2(cos).rem_euclid(TAU) / TAU * period;
+        let bpm = detected_bpm * octave_factor;
+        let period = 60. / bpm;
+        let mut beatgrid = Beatgrid::new(first_beat, bpm);
+        // the downbeat is the beat in the bar, that carries the most energy (usually the kick)
+        let energy_at = |position: f64| -> f32 {
+            let from = ((position - 0.02).max(0.) * ENERGY_BLOCKS_PER_SECOND) as usize;
+            let to = ((position + 0.05) * ENERGY_BLOCKS_PER_SECOND).ceil() as usize;
+            let to = std::cmp::min(to, energy.len());
+            if from >= to {
+                return 0.;
+            }
+            energy[from..to].iter().sum::<f32>() / (to - from) as f32
+        };
+        let last_beat = beatgrid.beat_index_at(*beats.last().unwrap()).floor() as i64;
+        let downbeat = (0..BEATS_PER_BAR)
+            .map(|offset| {
+                let energy: f32 = (offset..=last_beat)
+                    .step_by(BEATS_PER_BAR as usize)
+                    .map(|beat| energy_at(beatgrid.beat_position(beat as f64)))
+                    .sum();
+                (offset, energy)
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(offset, _)| offset)
+            .unwrap_or(0);
+        beatgrid.first_downbeat = first_beat + downbeat as f64 * period;
+        Analyzer::detect_tempo_changes(&mut beatgrid, beats, octave_factor);
+        Some(beatgrid)
+    }
+
+    /// adds tempo anchors to the beatgrid, where the local tempo of the detected beats deviates
+    /// from the grid's tempo
+    fn detect_tempo_changes(beatgrid: &mut Beatgrid, beats: &[f64], octave_factor: f64) {
+        for window in beats.chunks(TEMPO_CHANGE_WINDOW) {
+            if window.len() < TEMPO_CHANGE_WINDOW {
+                break;
+            }
+            let local_bpm = match Analyzer::median_beat_interval(window) {
+                Some(interval) => 60. / interval * octave_factor,
+                None => continue,
+            };
+            let grid_bpm = beatgrid.bpm_at(window[0]);
+            if (local_bpm - grid_bpm).abs() / grid_bpm > TEMPO_CHANGE_THRESHOLD {
+                let beat = beatgrid.nearest_beat(window[0]);
+                if beat.index > 0 {
+                    beatgrid.add_anchor(beat.index, local_bpm);
                 }
-                let t = tempo.get_bpm().floor() as usize;
-                // for _ in (0..5) {
-                //     if !bpm_range.contains(&t) {
-                //         self.analyze_bpm(bpm_range.clone(), hop_s << 2);
-                //     };
-                // }
-                self.track.change_bpm(t as u32);
-                // println!("{}", t);
             }
+        }
+    }
+
+    /// estimates the key from a chroma vector of the whole track. Keys read from tags take
+    /// precedence, so detection is skipped for those tracks.
+    fn analyze_key(&mut self) {
+        let key_detector = match self.key_detector.take() {
+            Some(key_detector) => key_detector,
+            None => return,
+        };
+        let chroma = match key_detector.finish() {
+            Ok(chroma) => chroma,
             Err(err) => {
-                println!("{:#?}", err);
+                warn!("failed to resample for key detection: {}", err);
+                return;
             }
         };
+        if let Some(key) = Analyzer::estimate_key(&chroma) {
+            self.track.meta.write().unwrap().key = Some(key);
+        }
+    }
+
+    /// finds the key, whose Krumhansl-Kessler profile correlates best with the chroma vector
+    fn estimate_key(chroma: &[f64; 12]) -> Option<Key> {
+        if chroma.iter().all(|c| *c == 0.) {
+            return None;
+        }
+        [
+            (Mode::Major, KEY_PROFILE_MAJOR),
+            (Mode::Minor, KEY_PROFILE_MINOR),
+        ]
+        .iter()
+        .flat_map(|(mode, profile)| {
+            (0..12u8).map(move |tonic| {
+                let rotated = (0..12)
+                    .map(|i| profile[(i + 12 - tonic as usize) % 12])
+                    .collect_vec();
+                (
+                    Key::new(tonic, *mode),
+                    Analyzer::correlation(chroma, &rotated),
+                )
+            })
+        })
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(key, _)| key)
+    }
+
+    /// pearson correlation of two vectors of equal length
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let mean_a = a.iter().sum::<f64>() / a.len() as f64;
+        let mean_b = b.iter().sum::<f64>() / b.len() as f64;
+        let (mut cov, mut var_a, mut var_b) = (0., 0., 0.);
+        for (x, y) in a.iter().zip(b.iter()) {
+            cov += (x - mean_a) * (y - mean_b);
+            var_a += (x - mean_a).powi(2);
+            var_b += (y - mean_b).powi(2);
+        }
+        if var_a == 0. || var_b == 0. {
+            0.
+        } else {
+            cov / (var_a * var_b).sqrt()
+        }
+    }
+
+    /// suggests cues at the first downbeat, the first drop, breakdowns and the outro. Suggestions
+    /// close to existing memory cues are left out.
+    fn suggest_cues(&mut self) {
+        let (beatgrid, duration) = match (self.track.beatgrid(), self.track.duration()) {
+            (Some(beatgrid), Some(duration)) => (beatgrid, duration),
+            _ => return,
+        };
+        let preview = self.track.preview_samples();
+        let mem_cues = self.track.mem_cues();
+        let tolerance = beatgrid.beat_length_at(0.) / 2.;
+        let suggested_cues = Analyzer::structure_cues(&beatgrid, &preview, duration)
+            .into_iter()
+            .filter(|suggestion| {
+                !mem_cues
+                    .iter()
+                    .any(|cue| (cue.position - suggestion.position).abs() < tolerance)
+            })
+            .collect();
+        self.track.set_suggested_cues(suggested_cues);
+    }
+
+    /// finds the structure of a track by comparing the energy of the preview between bars and
+    /// phrases of the beatgrid. The music starts at the first bar, that is not nearly silent. A
+    /// drop is the first main part phrase, in which the lows rise sharply, breakdowns are
+    /// phrases after it, in which the lows fall sharply, and the outro is the phrase after the
+    /// last main part phrase.
+    fn structure_cues(
+        beatgrid: &Beatgrid,
+        preview: &[PreviewSample],
+        duration: f64,
+    ) -> Vec<SuggestedCue> {
+        // mean energy of the low band and of all bands between two positions
+        let energy = |from: f64, to: f64| {
+            let start = ((from.max(0.) * PREVIEW_SAMPLE_RATE as f64) as usize).min(preview.len());
+            let end = ((to.max(0.) * PREVIEW_SAMPLE_RATE as f64) as usize).min(preview.len());
+            if start >= end {
+                return None;
+            }
+            let samples = &preview[start..end];
+            let n = samples.len() as f64;
+            let lows = samples.iter().map(|s| s.lows.abs() as f64).sum::<f64>() / n;
+            let total = samples
+                .iter()
+                .map(|s| (s.lows.abs() + s.mids.abs() + s.highs.abs()) as f64)
+                .sum::<f64>()
+                / n;
+            Some((lows, total))
+        };
+        // (position, low band energy, total energy) of every bar or phrase
+        let sections = |beats: i64| {
+            beatgrid
+                .beats_between(0., duration)
+                .into_iter()
+                .filter(|beat| beat.index.rem_euclid(beats) == 0)
+                .filter_map(|beat| {
+                    let end = beatgrid.beat_position((beat.index + beats) as f64);
+                    energy(beat.position, end).map(|(lows, total)| (beat.position, lows, total))
+                })
+                .collect_vec()
+        };
+        let max = |sections: &[(f64, f64, f64)]| {
+            sections
+                .iter()
+                .map(|(_, _, total)| *total)
+                .fold(0., f64::max)
+        };
+        let mut cues = vec![];
+        let bars = sections(BEATS_PER_BAR);
+        let max_bar = max(&bars);
+        if max_bar <= 0. {
+            return cues;
+        }
+        if let Some((position, _, _)) = bars
+            .iter()
+            .find(|(_, _, total)| *total > INTRO_ENERGY * max_bar)
+        {
+            cues.push(SuggestedCue::new(*position, CueKind::FirstDownbeat));
+        }
+        let phrases = sections(BEATS_PER_BAR * BARS_PER_PHRASE);
+        let max_phrase = max(&phrases);
+        let is_main = |total: f64| total >= MAIN_PART_ENERGY * max_phrase;
+        let outro = phrases
+            .iter()
+            .rposition(|(_, _, total)| is_main(*total))
+            .and_then(|last_main| phrases.get(last_main + 1))
+            .map(|(position, _, _)| *position);
+        let mut dropped = false;
+        for pair in phrases.windows(2) {
+            let (_, previous_lows, _) = pair[0];
+            let (position, lows, total) = pair[1];
+            if outro.map_or(false, |outro| position >= outro) {
+                break;
+            }
+            if !dropped && is_main(total) && lows > DROP_FACTOR * previous_lows {
+                cues.push(SuggestedCue::new(position, CueKind::Drop));
+                dropped = true;
+            } else if dropped && lows < BREAKDOWN_FACTOR * previous_lows {
+                cues.push(SuggestedCue::new(position, CueKind::Breakdown));
+            }
+        }
+        if let Some(outro) = outro {
+            cues.push(SuggestedCue::new(outro, CueKind::Outro));
+        }
+        cues
     }
 
     fn sum_to_mono(&mut self, samples: &[f32]) -> Vec<f32> {
@@ -371,6 +767,247 @@ impl Analyzer {
     }
 }
 
+//------------------------------------------------------------------//
+//                           AnalyzerPool                           //
+//------------------------------------------------------------------//
+
+/// Analyzes files with a fixed number of worker threads, so a large library does not start a
+/// decoder for every file at once. The workers stop, once the pool is dropped and all queued
+/// files are analyzed.
+#[derive(Clone)]
+pub struct AnalyzerPool {
+    files_out: Sender<String>,
+}
+
+impl AnalyzerPool {
+    /// spawns the workers, one per core, if jobs is None
+    pub fn spawn(
+        jobs: Option<usize>,
+        config: AnalyzerConfig,
+        analyzer_event_out: Sender<Event>,
+    ) -> Self {
+        let (files_out, files_in) = channel::<String>();
+        let files_in = Arc::new(Mutex::new(files_in));
+        let jobs = jobs
+            .or_else(|| available_parallelism().ok().map(|jobs| jobs.get()))
+            .unwrap_or(1)
+            .max(1);
+        for _ in 0..jobs {
+            let files_in = Arc::clone(&files_in);
+            let config = config.clone();
+            let analyzer_event_out = analyzer_event_out.clone();
+            spawn(move || loop {
+                let file_path = match files_in.lock().unwrap().recv() {
+                    Ok(file_path) => file_path,
+                    Err(_) => break,
+                };
+                // the analyzer panics on files, it can not read
+                let finished = Analyzer::spawn(
+                    file_path.clone(),
+                    config.clone(),
+                    analyzer_event_out.clone(),
+                )
+                .join()
+                .is_ok();
+                if !finished && analyzer_event_out.send(Event::Failed(file_path)).is_err() {
+                    break;
+                }
+            });
+        }
+        Self { files_out }
+    }
+
+    /// queues a file for analysis
+    pub fn analyze(&self, file_path: String) {
+        // the workers only stop, once the pool is dropped
+        self.files_out.send(file_path).unwrap();
+    }
+}
+
+//------------------------------------------------------------------//
+//                           BeatTracker                            //
+//------------------------------------------------------------------//
+
+/// Feeds the mono signal to aubio's tempo detection hop by hop, while a track is decoded. Only
+/// the detected beats and a coarse energy envelope are kept.
+struct BeatTracker {
+    tempo: aubio::Tempo,
+    /// samples, that do not fill a hop yet
+    hop: Vec<f32>,
+    /// positions of all detected beats in seconds
+    beats: Vec<f64>,
+    /// mean energy of every block of the signal, ENERGY_BLOCKS_PER_SECOND blocks per second
+    energy: Vec<f32>,
+    /// number of samples in a block of the energy envelope
+    block_size: usize,
+    /// sum of squares and number of samples of the block, that is not complete yet
+    block: (f32, usize),
+}
+
+impl BeatTracker {
+    fn new(sample_rate: u32) -> Option<Self> {
+        let tempo = std::panic::catch_unwind(|| {
+            aubio::Tempo::new(
+                aubio::OnsetMode::Phase,
+                TEMPO_BUF_SIZE,
+                TEMPO_HOP_SIZE,
+                sample_rate,
+            )
+            .unwrap()
+        });
+        match tempo {
+            Ok(tempo) => Some(Self {
+                tempo,
+                hop: Vec::with_capacity(TEMPO_HOP_SIZE),
+                beats: vec![],
+                energy: vec![],
+                block_size: (sample_rate as f64 / ENERGY_BLOCKS_PER_SECOND).round() as usize,
+                block: (0., 0),
+            }),
+            Err(err) => {
+                warn!("failed to create tempo detection: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// analyzes the next mono samples of the track
+    fn push(&mut self, mono: &[f32]) {
+        for sample in mono {
+            self.block.0 += sample * sample;
+            self.block.1 += 1;
+            if self.block.1 == self.block_size {
+                self.energy.push(self.block.0 / self.block.1 as f32);
+                self.block = (0., 0);
+            }
+            self.hop.push(*sample);
+            if self.hop.len() == TEMPO_HOP_SIZE {
+                self.detect_beat();
+            }
+        }
+    }
+
+    /// analyzes the last incomplete hop
+    fn finish(&mut self) {
+        if !self.hop.is_empty() {
+            // aubio expects exactly one hop of samples per call
+            self.hop.resize(TEMPO_HOP_SIZE, 0.);
+            self.detect_beat();
+        }
+    }
+
+    fn detect_beat(&mut self) {
+        if let Ok(beat) = self.tempo.do_result(&self.hop) {
+            if beat > 0. {
+                self.beats.push(self.tempo.get_last_s() as f64);
+            }
+        }
+        self.hop.clear();
+    }
+}
+
+//------------------------------------------------------------------//
+//                           KeyDetector                            //
+//------------------------------------------------------------------//
+
+/// Resamples the mono signal while a track is decoded and sums up the chroma of every complete
+/// frame, so only the current frame has to be kept.
+struct KeyDetector {
+    converter: Samplerate,
+    /// resampled samples of the current frame
+    frame: Vec<f32>,
+    /// hann window over a frame
+    window: Vec<f64>,
+    /// goertzel coefficients for every note in the analyzed range and its pitch class
+    coefficients: Vec<(u8, f64)>,
+    /// sum of the normalized chroma of all frames
+    chroma: [f64; 12],
+}
+
+impl KeyDetector {
+    fn new(sample_rate: u32) -> Option<Self> {
+        // the relevant pitches are well below 2kHz, so a lower sample rate saves a lot of work
+        let converter =
+            match Samplerate::new(ConverterType::SincFastest, sample_rate, KEY_SAMPLE_RATE, 1) {
+                Ok(converter) => converter,
+                Err(err) => {
+                    warn!("failed to create resampler for key detection: {}", err);
+                    return None;
+                }
+            };
+        let window = (0..KEY_FRAME_SIZE)
+            .map(|i| {
+                let phase = TAU * i as f64 / (KEY_FRAME_SIZE - 1) as f64;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect_vec();
+        let coefficients = KEY_MIDI_NOTES
+            .map(|note| {
+                let frequency = 440. * 2f64.powf((note as f64 - 69.) / 12.);
+                let k = (frequency * KEY_FRAME_SIZE as f64 / KEY_SAMPLE_RATE as f64).round();
+                (note % 12, 2. * (TAU * k / KEY_FRAME_SIZE as f64).cos())
+            })
+            .collect_vec();
+        Some(Self {
+            converter,
+            frame: Vec::with_capacity(KEY_FRAME_SIZE),
+            window,
+            coefficients,
+            chroma: [0f64; 12],
+        })
+    }
+
+    /// analyzes the next mono samples of the track
+    fn push(&mut self, mono: &[f32]) -> Result<(), samplerate::Error> {
+        let samples = self.converter.process(mono)?;
+        self.add_samples(&samples);
+        Ok(())
+    }
+
+    /// flushes the resampler and returns the summed chroma, an incomplete last frame is dropped
+    fn finish(mut self) -> Result<[f64; 12], samplerate::Error> {
+        let samples = self.converter.process_last(&[])?;
+        self.add_samples(&samples);
+        Ok(self.chroma)
+    }
+
+    fn add_samples(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.frame.push(*sample);
+            if self.frame.len() == KEY_FRAME_SIZE {
+                self.add_frame();
+                self.frame.clear();
+            }
+        }
+    }
+
+    fn add_frame(&mut self) {
+        let frame = self
+            .frame
+            .iter()
+            .zip(self.window.iter())
+            .map(|(s, w)| *s as f64 * w)
+            .collect_vec();
+        let mut frame_chroma = [0f64; 12];
+        for (pitch_class, coefficient) in &self.coefficients {
+            let (mut s1, mut s2) = (0., 0.);
+            for sample in &frame {
+                let s0 = sample + coefficient * s1 - s2;
+                s2 = s1;
+                s1 = s0;
+            }
+            let power = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+            frame_chroma[*pitch_class as usize] += power.max(0.).sqrt();
+        }
+        let total: f64 = frame_chroma.iter().sum();
+        if total > 0. {
+            for (c, f) in self.chroma.iter_mut().zip(frame_chroma.iter()) {
+                *c += f / total;
+            }
+        }
+    }
+}
+
 //------------------------------------------------------------------//
 //                      PeakIntersampleFilter                       //
 //------------------------------------------------------------------//
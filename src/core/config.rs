@@ -0,0 +1,152 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+//------------------------------------------------------------------//
+//                              Config                              //
+//------------------------------------------------------------------//
+
+/// name of the config file in the config directory
+const CONFIG_FILE: &str = "config.toml";
+
+/// Errors, that can occur while reading or writing the config file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialization(toml::ser::Error),
+    /// an include or exclude pattern is no valid glob
+    Pattern(globset::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "io error: {}", err),
+            ConfigError::Parse(err) => write!(f, "invalid config: {}", err),
+            ConfigError::Serialization(err) => write!(f, "serialization error: {}", err),
+            ConfigError::Pattern(err) => write!(f, "invalid pattern: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(err: toml::ser::Error) -> Self {
+        ConfigError::Serialization(err)
+    }
+}
+
+impl From<globset::Error> for ConfigError {
+    fn from(err: globset::Error) -> Self {
+        ConfigError::Pattern(err)
+    }
+}
+
+/// User settings from the config file, e.g. ~/.config/flow/config.toml:
+/// ```toml
+/// roots = ["/home/dj/Music", "/media/usb/Tracks"]
+/// include = []
+/// exclude = ["**/Samples/**"]
+/// follow_symlinks = false
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// directories, that are scanned for tracks
+    pub roots: Vec<PathBuf>,
+    /// glob patterns, one of which a track has to match, e.g. "**/*.flac". All supported files
+    /// are listed, if there are none.
+    pub include: Vec<String>,
+    /// glob patterns of tracks and directories, that are skipped, e.g. "**/Samples/**"
+    pub exclude: Vec<String>,
+    /// whether symbolic links to tracks and directories are followed
+    pub follow_symlinks: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            // the XDG music directory, e.g. ~/Music
+            roots: dirs::audio_dir().into_iter().collect(),
+            include: vec![],
+            exclude: vec![],
+            follow_symlinks: false,
+        }
+    }
+}
+
+impl Config {
+    /// the config file in the user's config directory (e.g. ~/.config/flow/config.toml)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join("flow").join(CONFIG_FILE))
+    }
+
+    /// loads a config file. Returns the default config, if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Ok(Config::load_file(path)?.unwrap_or_default())
+    }
+
+    /// loads a config file, None if it does not exist yet. Relative roots are relative to the
+    /// directory of the file, so they do not depend on where flow is started.
+    pub fn load_file(path: &Path) -> Result<Option<Self>, ConfigError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut config: Config = toml::from_str(&fs::read_to_string(path)?)?;
+        let current_dir = std::env::current_dir()?;
+        let base = absolute_root(path.parent().unwrap_or(&current_dir), &current_dir);
+        config.roots = config
+            .roots
+            .iter()
+            .map(|root| absolute_root(root, &base))
+            .collect();
+        Ok(Some(config))
+    }
+
+    /// writes the config file and creates its directory if necessary
+    pub fn store(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// whether a file is in one of the roots
+    pub fn contains(&self, file_path: &str) -> bool {
+        self.roots
+            .iter()
+            .any(|root| Path::new(file_path).starts_with(root))
+    }
+}
+
+/// makes a root absolute, so tracks are stored under the same path wherever flow is started.
+/// A leading ~ is replaced by the home directory, other relative roots are relative to base.
+pub fn absolute_root(root: &Path, base: &Path) -> PathBuf {
+    let root = match (root.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => root.to_path_buf(),
+    };
+    if root.is_absolute() {
+        root
+    } else {
+        base.join(root)
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{core::analyzer::PreviewSample, view::model::beatgrid::Beatgrid};
+
+//------------------------------------------------------------------//
+//                           ANLZ Files                             //
+//------------------------------------------------------------------//
+
+/// number of columns in the monochrome waveform preview (PWAV)
+const PREVIEW_COLUMNS: usize = 400;
+/// entries per second in the scrolling waveforms (PWV3, PWV5)
+const DETAIL_ENTRIES_PER_SECOND: f64 = 150.;
+
+/// the two cue lists of an analysis file
+#[derive(Clone, Copy, PartialEq)]
+pub enum CueListType {
+    Memory = 0,
+    Hot = 1,
+}
+
+/// A cue as it is stored in a PCPT entry
+#[derive(Clone, Debug)]
+pub struct AnlzCue {
+    /// 0 for memory cues, 1 for hot cue A, 2 for B, ...
+    pub hot_cue: u32,
+    /// position in milliseconds
+    pub time: u32,
+    /// end of the loop in milliseconds, if the cue is a loop
+    pub loop_time: Option<u32>,
+}
+
+/// Builder for the analysis files (ANLZ0000.DAT and .EXT), that CDJs read beatgrids, cues and
+/// waveforms from. All numbers are big endian. Every section starts with a four character tag,
+/// the length of its header and its total length. Like export.pdb, the files are written without
+/// rekordcrate, which can only read them.
+pub struct AnlzFile {
+    sections: Vec<u8>,
+}
+
+impl AnlzFile {
+    pub fn new() -> Self {
+        Self { sections: vec![] }
+    }
+
+    /// PPTH: path of the audio file on the device
+    pub fn add_path(&mut self, path: &str) {
+        let body: Vec<u8> = path
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(|c| c.to_be_bytes())
+            .collect();
+        let header = (body.len() as u32).to_be_bytes();
+        self.add_section(b"PPTH", &header, &body);
+    }
+
+    /// PQTZ: one entry per beat with its number in the bar, the tempo and its position
+    pub fn add_beatgrid(&mut self, beatgrid: &Beatgrid, duration: f64) {
+        let beats = beatgrid.beats_between(0., duration);
+        let mut body = vec![];
+        for beat in &beats {
+            body.extend((beat.beat_in_bar as u16).to_be_bytes());
+            body.extend(((beatgrid.bpm_at(beat.position) * 100.).round() as u16).to_be_bytes());
+            body.extend(((beat.position * 1000.).round() as u32).to_be_bytes());
+        }
+        let mut header = vec![];
+        header.extend(0u32.to_be_bytes());
+        header.extend(0x0008_0000u32.to_be_bytes());
+        header.extend((beats.len() as u32).to_be_bytes());
+        self.add_section(b"PQTZ", &header, &body);
+    }
+
+    /// PCOB: a list of PCPT cue entries
+    pub fn add_cues(&mut self, list_type: CueListType, cues: &[AnlzCue]) {
+        let mut body = vec![];
+        for (i, cue) in cues.iter().enumerate() {
+            // entries form a doubly linked list
+            let previous = if i == 0 { 0xffff } else { i as u16 - 1 };
+            let next = if i + 1 == cues.len() {
+                0xffff
+            } else {
+                i as u16 + 1
+            };
+            let mut entry_header = vec![];
+            entry_header.extend(cue.hot_cue.to_be_bytes());
+            // status: 1 is a cue, 4 a loop
+            entry_header.extend(if cue.loop_time.is_some() { 4u32 } else { 1u32 }.to_be_bytes());
+            entry_header.extend(0x0001_0000u32.to_be_bytes());
+            entry_header.extend(previous.to_be_bytes());
+            entry_header.extend(next.to_be_bytes());
+            let mut entry_body = vec![];
+            // type: 1 is a cue, 2 a loop
+            entry_body.push(if cue.loop_time.is_some() { 2 } else { 1 });
+            entry_body.extend([0x00, 0x03, 0xe8]);
+            entry_body.extend(cue.time.to_be_bytes());
+            entry_body.extend(cue.loop_time.unwrap_or(0xffff_ffff).to_be_bytes());
+            entry_body.extend([0; 16]);
+            body.extend(section(b"PCPT", &entry_header, &entry_body));
+        }
+        let mut header = vec![];
+        header.extend((list_type as u32).to_be_bytes());
+        header.extend(0u16.to_be_bytes());
+        header.extend((cues.len() as u16).to_be_bytes());
+        header.extend(0xffff_ffffu32.to_be_bytes());
+        self.add_section(b"PCOB", &header, &body);
+    }
+
+    /// PWAV: monochrome preview of the whole track with 400 columns. Every byte holds the height
+    /// in the lower 5 bits and the whiteness in the upper 3 bits.
+    pub fn add_waveform_preview(&mut self, preview: &[PreviewSample]) {
+        let columns = resample(preview, PREVIEW_COLUMNS);
+        let body: Vec<u8> = columns.iter().map(mono_entry).collect();
+        let mut header = vec![];
+        header.extend((body.len() as u32).to_be_bytes());
+        header.extend(0x0001_0000u32.to_be_bytes());
+        self.add_section(b"PWAV", &header, &body);
+    }
+
+    /// PWV3: monochrome scrolling waveform with 150 entries per second, one byte per entry in the
+    /// same format as PWAV
+    pub fn add_waveform_detail(&mut self, preview: &[PreviewSample], duration: f64) {
+        let entries = resample(preview, (duration * DETAIL_ENTRIES_PER_SECOND) as usize);
+        let body: Vec<u8> = entries.iter().map(mono_entry).collect();
+        let mut header = vec![];
+        header.extend(1u32.to_be_bytes());
+        header.extend((body.len() as u32).to_be_bytes());
+        header.extend(0x0096_0000u32.to_be_bytes());
+        self.add_section(b"PWV3", &header, &body);
+    }
+
+    /// PWV5: colored scrolling waveform with 150 entries per second. Every entry has two bytes
+    /// with 3 bits each for red (lows), green (mids) and blue (highs) and 5 bits for the height.
+    pub fn add_color_waveform_detail(&mut self, preview: &[PreviewSample], duration: f64) {
+        let entries = resample(preview, (duration * DETAIL_ENTRIES_PER_SECOND) as usize);
+        let mut body = vec![];
+        for sample in &entries {
+            let total = sample.lows + sample.mids + sample.highs;
+            let color = |band: f32| {
+                if total > 0. {
+                    ((band / total) * 7.).round() as u16
+                } else {
+                    0
+                }
+            };
+            let height = (total.min(1.) * 31.).round() as u16;
+            let entry = color(sample.lows) << 13
+                | color(sample.mids) << 10
+                | color(sample.highs) << 7
+                | height << 2;
+            body.extend(entry.to_be_bytes());
+        }
+        let mut header = vec![];
+        header.extend(2u32.to_be_bytes());
+        header.extend((entries.len() as u32).to_be_bytes());
+        header.extend(0x0096_0305u32.to_be_bytes());
+        self.add_section(b"PWV5", &header, &body);
+    }
+
+    /// the complete file including the PMAI file header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len_header = 28u32;
+        let mut bytes = vec![];
+        bytes.extend(b"PMAI");
+        bytes.extend(len_header.to_be_bytes());
+        bytes.extend((len_header + self.sections.len() as u32).to_be_bytes());
+        bytes.extend([0; 16]);
+        bytes.extend(&self.sections);
+        bytes
+    }
+
+    fn add_section(&mut self, tag: &[u8; 4], header: &[u8], body: &[u8]) {
+        self.sections.extend(section(tag, header, body));
+    }
+}
+
+/// a tagged section: tag, header length, total length, the rest of the header and the body
+fn section(tag: &[u8; 4], header: &[u8], body: &[u8]) -> Vec<u8> {
+    let len_header = 12 + header.len() as u32;
+    let len_tag = len_header + body.len() as u32;
+    let mut bytes = Vec::with_capacity(len_tag as usize);
+    bytes.extend(tag);
+    bytes.extend(len_header.to_be_bytes());
+    bytes.extend(len_tag.to_be_bytes());
+    bytes.extend(header);
+    bytes.extend(body);
+    bytes
+}
+
+/// height and whiteness of a monochrome waveform entry. Tracks with more highs appear whiter.
+fn mono_entry(sample: &PreviewSample) -> u8 {
+    let total = sample.lows + sample.mids + sample.highs;
+    let height = (total.min(1.) * 31.).round() as u8;
+    let whiteness = if total > 0. {
+        ((sample.highs / total) * 7.).round() as u8
+    } else {
+        0
+    };
+    whiteness << 5 | height
+}
+
+/// averages the preview into the given number of entries and normalizes it, so the loudest entry
+/// has a total amplitude of 1
+pub(super) fn resample(preview: &[PreviewSample], len: usize) -> Vec<PreviewSample> {
+    if preview.is_empty() || len == 0 {
+        return vec![];
+    }
+    let entries: Vec<PreviewSample> = (0..len)
+        .map(|i| {
+            let from = i * preview.len() / len;
+            let to = ((i + 1) * preview.len() / len)
+                .max(from + 1)
+                .min(preview.len());
+            let chunk = &preview[from..to];
+            let n = chunk.len() as f32;
+            let sum: PreviewSample = chunk.iter().cloned().sum();
+            PreviewSample {
+                lows: sum.lows.abs() / n,
+                mids: sum.mids.abs() / n,
+                highs: sum.highs.abs() / n,
+            }
+        })
+        .collect();
+    let max = entries
+        .iter()
+        .map(|s| s.lows + s.mids + s.highs)
+        .fold(0f32, f32::max);
+    if max <= 0. {
+        return entries;
+    }
+    entries
+        .into_iter()
+        .map(|s| PreviewSample {
+            lows: s.lows / max,
+            mids: s.mids / max,
+            highs: s.highs / max,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_file_header_and_the_path() {
+        let mut anlz = AnlzFile::new();
+        anlz.add_path("/a");
+        let expected: Vec<u8> = [
+            &b"PMAI"[..],
+            &[0, 0, 0, 28, 0, 0, 0, 50],
+            &[0; 16],
+            b"PPTH",
+            &[0, 0, 0, 16, 0, 0, 0, 22],
+            // length of the path in bytes
+            &[0, 0, 0, 6],
+            &[0, b'/', 0, b'a', 0, 0],
+        ]
+        .concat();
+        assert_eq!(anlz.to_bytes(), expected);
+    }
+
+    #[test]
+    fn writes_a_beat_per_entry() {
+        let mut anlz = AnlzFile::new();
+        anlz.add_beatgrid(&Beatgrid::new(0.1, 120.), 1.);
+        let expected: Vec<u8> = [
+            &b"PQTZ"[..],
+            &[0, 0, 0, 24, 0, 0, 0, 40],
+            &[0, 0, 0, 0, 0, 8, 0, 0],
+            // number of beats
+            &[0, 0, 0, 2],
+            // beat in bar, tempo in 1/100 bpm and position in milliseconds
+            &[0, 1, 0x2e, 0xe0, 0, 0, 0, 100],
+            &[0, 2, 0x2e, 0xe0, 0, 0, 0x02, 0x58],
+        ]
+        .concat();
+        assert_eq!(anlz.sections, expected);
+    }
+
+    #[test]
+    fn nests_cue_entries_in_the_cue_list() {
+        let mut anlz = AnlzFile::new();
+        let cue = AnlzCue {
+            hot_cue: 1,
+            time: 1500,
+            loop_time: None,
+        };
+        anlz.add_cues(CueListType::Hot, &[cue]);
+        let expected: Vec<u8> = [
+            &b"PCOB"[..],
+            &[0, 0, 0, 24, 0, 0, 0, 80],
+            // list type, number of cues and memory count
+            &[0, 0, 0, 1, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff],
+            b"PCPT",
+            &[0, 0, 0, 28, 0, 0, 0, 56],
+            // hot cue, status, unknown, previous and next entry
+            &[0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff],
+            // type, unknown, position and loop end
+            &[1, 0, 3, 0xe8, 0, 0, 0x05, 0xdc, 0xff, 0xff, 0xff, 0xff],
+            &[0; 16],
+        ]
+        .concat();
+        assert_eq!(anlz.sections, expected);
+    }
+}
@@ -0,0 +1,627 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+use rand::Rng;
+use rusqlite::{params, Connection, Transaction};
+
+use super::{anlz::resample, file_extension, non_empty, sanitize, DeviceFiles, InteropError};
+use crate::{
+    core::analyzer::PreviewSample,
+    view::model::{
+        beatgrid::Beatgrid,
+        cue::{CueColor, HotCue, Loop},
+        key::{Key, Mode},
+        playlist::PlaylistNode,
+        track::Track,
+    },
+};
+
+//------------------------------------------------------------------//
+//                        Engine DJ Export                          //
+//------------------------------------------------------------------//
+
+/// schema version of the database, that Engine DJ 2.x and the SC players read
+const SCHEMA_VERSION: (i64, i64, i64) = (2, 18, 0);
+/// number of hot cue and loop slots on Denon players
+const SLOTS: usize = 8;
+/// number of entries in the overview waveform
+const OVERVIEW_ENTRIES: usize = 1024;
+
+const SCHEMA: &str = "
+CREATE TABLE Information (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid TEXT,
+    schemaVersionMajor INTEGER,
+    schemaVersionMinor INTEGER,
+    schemaVersionPatch INTEGER,
+    currentPlayedIndiciator INTEGER,
+    lastRekordBoxLibraryImportReadCounter INTEGER
+);
+CREATE TABLE AlbumArt (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    hash TEXT,
+    albumArt BLOB
+);
+CREATE TABLE Track (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    playOrder INTEGER,
+    length INTEGER,
+    bpm INTEGER,
+    year INTEGER,
+    path TEXT,
+    filename TEXT,
+    bitrate INTEGER,
+    bpmAnalyzed REAL,
+    albumArtId INTEGER,
+    fileBytes INTEGER,
+    title TEXT,
+    artist TEXT,
+    album TEXT,
+    genre TEXT,
+    comment TEXT,
+    label TEXT,
+    composer TEXT,
+    remixer TEXT,
+    key INTEGER,
+    rating INTEGER,
+    albumArt TEXT,
+    timeLastPlayed DATETIME,
+    isPlayed BOOLEAN,
+    fileType TEXT,
+    isAnalyzed BOOLEAN,
+    dateCreated DATETIME,
+    dateAdded DATETIME,
+    isAvailable BOOLEAN,
+    isMetadataOfPackedTrackChanged BOOLEAN,
+    isPerfomanceDataOfPackedTrackChanged BOOLEAN,
+    playedIndicator INTEGER,
+    isMetadataImported BOOLEAN,
+    pdbImportKey INTEGER,
+    streamingSource TEXT,
+    uri TEXT,
+    isBeatGridLocked BOOLEAN,
+    originDatabaseUuid TEXT,
+    originTrackId INTEGER,
+    trackData BLOB,
+    overviewWaveFormData BLOB,
+    beatData BLOB,
+    quickCues BLOB,
+    loops BLOB,
+    thirdPartySourceId INTEGER,
+    streamingFlags INTEGER,
+    explicitLyrics BOOLEAN,
+    activeOnLoadLoops INTEGER,
+    lastEditTime DATETIME,
+    FOREIGN KEY (albumArtId) REFERENCES AlbumArt (id) ON DELETE RESTRICT
+);
+CREATE TABLE Playlist (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT,
+    parentListId INTEGER,
+    isPersisted BOOLEAN,
+    nextListId INTEGER,
+    lastEditTime DATETIME,
+    isExplicitlyExported BOOLEAN
+);
+CREATE TABLE PlaylistEntity (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    listId INTEGER,
+    trackId INTEGER,
+    databaseUuid TEXT,
+    nextEntityId INTEGER,
+    membershipReference INTEGER,
+    FOREIGN KEY (listId) REFERENCES Playlist (id) ON DELETE CASCADE
+);
+CREATE VIEW PerformanceData AS
+    SELECT id AS trackId, isAnalyzed, trackData, overviewWaveFormData, beatData, quickCues,
+        loops, thirdPartySourceId, activeOnLoadLoops
+    FROM Track;
+";
+
+/// Exports tracks and playlists as an Engine Library, that Engine DJ and Denon SC players read:
+///
+/// - the audio files in Engine Library/Music/<artist>/<album>/
+/// - the database in Engine Library/Database2/m.db
+///
+/// The database is written from scratch on every export. It replaces the previous one only once it
+/// is complete, so a failed export keeps the existing collection. Audio files, that already exist
+/// with the same size, are not copied again. Tracks, whose files can not be read, are skipped.
+pub fn export(
+    tracks: &[Arc<Track>],
+    playlists: &[PlaylistNode],
+    dest: &Path,
+) -> Result<(), InteropError> {
+    let library_dir = dest.join("Engine Library");
+    let database_dir = library_dir.join("Database2");
+    fs::create_dir_all(&database_dir)?;
+    // e.g. left behind by an export, that failed
+    let tmp_path = database_dir.join("m.db.tmp");
+    if tmp_path.exists() {
+        fs::remove_file(&tmp_path)?;
+    }
+    write_database(tracks, playlists, &library_dir, &tmp_path)?;
+    // sqlite would apply the journals of the old database to the new one
+    for journal in ["m.db-journal", "m.db-wal", "m.db-shm"] {
+        let journal_path = database_dir.join(journal);
+        if journal_path.exists() {
+            fs::remove_file(journal_path)?;
+        }
+    }
+    fs::rename(tmp_path, database_dir.join("m.db"))?;
+    Ok(())
+}
+
+/// writes the database with all tracks, that could be copied into the library directory
+fn write_database(
+    tracks: &[Arc<Track>],
+    playlists: &[PlaylistNode],
+    library_dir: &Path,
+    db_path: &Path,
+) -> Result<(), InteropError> {
+    let mut conn = Connection::open(db_path)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+    let uuid = random_uuid();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    tx.execute(
+        "INSERT INTO Information (uuid, schemaVersionMajor, schemaVersionMinor, \
+         schemaVersionPatch, currentPlayedIndiciator, lastRekordBoxLibraryImportReadCounter) \
+         VALUES (?, ?, ?, ?, 0, 0)",
+        params![uuid, SCHEMA_VERSION.0, SCHEMA_VERSION.1, SCHEMA_VERSION.2],
+    )?;
+    // tracks reference the album art with id 1, when they have none
+    tx.execute(
+        "INSERT INTO AlbumArt (hash, albumArt) VALUES (NULL, NULL)",
+        [],
+    )?;
+    let mut track_ids = vec![];
+    let mut files = DeviceFiles::default();
+    for track in tracks {
+        let meta = track.meta.read().unwrap().clone();
+        let artist = non_empty(&meta.artist, "Unknown Artist");
+        let album = non_empty(&meta.album, "Unknown Album");
+        // copy the audio file, paths are relative to the database directory
+        let dir = format!("Music/{}/{}", sanitize(artist), sanitize(album));
+        let file = match files.copy(track, library_dir, &dir)? {
+            Some(file) => file,
+            None => continue,
+        };
+        let file_size = file.size;
+        let duration = track.duration().unwrap_or(0.);
+        let bitrate = if duration > 0. {
+            Some((file_size as f64 * 8. / duration / 1000.).round() as i64)
+        } else {
+            None
+        };
+        let sample_rate = track.codec_params.sample_rate.unwrap_or(0) as f64;
+        let n_frames = track.codec_params.n_frames.unwrap_or(0) as i64;
+        let key = meta.key.map(engine_key);
+        let main_cue = track
+            .mem_cues()
+            .first()
+            .map_or(0., |cue| cue.position * sample_rate);
+        let beat_data = track
+            .beatgrid()
+            .map(|beatgrid| beat_data(&beatgrid, sample_rate, n_frames, duration));
+        tx.execute(
+            "INSERT INTO Track (playOrder, length, bpm, path, filename, bitrate, bpmAnalyzed, \
+             albumArtId, fileBytes, title, artist, album, key, rating, isPlayed, fileType, \
+             isAnalyzed, dateCreated, dateAdded, isAvailable, isMetadataOfPackedTrackChanged, \
+             isPerfomanceDataOfPackedTrackChanged, playedIndicator, isMetadataImported, \
+             pdbImportKey, isBeatGridLocked, originDatabaseUuid, originTrackId, trackData, \
+             overviewWaveFormData, beatData, quickCues, loops, streamingFlags, explicitLyrics, \
+             lastEditTime) \
+             VALUES (NULL, ?, ?, ?, ?, ?, ?, 1, ?, ?, ?, ?, ?, 0, 0, ?, 1, ?, ?, 1, 0, 0, 0, 1, \
+             0, 0, ?, NULL, ?, ?, ?, ?, ?, 0, 0, ?)",
+            params![
+                duration.round() as i64,
+                meta.bpm.map(|bpm| bpm.value.round() as i64),
+                format!("../{}", file.path),
+                file.file_name,
+                bitrate,
+                meta.bpm.map(|bpm| bpm.value),
+                file_size as i64,
+                non_empty(&meta.title, &track.file_name),
+                artist,
+                album,
+                key,
+                file_extension(&track.file_path),
+                now,
+                now,
+                uuid,
+                compress(&track_data(sample_rate, n_frames, key.unwrap_or(0)))?,
+                compress(&overview_data(&track.preview_samples(), n_frames))?,
+                beat_data.map(|data| compress(&data)).transpose()?,
+                compress(&quick_cues(&track.hot_cues(), main_cue, sample_rate))?,
+                loops(&track.loops(), sample_rate),
+                now,
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.execute(
+            "UPDATE Track SET originTrackId = ? WHERE id = ?",
+            params![id, id],
+        )?;
+        track_ids.push((track.file_path.as_str(), id));
+    }
+    add_playlist_nodes(&tx, playlists, 0, &track_ids, &uuid, now)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// adds playlists for the nodes with their entries. Engine has no folders, so folders become
+/// playlists, that only have children. Siblings and entries form linked lists.
+fn add_playlist_nodes(
+    tx: &Transaction,
+    nodes: &[PlaylistNode],
+    parent_id: i64,
+    track_ids: &[(&str, i64)],
+    uuid: &str,
+    now: i64,
+) -> Result<(), InteropError> {
+    let mut previous_id = None;
+    for node in nodes {
+        tx.execute(
+            "INSERT INTO Playlist (title, parentListId, isPersisted, nextListId, lastEditTime, \
+             isExplicitlyExported) VALUES (?, ?, 1, 0, ?, 1)",
+            params![node.name(), parent_id, now],
+        )?;
+        let id = tx.last_insert_rowid();
+        if let Some(previous_id) = previous_id {
+            tx.execute(
+                "UPDATE Playlist SET nextListId = ? WHERE id = ?",
+                params![id, previous_id],
+            )?;
+        }
+        previous_id = Some(id);
+        match node {
+            PlaylistNode::Folder { children, .. } => {
+                add_playlist_nodes(tx, children, id, track_ids, uuid, now)?;
+            }
+            PlaylistNode::Playlist { tracks, .. } => {
+                let mut previous_entity = None;
+                let entries = tracks.iter().filter_map(|file_path| {
+                    track_ids
+                        .iter()
+                        .find(|(path, _)| path == file_path)
+                        .map(|(_, track_id)| *track_id)
+                });
+                for track_id in entries {
+                    tx.execute(
+                        "INSERT INTO PlaylistEntity (listId, trackId, databaseUuid, \
+                         nextEntityId, membershipReference) VALUES (?, ?, ?, 0, 0)",
+                        params![id, track_id, uuid],
+                    )?;
+                    let entity_id = tx.last_insert_rowid();
+                    if let Some(previous_entity) = previous_entity {
+                        tx.execute(
+                            "UPDATE PlaylistEntity SET nextEntityId = ? WHERE id = ?",
+                            params![entity_id, previous_entity],
+                        )?;
+                    }
+                    previous_entity = Some(entity_id);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+//------------------------------------------------------------------//
+//                         Performance Data                         //
+//------------------------------------------------------------------//
+
+/// compresses a blob like Qt's qCompress: the uncompressed length as big endian u32, followed by
+/// the zlib stream
+fn compress(data: &[u8]) -> Result<Vec<u8>, InteropError> {
+    let mut encoder = ZlibEncoder::new(
+        (data.len() as u32).to_be_bytes().to_vec(),
+        Compression::default(),
+    );
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// trackData: sample rate, number of samples, average loudness and key, all big endian
+fn track_data(sample_rate: f64, n_frames: i64, key: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend(sample_rate.to_be_bytes());
+    bytes.extend(n_frames.to_be_bytes());
+    bytes.extend(0f64.to_be_bytes());
+    bytes.extend((key as i32).to_be_bytes());
+    bytes
+}
+
+/// beatData: sample rate and number of samples, followed by the default and the adjusted
+/// beatgrid, which are the same here. The first marker lies before the start of the track and the
+/// last one after its end.
+fn beat_data(beatgrid: &Beatgrid, sample_rate: f64, n_frames: i64, duration: f64) -> Vec<u8> {
+    let first = beatgrid.beat_index_at(0.).floor() as i64;
+    let last = beatgrid.beat_index_at(duration).ceil() as i64 + 1;
+    let mut beats = vec![first];
+    beats.extend(
+        beatgrid
+            .anchors
+            .iter()
+            .map(|anchor| anchor.beat)
+            .filter(|beat| *beat > first && *beat < last),
+    );
+    beats.push(last);
+    // the number of markers is big endian, the markers themselves are little endian
+    let mut markers = vec![];
+    markers.extend((beats.len() as i64).to_be_bytes());
+    for (i, beat) in beats.iter().enumerate() {
+        let beats_until_next = beats.get(i + 1).map_or(0, |next| next - beat);
+        markers.extend((beatgrid.beat_position(*beat as f64) * sample_rate).to_le_bytes());
+        markers.extend(beat.to_le_bytes());
+        markers.extend((beats_until_next as i32).to_le_bytes());
+        markers.extend(0i32.to_le_bytes());
+    }
+    let mut bytes = vec![];
+    bytes.extend(sample_rate.to_be_bytes());
+    bytes.extend((n_frames as f64).to_be_bytes());
+    bytes.push(1);
+    bytes.extend(&markers);
+    bytes.extend(&markers);
+    bytes
+}
+
+/// quickCues: eight hot cue slots and the main cue, positions in samples. Denon players have no
+/// memory cues, so the first one becomes the main cue.
+fn quick_cues(hot_cues: &[(usize, HotCue)], main_cue: f64, sample_rate: f64) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend((SLOTS as i64).to_be_bytes());
+    for slot in 0..SLOTS {
+        match hot_cues.iter().find(|(cue_slot, _)| *cue_slot == slot) {
+            Some((_, cue)) => {
+                // the length of the label is a single byte
+                let mut end = cue.label.len().min(u8::MAX as usize);
+                while !cue.label.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let label = &cue.label.as_bytes()[..end];
+                bytes.push(label.len() as u8);
+                bytes.extend(label);
+                bytes.extend((cue.position * sample_rate).to_be_bytes());
+                bytes.extend([0xff, cue.color.r, cue.color.g, cue.color.b]);
+            }
+            None => {
+                bytes.push(0);
+                bytes.extend((-1f64).to_be_bytes());
+                bytes.extend([0; 4]);
+            }
+        }
+    }
+    bytes.extend(main_cue.to_be_bytes());
+    bytes.push(0);
+    bytes.extend(main_cue.to_be_bytes());
+    bytes
+}
+
+/// loops: the first eight saved loops, positions in samples. Unlike the other blobs, this one is
+/// little endian and not compressed.
+fn loops(saved_loops: &[Loop], sample_rate: f64) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend((SLOTS as i64).to_le_bytes());
+    for slot in 0..SLOTS {
+        match saved_loops.get(slot) {
+            Some(saved_loop) => {
+                bytes.push(0);
+                bytes.extend((saved_loop.start * sample_rate).to_le_bytes());
+                bytes.extend((saved_loop.end * sample_rate).to_le_bytes());
+                // whether the start and end are set
+                bytes.extend([1, 1]);
+                let color = CueColor::for_slot(slot);
+                bytes.extend([0xff, color.r, color.g, color.b]);
+            }
+            None => {
+                bytes.push(0);
+                bytes.extend((-1f64).to_le_bytes());
+                bytes.extend((-1f64).to_le_bytes());
+                bytes.extend([0, 0]);
+                bytes.extend([0; 4]);
+            }
+        }
+    }
+    bytes
+}
+
+/// overviewWaveFormData: the number of entries (twice) and samples per entry, followed by one
+/// byte each for lows, mids and highs per entry and the maximum of every band
+fn overview_data(preview: &[PreviewSample], n_frames: i64) -> Vec<u8> {
+    let entries = resample(preview, OVERVIEW_ENTRIES);
+    let mut bytes = vec![];
+    bytes.extend((entries.len() as i64).to_be_bytes());
+    bytes.extend((entries.len() as i64).to_be_bytes());
+    bytes.extend((n_frames as f64 / entries.len().max(1) as f64).to_be_bytes());
+    let scale = |band: f32| (band.min(1.) * 255.).round() as u8;
+    let mut max = [0u8; 3];
+    for entry in &entries {
+        let bands = [scale(entry.lows), scale(entry.mids), scale(entry.highs)];
+        for (max, band) in max.iter_mut().zip(bands) {
+            *max = (*max).max(band);
+        }
+        bytes.extend(bands);
+    }
+    bytes.extend(max);
+    bytes
+}
+
+//------------------------------------------------------------------//
+//                             Helpers                              //
+//------------------------------------------------------------------//
+
+/// Engine numbers keys along the circle of fifths, starting at C major, with every major key
+/// followed by its relative minor: 0 is C, 1 Am, 2 G, 3 Em, ...
+fn engine_key(key: Key) -> i64 {
+    // C major is 8B on the Camelot wheel
+    let fifths = (key.camelot_number() as i64 - 8).rem_euclid(12);
+    fifths * 2
+        + match key.mode {
+            Mode::Major => 0,
+            Mode::Minor => 1,
+        }
+}
+
+/// a random version 4 uuid, that identifies the database
+fn random_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    /// a low sample rate keeps the positions in samples readable
+    const RATE: f64 = 1000.;
+
+    #[test]
+    fn compresses_like_qt() {
+        let data = b"engine".repeat(10);
+        let compressed = compress(&data).unwrap();
+        assert_eq!(compressed[..4], [0, 0, 0, 60]);
+        let mut decompressed = vec![];
+        ZlibDecoder::new(&compressed[4..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn writes_beat_markers_around_the_track() {
+        let beatgrid = Beatgrid::new(0.25, 120.);
+        let markers: Vec<u8> = [
+            &2i64.to_be_bytes()[..],
+            // the beat before the start of the track, 4 beats until the next marker
+            &(-250f64).to_le_bytes(),
+            &(-1i64).to_le_bytes(),
+            &[4, 0, 0, 0, 0, 0, 0, 0],
+            // the beat after the end of the track
+            &1750f64.to_le_bytes(),
+            &3i64.to_le_bytes(),
+            &[0; 8],
+        ]
+        .concat();
+        let expected: Vec<u8> = [
+            &RATE.to_be_bytes()[..],
+            &1000f64.to_be_bytes(),
+            &[1],
+            &markers,
+            &markers,
+        ]
+        .concat();
+        assert_eq!(beat_data(&beatgrid, RATE, 1000, 1.), expected);
+    }
+
+    #[test]
+    fn writes_quick_cues_into_their_slots() {
+        let cue = HotCue {
+            position: 0.5,
+            label: String::from("Drop"),
+            color: CueColor::new(1, 2, 3),
+        };
+        let empty: Vec<u8> = [&[0][..], &(-1f64).to_be_bytes(), &[0; 4]].concat();
+        let mut expected: Vec<u8> = [
+            &8i64.to_be_bytes()[..],
+            &empty,
+            &[4],
+            b"Drop",
+            &500f64.to_be_bytes(),
+            &[0xff, 1, 2, 3],
+        ]
+        .concat();
+        for _ in 2..SLOTS {
+            expected.extend(&empty);
+        }
+        // the main cue, twice
+        expected.extend(250f64.to_be_bytes());
+        expected.push(0);
+        expected.extend(250f64.to_be_bytes());
+        assert_eq!(quick_cues(&[(1, cue)], 250., RATE), expected);
+    }
+
+    #[test]
+    fn cuts_quick_cue_labels_at_a_character() {
+        let cue = HotCue {
+            position: 0.,
+            label: format!("{}é", "a".repeat(254)),
+            color: CueColor::new(0, 0, 0),
+        };
+        let bytes = quick_cues(&[(0, cue)], 0., RATE);
+        assert_eq!(bytes[8], 254);
+        assert_eq!(bytes[9..9 + 254], *"a".repeat(254).as_bytes());
+    }
+
+    #[test]
+    fn writes_loops_little_endian() {
+        let color = CueColor::for_slot(0);
+        let mut expected: Vec<u8> = [
+            &8i64.to_le_bytes()[..],
+            &[0],
+            &1000f64.to_le_bytes(),
+            &2000f64.to_le_bytes(),
+            &[1, 1, 0xff, color.r, color.g, color.b],
+        ]
+        .concat();
+        for _ in 1..SLOTS {
+            expected.push(0);
+            expected.extend((-1f64).to_le_bytes());
+            expected.extend((-1f64).to_le_bytes());
+            expected.extend([0; 6]);
+        }
+        assert_eq!(loops(&[Loop::new(1., 2.)], RATE), expected);
+    }
+
+    #[test]
+    fn writes_the_overview_bands_and_their_maximum() {
+        let preview = [
+            PreviewSample {
+                lows: 0.5,
+                mids: 0.25,
+                highs: 0.25,
+            },
+            PreviewSample {
+                lows: 0.,
+                mids: 0.5,
+                highs: 0.,
+            },
+        ];
+        let mut expected: Vec<u8> = [
+            &1024i64.to_be_bytes()[..],
+            &1024i64.to_be_bytes(),
+            // samples per entry
+            &2f64.to_be_bytes(),
+        ]
+        .concat();
+        for _ in 0..512 {
+            expected.extend([128, 64, 64]);
+        }
+        for _ in 0..512 {
+            expected.extend([0, 128, 0]);
+        }
+        expected.extend([128, 128, 64]);
+        assert_eq!(overview_data(&preview, 2048), expected);
+    }
+}
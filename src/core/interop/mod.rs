@@ -0,0 +1,464 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    io::BufRead,
+    path::Path,
+};
+
+use log::warn;
+use quick_xml::{events::BytesStart, Reader};
+
+use crate::view::model::{
+    beatgrid::Beatgrid,
+    cue::{HotCue, Loop, MemCue, MEM_CUE_LIMIT},
+    playlist::PlaylistNode,
+    track::{Track, TrackMeta},
+};
+
+pub mod anlz;
+pub mod engine;
+pub mod pdb;
+pub mod rekordbox_device;
+pub mod rekordbox_xml;
+pub mod serato;
+pub mod traktor_nml;
+
+//------------------------------------------------------------------//
+//                              Interop                             //
+//------------------------------------------------------------------//
+
+/// Errors, that can occur while exchanging data with other DJ software
+#[derive(Debug)]
+pub enum InteropError {
+    Io(io::Error),
+    Xml(quick_xml::Error),
+    Sqlite(rusqlite::Error),
+    Id3(id3::Error),
+    /// the input is readable, but does not look like the expected format
+    Format(String),
+}
+
+impl fmt::Display for InteropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InteropError::Io(err) => write!(f, "io error: {}", err),
+            InteropError::Xml(err) => write!(f, "xml error: {}", err),
+            InteropError::Sqlite(err) => write!(f, "sqlite error: {}", err),
+            InteropError::Id3(err) => write!(f, "id3 error: {}", err),
+            InteropError::Format(msg) => write!(f, "invalid format: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for InteropError {}
+
+impl From<io::Error> for InteropError {
+    fn from(err: io::Error) -> Self {
+        InteropError::Io(err)
+    }
+}
+
+impl From<quick_xml::Error> for InteropError {
+    fn from(err: quick_xml::Error) -> Self {
+        InteropError::Xml(err)
+    }
+}
+
+impl From<rusqlite::Error> for InteropError {
+    fn from(err: rusqlite::Error) -> Self {
+        InteropError::Sqlite(err)
+    }
+}
+
+impl From<id3::Error> for InteropError {
+    fn from(err: id3::Error) -> Self {
+        InteropError::Id3(err)
+    }
+}
+
+//------------------------------------------------------------------//
+//                              Import                              //
+//------------------------------------------------------------------//
+
+/// Everything, that was read from another application's collection
+#[derive(Clone, Debug, Default)]
+pub struct Import {
+    pub tracks: Vec<ImportedTrack>,
+    pub playlists: Vec<PlaylistNode>,
+}
+
+/// The prepared data of a single track in another application. Positions are in seconds, because
+/// the codec parameters of the file are only known, once it gets opened.
+#[derive(Clone, Debug)]
+pub struct ImportedTrack {
+    pub file_path: String,
+    /// size of the file in bytes, if the collection has it
+    pub size: Option<u64>,
+    pub meta: TrackMeta,
+    pub beatgrid: Option<Beatgrid>,
+    /// memory cues in the order of the cue list
+    pub mem_cues: Vec<MemCue>,
+    /// hot cues with their slots
+    pub hot_cues: Vec<(usize, HotCue)>,
+    /// saved loops
+    pub loops: Vec<Loop>,
+}
+
+impl ImportedTrack {
+    pub fn new(file_path: String) -> Self {
+        Self {
+            file_path,
+            size: None,
+            meta: TrackMeta::default(),
+            beatgrid: None,
+            mem_cues: vec![],
+            hot_cues: vec![],
+            loops: vec![],
+        }
+    }
+
+    /// whether the imported size matches the size of a file. Traktor only stores kilobytes, so
+    /// sizes are compared in kilobytes. Unknown sizes always match.
+    fn matches_size(&self, file_size: Option<u64>) -> bool {
+        match (self.size, file_size) {
+            (Some(size), Some(file_size)) => size / 1024 == file_size / 1024,
+            _ => true,
+        }
+    }
+
+    /// copies the imported data onto a track. Imported values win over tags and analysis
+    /// results, empty values are ignored.
+    pub fn apply_to(&self, track: &Track) {
+        {
+            let mut guard = track.meta.write().unwrap();
+            let meta = &mut *guard;
+            for (field, imported) in [
+                (&mut meta.title, &self.meta.title),
+                (&mut meta.artist, &self.meta.artist),
+                (&mut meta.album, &self.meta.album),
+            ] {
+                if !imported.is_empty() {
+                    *field = imported.clone();
+                }
+            }
+            if self.meta.bpm.is_some() {
+                meta.bpm = self.meta.bpm;
+            }
+            if self.meta.key.is_some() {
+                meta.key = self.meta.key;
+            }
+        }
+        if let Some(beatgrid) = &self.beatgrid {
+            track.set_beatgrid(beatgrid.clone());
+        }
+        let existing = track.mem_cues();
+        for cue in &self.mem_cues {
+            // importing the same collection twice should not duplicate cues. Most formats store
+            // milliseconds, so closer cues are the same.
+            if existing
+                .iter()
+                .any(|other| (other.position - cue.position).abs() < 0.001)
+            {
+                continue;
+            }
+            if !track.add_mem_cue(cue.clone()) {
+                warn!(
+                    "skipped imported cues of {}: limit of {} memory cues reached",
+                    track.file_path, MEM_CUE_LIMIT
+                );
+                break;
+            }
+        }
+        for (slot, cue) in &self.hot_cues {
+            track.set_hot_cue(*slot, cue.clone());
+        }
+        for imported_loop in &self.loops {
+            track.add_loop(*imported_loop);
+        }
+    }
+}
+
+/// Imported tracks, that wait for the corresponding file to show up in the track list
+#[derive(Default)]
+pub struct PendingImports {
+    tracks: HashMap<String, ImportedTrack>,
+}
+
+impl PendingImports {
+    pub fn insert(&mut self, imported: ImportedTrack) {
+        self.tracks.insert(imported.file_path.clone(), imported);
+    }
+
+    /// removes and returns the import for a file. Collections from other machines have different
+    /// paths, so if there is no exact match, the track is matched by its file name and size. Files
+    /// like "01 Intro.mp3" are common, so a match is only used, if it is the only one.
+    pub fn take(&mut self, file_path: &str) -> Option<ImportedTrack> {
+        if let Some(imported) = self.tracks.remove(file_path) {
+            return Some(imported);
+        }
+        let file_name = Path::new(file_path).file_name()?;
+        let file_size = fs::metadata(file_path).ok().map(|metadata| metadata.len());
+        let candidates: Vec<&String> = self
+            .tracks
+            .iter()
+            .filter(|(path, imported)| {
+                Path::new(path).file_name() == Some(file_name) && imported.matches_size(file_size)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        match candidates.as_slice() {
+            [key] => {
+                let key = (*key).clone();
+                self.tracks.remove(&key)
+            }
+            [] => None,
+            _ => {
+                warn!(
+                    "skipped import for {}: {} imported tracks have the same file name",
+                    file_path,
+                    candidates.len()
+                );
+                None
+            }
+        }
+    }
+
+    /// the file paths of the imports, that are still waiting for their track
+    pub fn file_paths(&self) -> Vec<String> {
+        let mut file_paths: Vec<String> = self.tracks.keys().cloned().collect();
+        file_paths.sort();
+        file_paths
+    }
+}
+
+/// A tempo marker: (position in seconds, bpm, beat number within the bar)
+pub type Tempo = (f64, f64, i64);
+
+/// builds a beatgrid from tempo markers. Rekordbox writes one marker per tempo change, some
+/// versions even one per beat, so markers, that don't change the tempo, are skipped.
+pub fn beatgrid_from_tempos(mut tempos: Vec<Tempo>) -> Option<Beatgrid> {
+    tempos.retain(|(_, bpm, _)| *bpm > 0.);
+    tempos.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (position, bpm, beat_in_bar) = *tempos.first()?;
+    let first_downbeat = position - (beat_in_bar - 1) as f64 * 60. / bpm;
+    let mut beatgrid = Beatgrid::new(first_downbeat, bpm);
+    for (position, bpm, _) in tempos.into_iter().skip(1) {
+        if (bpm - beatgrid.bpm_at(position)).abs() > 0.01 {
+            let beat = beatgrid.beat_index_at(position).round() as i64;
+            beatgrid.add_anchor(beat, bpm);
+        }
+    }
+    Some(beatgrid)
+}
+
+/// puts imported playlists into a folder of the given name, replacing the one from a previous
+/// import
+pub fn replace_folder(
+    playlists: &mut Vec<PlaylistNode>,
+    folder_name: &str,
+    imported: Vec<PlaylistNode>,
+) {
+    playlists
+        .retain(|node| !matches!(node, PlaylistNode::Folder { name, .. } if name == folder_name));
+    playlists.push(PlaylistNode::folder(String::from(folder_name), imported));
+}
+
+/// closes the innermost open playlist node and adds it to its parent. The root node itself is not
+/// part of the imported tree, only its children.
+pub fn close_node(nodes: &mut Vec<PlaylistNode>, import: &mut Import) {
+    match (nodes.pop(), nodes.last_mut()) {
+        (Some(node), Some(PlaylistNode::Folder { children, .. })) => children.push(node),
+        (Some(PlaylistNode::Folder { children, .. }), None) => import.playlists = children,
+        _ => {}
+    }
+}
+
+//------------------------------------------------------------------//
+//                           Device Files                           //
+//------------------------------------------------------------------//
+
+/// An audio file, that was copied to a device
+pub struct CopiedFile {
+    /// path relative to the root of the export, e.g. "Contents/Artist/Album/track01 (2).mp3"
+    pub path: String,
+    /// the file name on the device, which may have a numbered suffix
+    pub file_name: String,
+    /// size in bytes
+    pub size: u64,
+}
+
+/// Copies the audio files of an export to a device. Different tracks with the same artist, album
+/// and file name, e.g. "Unknown Artist/Unknown Album/track01.mp3", get a numbered suffix, so each
+/// of them keeps its own file.
+#[derive(Default)]
+pub struct DeviceFiles {
+    /// lowercase paths of the copied files, because FAT32 drives ignore the case
+    taken: HashSet<String>,
+}
+
+impl DeviceFiles {
+    /// copies a track into a directory below root. Files, that already exist with the same size,
+    /// are not copied again. Returns None, if the track can not be read, so a single missing file
+    /// does not fail the whole export.
+    pub fn copy(
+        &mut self,
+        track: &Track,
+        root: &Path,
+        dir: &str,
+    ) -> Result<Option<CopiedFile>, InteropError> {
+        let size = match fs::metadata(&track.file_path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) => {
+                warn!("skipped {} in export: {}", track.file_path, err);
+                return Ok(None);
+            }
+        };
+        let (path, file_name) = self.unique_path(dir, &sanitize(&track.file_name));
+        let target = root.join(&path);
+        if fs::metadata(&target).map(|m| m.len()).ok() != Some(size) {
+            fs::create_dir_all(target.parent().unwrap_or(root))?;
+            fs::copy(&track.file_path, &target)?;
+        }
+        Ok(Some(CopiedFile {
+            path,
+            file_name,
+            size,
+        }))
+    }
+
+    /// the first free one of "dir/name.mp3", "dir/name (2).mp3", "dir/name (3).mp3" and so on,
+    /// together with its file name
+    fn unique_path(&mut self, dir: &str, file_name: &str) -> (String, String) {
+        let name = Path::new(file_name);
+        let stem = name
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(file_name);
+        let extension = name
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| format!(".{}", extension))
+            .unwrap_or_default();
+        (1..)
+            .map(|n| match n {
+                1 => String::from(file_name),
+                n => format!("{} ({}){}", stem, n, extension),
+            })
+            .map(|file_name| (format!("{}/{}", dir, file_name), file_name))
+            .find(|(path, _)| self.taken.insert(path.to_lowercase()))
+            .unwrap()
+    }
+}
+
+//------------------------------------------------------------------//
+//                             Helpers                              //
+//------------------------------------------------------------------//
+
+/// converts an absolute path into a file url, as used by most DJ software, e.g.
+/// "file://localhost/music/my%20track.mp3"
+pub fn path_to_file_url(path: &str) -> String {
+    let mut url = String::from("file://localhost");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+/// the inverse of path_to_file_url. Also accepts urls without a host ("file:///music/...").
+pub fn file_url_to_path(url: &str) -> Option<String> {
+    let path = url
+        .strip_prefix("file://localhost")
+        .or_else(|| url.strip_prefix("file://"))?;
+    let mut bytes = vec![];
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// the lowercase file extension of a path, e.g. "mp3"
+pub fn file_extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// collects the attributes of an xml element into a map
+pub fn xml_attributes<B: BufRead>(
+    element: &BytesStart,
+    reader: &Reader<B>,
+) -> Result<HashMap<String, String>, InteropError> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(attribute.key).into_owned();
+        attributes.insert(key, attribute.unescape_and_decode_value(reader)?);
+    }
+    Ok(attributes)
+}
+
+/// the string or a default, if it is empty
+pub fn non_empty<'a>(s: &'a str, default: &'a str) -> &'a str {
+    if s.trim().is_empty() {
+        default
+    } else {
+        s
+    }
+}
+
+/// replaces characters, that are not allowed in file names on FAT32 drives
+pub fn sanitize(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_file_urls() {
+        for path in [
+            "/music/track.mp3",
+            "/music/My Track (Remix) [2021].mp3",
+            "/music/Café/100% Ünïcode & more?#.flac",
+        ] {
+            let url = path_to_file_url(path);
+            assert!(url.is_ascii());
+            assert_eq!(file_url_to_path(&url).as_deref(), Some(path));
+        }
+        assert_eq!(
+            path_to_file_url("/music/a b.mp3"),
+            "file://localhost/music/a%20b.mp3"
+        );
+    }
+
+    #[test]
+    fn accepts_file_urls_without_host() {
+        assert_eq!(
+            file_url_to_path("file:///music/a%20b.mp3").as_deref(),
+            Some("/music/a b.mp3")
+        );
+        assert_eq!(file_url_to_path("http://localhost/a.mp3"), None);
+        assert_eq!(file_url_to_path("file://localhost/a%2"), None);
+    }
+}
@@ -0,0 +1,426 @@
+use log::warn;
+
+//------------------------------------------------------------------//
+//                            export.pdb                            //
+//------------------------------------------------------------------//
+
+/// size of every page in the database
+const PAGE_SIZE: usize = 4096;
+/// size of the header at the start of every data page, the row heap starts after it
+const PAGE_HEADER_SIZE: usize = 0x28;
+/// row groups at the end of a page index up to 16 rows each: 16 row offsets, a bitmask of the
+/// rows, that are present, and an unknown 16 bit value
+const ROW_GROUP_SIZE: usize = 0x24;
+const ROWS_PER_GROUP: usize = 16;
+/// flags of a page, that contains rows
+const DATA_PAGE_FLAGS: u8 = 0x34;
+/// number of tables, that rekordbox writes into every export
+const NUM_TABLES: usize = 20;
+
+/// The tables of the database in the order of their type ids
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableType {
+    Tracks = 0,
+    Genres = 1,
+    Artists = 2,
+    Albums = 3,
+    Labels = 4,
+    Keys = 5,
+    Colors = 6,
+    PlaylistTree = 7,
+    PlaylistEntries = 8,
+    HistoryPlaylists = 11,
+    HistoryEntries = 12,
+    Artwork = 13,
+    Columns = 16,
+    History = 19,
+}
+
+/// Writer for the DeviceSQL database (export.pdb), that Pioneer DJ players browse tracks and
+/// playlists with. The file is a sequence of 4096 byte pages, all numbers are little endian. The
+/// first page holds pointers to the first and last page of every table, the pages of a table form
+/// a linked list. Only data pages are written, index pages are omitted.
+///
+/// rekordcrate only parses export.pdb and cannot write it, so the pages are laid out here. The
+/// offsets follow its table and row definitions and the crate-digger analysis of the format.
+pub struct Pdb {
+    /// rows of every table, indexed by table type
+    tables: Vec<Vec<Vec<u8>>>,
+}
+
+impl Pdb {
+    pub fn new() -> Self {
+        Self {
+            tables: vec![vec![]; NUM_TABLES],
+        }
+    }
+
+    pub fn add_row(&mut self, table: TableType, row: Vec<u8>) {
+        self.tables[table as usize].push(row);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // distribute the rows on pages, page 0 is the file header
+        let mut pages: Vec<(usize, Vec<&[u8]>)> = vec![];
+        let mut table_pages = vec![];
+        for (table, rows) in self.tables.iter().enumerate() {
+            let first_page = pages.len() + 1;
+            pages.extend(split_into_pages(rows).into_iter().map(|rows| (table, rows)));
+            table_pages.push((first_page, pages.len()));
+        }
+        let next_unused_page = pages.len() + 1;
+        let mut bytes = vec![0; PAGE_SIZE * next_unused_page];
+        // file header
+        put_u32(&mut bytes, 0x04, PAGE_SIZE as u32);
+        put_u32(&mut bytes, 0x08, NUM_TABLES as u32);
+        put_u32(&mut bytes, 0x0c, next_unused_page as u32);
+        put_u32(&mut bytes, 0x10, 5);
+        // sequence number of the database, incremented by rekordbox on every change
+        put_u32(&mut bytes, 0x14, 1);
+        for (table, (first_page, last_page)) in table_pages.iter().enumerate() {
+            let pointer = 0x1c + table * 16;
+            put_u32(&mut bytes, pointer, table as u32);
+            // the page, that would be used for the next new page of this table
+            put_u32(&mut bytes, pointer + 4, next_unused_page as u32);
+            put_u32(&mut bytes, pointer + 8, *first_page as u32);
+            put_u32(&mut bytes, pointer + 12, *last_page as u32);
+        }
+        for (i, (table, rows)) in pages.iter().enumerate() {
+            let page_index = i + 1;
+            let next_page = if page_index == table_pages[*table].1 {
+                next_unused_page
+            } else {
+                page_index + 1
+            };
+            let page = &mut bytes[page_index * PAGE_SIZE..(page_index + 1) * PAGE_SIZE];
+            write_page(page, page_index, *table, next_page, rows);
+        }
+        bytes
+    }
+}
+
+/// greedily fills pages with rows. Every table gets at least one (possibly empty) page.
+fn split_into_pages(rows: &[Vec<u8>]) -> Vec<Vec<&[u8]>> {
+    let available = PAGE_SIZE - PAGE_HEADER_SIZE;
+    let mut pages = vec![vec![]];
+    let mut heap_size = 0;
+    for row in rows {
+        let row_size = aligned(row.len());
+        if row_size + ROW_GROUP_SIZE > available {
+            warn!(
+                "skipping database row of {} bytes, that does not fit a page",
+                row.len()
+            );
+            continue;
+        }
+        let num_rows = pages.last().unwrap().len() + 1;
+        if heap_size + row_size + num_groups(num_rows) * ROW_GROUP_SIZE > available {
+            pages.push(vec![]);
+            heap_size = 0;
+        }
+        pages.last_mut().unwrap().push(row.as_slice());
+        heap_size += row_size;
+    }
+    pages
+}
+
+fn write_page(page: &mut [u8], page_index: usize, table: usize, next_page: usize, rows: &[&[u8]]) {
+    put_u32(page, 0x04, page_index as u32);
+    put_u32(page, 0x08, table as u32);
+    put_u32(page, 0x0c, next_page as u32);
+    put_u32(page, 0x10, 1);
+    // 13 bits for the number of row offsets and 11 bits for the number of rows
+    let row_counts = rows.len() as u32 | (rows.len() as u32) << 13;
+    page[0x18..0x1b].copy_from_slice(&row_counts.to_le_bytes()[..3]);
+    page[0x1b] = DATA_PAGE_FLAGS;
+    // rows are stored from the start of the heap, their offsets from the end of the page
+    let mut offsets = vec![];
+    let mut heap_size = 0;
+    for row in rows {
+        let start = PAGE_HEADER_SIZE + heap_size;
+        page[start..start + row.len()].copy_from_slice(row);
+        offsets.push(heap_size as u16);
+        heap_size += aligned(row.len());
+    }
+    let free_size =
+        PAGE_SIZE - PAGE_HEADER_SIZE - heap_size - num_groups(rows.len()) * ROW_GROUP_SIZE;
+    put_u16(page, 0x1c, free_size as u16);
+    put_u16(page, 0x1e, heap_size as u16);
+    put_u16(page, 0x20, 1);
+    // readers fall back to this count, if the row count does not fit into one byte
+    if rows.len() > 0xff {
+        put_u16(page, 0x22, rows.len() as u16);
+    }
+    for (group, offsets) in offsets.chunks(ROWS_PER_GROUP).enumerate() {
+        let end = PAGE_SIZE - group * ROW_GROUP_SIZE;
+        let present = ((1u32 << offsets.len()) - 1) as u16;
+        put_u16(page, end - 4, present);
+        for (i, offset) in offsets.iter().enumerate() {
+            put_u16(page, end - 6 - 2 * i, *offset);
+        }
+    }
+}
+
+fn num_groups(num_rows: usize) -> usize {
+    (num_rows + ROWS_PER_GROUP - 1) / ROWS_PER_GROUP
+}
+
+/// rows start at 4 byte boundaries
+fn aligned(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn put_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+//------------------------------------------------------------------//
+//                               Rows                               //
+//------------------------------------------------------------------//
+
+/// The columns of a track row, that Flow knows about
+pub struct TrackRow {
+    pub id: u32,
+    pub title: String,
+    pub artist_id: u32,
+    pub album_id: u32,
+    pub key_id: u32,
+    pub sample_rate: u32,
+    pub file_size: u32,
+    /// bitrate in kbit/s
+    pub bitrate: u32,
+    /// tempo in 1/100 bpm
+    pub tempo: u32,
+    /// duration in seconds
+    pub duration: u16,
+    pub file_type: u16,
+    /// path of the audio file on the device
+    pub file_path: String,
+    pub file_name: String,
+    /// path of the ANLZ0000.DAT file on the device
+    pub analyze_path: String,
+    /// date in the format "YYYY-MM-DD"
+    pub date_added: String,
+}
+
+/// number of string offsets at the end of the fixed part of a track row
+const TRACK_STRINGS: usize = 21;
+/// size of the fixed part of a track row
+const TRACK_ROW_HEADER_SIZE: usize = 0x5e + 2 * TRACK_STRINGS;
+
+pub fn track_row(track: &TrackRow) -> Vec<u8> {
+    let mut row = vec![0; TRACK_ROW_HEADER_SIZE];
+    put_u16(&mut row, 0x00, 0x24);
+    put_u16(&mut row, 0x02, index_shift(track.id));
+    put_u32(&mut row, 0x04, 0x000c_0700);
+    put_u32(&mut row, 0x08, track.sample_rate);
+    put_u32(&mut row, 0x10, track.file_size);
+    put_u16(&mut row, 0x18, 19048);
+    put_u16(&mut row, 0x1a, 30967);
+    put_u32(&mut row, 0x20, track.key_id);
+    put_u32(&mut row, 0x30, track.bitrate);
+    put_u32(&mut row, 0x38, track.tempo);
+    put_u32(&mut row, 0x40, track.album_id);
+    put_u32(&mut row, 0x44, track.artist_id);
+    put_u32(&mut row, 0x48, track.id);
+    put_u16(&mut row, 0x52, 16);
+    put_u16(&mut row, 0x54, track.duration);
+    put_u16(&mut row, 0x56, 0x29);
+    put_u16(&mut row, 0x5a, track.file_type);
+    put_u16(&mut row, 0x5c, 3);
+    // string columns, most of them are not used by flow
+    let mut strings = vec![String::new(); TRACK_STRINGS];
+    strings[10] = track.date_added.clone();
+    strings[14] = track.analyze_path.clone();
+    strings[15] = track.date_added.clone();
+    strings[17] = track.title.clone();
+    strings[19] = track.file_name.clone();
+    strings[20] = track.file_path.clone();
+    for (i, string) in strings.iter().enumerate() {
+        let offset = row.len() as u16;
+        put_u16(&mut row, 0x5e + 2 * i, offset);
+        row.extend(device_sql_string(string));
+    }
+    row
+}
+
+pub fn artist_row(id: u32, name: &str) -> Vec<u8> {
+    let mut row = vec![0; 10];
+    put_u16(&mut row, 0x00, 0x60);
+    put_u16(&mut row, 0x02, index_shift(id));
+    put_u32(&mut row, 0x04, id);
+    row[0x08] = 0x03;
+    // offset of the name
+    row[0x09] = 10;
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn album_row(id: u32, artist_id: u32, name: &str) -> Vec<u8> {
+    let mut row = vec![0; 22];
+    put_u16(&mut row, 0x00, 0x80);
+    put_u16(&mut row, 0x02, index_shift(id));
+    put_u32(&mut row, 0x08, artist_id);
+    put_u32(&mut row, 0x0c, id);
+    row[0x14] = 0x03;
+    // offset of the name
+    row[0x15] = 22;
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn key_row(id: u32, name: &str) -> Vec<u8> {
+    let mut row = vec![];
+    row.extend(id.to_le_bytes());
+    row.extend(id.to_le_bytes());
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn playlist_tree_row(
+    id: u32,
+    parent_id: u32,
+    sort_order: u32,
+    is_folder: bool,
+    name: &str,
+) -> Vec<u8> {
+    let mut row = vec![];
+    row.extend(parent_id.to_le_bytes());
+    row.extend(0u32.to_le_bytes());
+    row.extend(sort_order.to_le_bytes());
+    row.extend(id.to_le_bytes());
+    row.extend((is_folder as u32).to_le_bytes());
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn playlist_entry_row(entry_index: u32, track_id: u32, playlist_id: u32) -> Vec<u8> {
+    let mut row = vec![];
+    row.extend(entry_index.to_le_bytes());
+    row.extend(track_id.to_le_bytes());
+    row.extend(playlist_id.to_le_bytes());
+    row
+}
+
+/// rows with a subtype store their position in the table shifted by 5 bits
+fn index_shift(id: u32) -> u16 {
+    (id.saturating_sub(1) << 5) as u16
+}
+
+/// DeviceSQL strings: short ASCII strings have a one byte header with their length, longer ones
+/// and strings with other characters have a four byte header and are stored as ASCII or UTF-16LE
+fn device_sql_string(s: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    if s.is_ascii() && s.len() < 127 {
+        bytes.push((((s.len() + 1) << 1) | 1) as u8);
+        bytes.extend(s.as_bytes());
+    } else if s.is_ascii() {
+        bytes.push(0x40);
+        bytes.extend(((s.len() + 4) as u16).to_le_bytes());
+        bytes.push(0);
+        bytes.extend(s.as_bytes());
+    } else {
+        let chars: Vec<u8> = s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+        bytes.push(0x90);
+        bytes.extend(((chars.len() + 4) as u16).to_le_bytes());
+        bytes.push(0);
+        bytes.extend(chars);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the page of a table in a database, where every table fits into one page
+    fn page(bytes: &[u8], table: TableType) -> &[u8] {
+        let page_index = u32::from_le_bytes(
+            bytes[0x1c + table as usize * 16 + 8..][..4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        &bytes[page_index * PAGE_SIZE..(page_index + 1) * PAGE_SIZE]
+    }
+
+    #[test]
+    fn writes_the_page_header() {
+        let mut pdb = Pdb::new();
+        pdb.add_row(TableType::Artists, artist_row(1, "A"));
+        let bytes = pdb.to_bytes();
+        // one page per table after the file header
+        assert_eq!(bytes.len(), 21 * PAGE_SIZE);
+        assert_eq!(
+            bytes[0x1c + 2 * 16..0x1c + 3 * 16],
+            [2, 0, 0, 0, 21, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0]
+        );
+        assert_eq!(
+            page(&bytes, TableType::Artists)[..PAGE_HEADER_SIZE],
+            [
+                0, 0, 0, 0, // always zero
+                3, 0, 0, 0, // page index
+                2, 0, 0, 0, // table type
+                21, 0, 0, 0, // next page
+                1, 0, 0, 0, // unknown
+                0, 0, 0, 0, // unknown
+                0x01, 0x20, 0x00, // row counts
+                0x34, // page flags
+                0xa8, 0x0f, // free size
+                12, 0, // used size
+                1, 0, // unknown
+                0, 0, // large row count
+                0, 0, 0, 0, // unknown
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_the_row_index() {
+        let mut pdb = Pdb::new();
+        for id in 1..=17 {
+            pdb.add_row(TableType::Keys, key_row(id, "1A"));
+        }
+        let bytes = pdb.to_bytes();
+        let page = page(&bytes, TableType::Keys);
+        // the rows are padded to 12 bytes
+        assert_eq!(
+            page[PAGE_HEADER_SIZE + 12..][..11],
+            *b"\x02\0\0\0\x02\0\0\0\x071A"
+        );
+        // the first group is full, its offsets are stored backwards before the bitmask
+        let first_group = &page[PAGE_SIZE - ROW_GROUP_SIZE..];
+        assert_eq!(first_group[ROW_GROUP_SIZE - 4..], [0xff, 0xff, 0, 0]);
+        assert_eq!(first_group[ROW_GROUP_SIZE - 8..][..4], [12, 0, 0, 0]);
+        assert_eq!(first_group[..2], [180, 0]);
+        // the second group is in front of it and only has the 17th row
+        let second_group = &page[PAGE_SIZE - 2 * ROW_GROUP_SIZE..][..ROW_GROUP_SIZE];
+        assert_eq!(second_group[ROW_GROUP_SIZE - 6..], [192, 0, 1, 0, 0, 0]);
+        assert!(second_group[..ROW_GROUP_SIZE - 6].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn encodes_short_strings() {
+        assert_eq!(device_sql_string(""), [0x03]);
+        assert_eq!(device_sql_string("abc"), [0x09, b'a', b'b', b'c']);
+    }
+
+    #[test]
+    fn encodes_long_strings() {
+        let s = "a".repeat(127);
+        let bytes = device_sql_string(&s);
+        assert_eq!(bytes[..4], [0x40, 131, 0, 0]);
+        assert_eq!(bytes[4..], *s.as_bytes());
+    }
+
+    #[test]
+    fn encodes_utf16_strings() {
+        assert_eq!(
+            device_sql_string("Café"),
+            [0x90, 12, 0, 0, b'C', 0, b'a', 0, b'f', 0, 0xe9, 0]
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    anlz::{AnlzCue, AnlzFile, CueListType},
+    file_extension, non_empty,
+    pdb::{self, Pdb, TableType, TrackRow},
+    sanitize, DeviceFiles, InteropError,
+};
+use crate::view::model::{playlist::PlaylistNode, track::Track};
+
+//------------------------------------------------------------------//
+//                     Rekordbox Device Export                      //
+//------------------------------------------------------------------//
+
+/// Exports tracks and playlists in the layout, that Pioneer DJ players read from USB drives:
+///
+/// - the audio files in Contents/<artist>/<album>/
+/// - the database in PIONEER/rekordbox/export.pdb
+/// - beatgrids, cues and waveforms in PIONEER/USBANLZ/P000/<track id>/ANLZ0000.DAT and .EXT
+///
+/// Audio files, that already exist on the device with the same size, are not copied again. Tracks,
+/// whose files can not be read, are skipped.
+pub fn export(
+    tracks: &[Arc<Track>],
+    playlists: &[PlaylistNode],
+    dest: &Path,
+) -> Result<(), InteropError> {
+    fs::create_dir_all(dest.join("PIONEER").join("rekordbox"))?;
+    let date = today();
+    let mut pdb = Pdb::new();
+    let mut artists = Interner::default();
+    let mut albums = Interner::default();
+    let mut keys = Interner::default();
+    let mut track_ids = HashMap::new();
+    let mut files = DeviceFiles::default();
+    for track in tracks {
+        let meta = track.meta.read().unwrap().clone();
+        let artist = non_empty(&meta.artist, "UnknownArtist");
+        let album = non_empty(&meta.album, "UnknownAlbum");
+        // copy the audio file
+        let dir = format!("Contents/{}/{}", sanitize(artist), sanitize(album));
+        let file = match files.copy(track, dest, &dir)? {
+            Some(file) => file,
+            None => continue,
+        };
+        let id = track_ids.len() as u32 + 1;
+        let file_path = format!("/{}", file.path);
+        let file_size = file.size;
+        // analysis files
+        let analyze_path = format!("/PIONEER/USBANLZ/P000/{:08X}/ANLZ0000.DAT", id);
+        write_analysis(track, &file_path, &device_path(dest, &analyze_path))?;
+        // database rows
+        let artist_id = artists.id(artist, pdb::artist_row, &mut pdb, TableType::Artists);
+        let album_id = albums.id(
+            album,
+            |id, name| pdb::album_row(id, artist_id, name),
+            &mut pdb,
+            TableType::Albums,
+        );
+        let key_id = meta.key.map_or(0, |key| {
+            keys.id(&key.classical(), pdb::key_row, &mut pdb, TableType::Keys)
+        });
+        let duration = track.duration().unwrap_or(0.);
+        let bitrate = if duration > 0. {
+            (file_size as f64 * 8. / duration / 1000.).round() as u32
+        } else {
+            0
+        };
+        let row = TrackRow {
+            id,
+            title: non_empty(&meta.title, &track.file_name).to_string(),
+            artist_id,
+            album_id,
+            key_id,
+            sample_rate: track.codec_params.sample_rate.unwrap_or(0),
+            file_size: file_size as u32,
+            bitrate,
+            tempo: meta.bpm.map_or(0, |bpm| (bpm.value * 100.).round() as u32),
+            duration: duration.round() as u16,
+            file_type: file_type(&track.file_path),
+            file_path,
+            file_name: file.file_name,
+            analyze_path,
+            date_added: date.clone(),
+        };
+        pdb.add_row(TableType::Tracks, pdb::track_row(&row));
+        track_ids.insert(track.file_path.as_str(), id);
+    }
+    let mut next_playlist_id = 1;
+    for (sort_order, node) in playlists.iter().enumerate() {
+        add_playlist_node(
+            &mut pdb,
+            node,
+            0,
+            sort_order as u32,
+            &mut next_playlist_id,
+            &track_ids,
+        );
+    }
+    fs::write(
+        dest.join("PIONEER").join("rekordbox").join("export.pdb"),
+        pdb.to_bytes(),
+    )?;
+    Ok(())
+}
+
+/// writes ANLZ0000.DAT with the beatgrid, memory and hot cues, memory loops and waveform preview,
+/// and ANLZ0000.EXT with the scrolling waveforms
+fn write_analysis(track: &Track, file_path: &str, dat_path: &Path) -> Result<(), InteropError> {
+    let duration = track.duration().unwrap_or(0.);
+    let preview = track.preview_samples();
+    let mem_cues: Vec<AnlzCue> = track
+        .mem_cues()
+        .iter()
+        .map(|cue| AnlzCue {
+            hot_cue: 0,
+            time: (cue.position * 1000.).round() as u32,
+            loop_time: None,
+        })
+        .chain(track.loops().into_iter().map(|saved_loop| AnlzCue {
+            hot_cue: 0,
+            time: (saved_loop.start * 1000.).round() as u32,
+            loop_time: Some((saved_loop.end * 1000.).round() as u32),
+        }))
+        .collect();
+    let hot_cues: Vec<AnlzCue> = track
+        .hot_cues()
+        .into_iter()
+        .map(|(slot, cue)| AnlzCue {
+            hot_cue: slot as u32 + 1,
+            time: (cue.position * 1000.).round() as u32,
+            loop_time: None,
+        })
+        .collect();
+    let mut dat = AnlzFile::new();
+    dat.add_path(file_path);
+    if let Some(beatgrid) = track.beatgrid() {
+        dat.add_beatgrid(&beatgrid, duration);
+    }
+    dat.add_waveform_preview(&preview);
+    dat.add_cues(CueListType::Hot, &hot_cues);
+    dat.add_cues(CueListType::Memory, &mem_cues);
+    let mut ext = AnlzFile::new();
+    ext.add_path(file_path);
+    ext.add_cues(CueListType::Hot, &hot_cues);
+    ext.add_cues(CueListType::Memory, &mem_cues);
+    ext.add_waveform_detail(&preview, duration);
+    ext.add_color_waveform_detail(&preview, duration);
+    fs::create_dir_all(dat_path.parent().unwrap())?;
+    fs::write(dat_path, dat.to_bytes())?;
+    fs::write(dat_path.with_extension("EXT"), ext.to_bytes())?;
+    Ok(())
+}
+
+/// adds a playlist tree row for the node and rows for its children or entries
+fn add_playlist_node(
+    pdb: &mut Pdb,
+    node: &PlaylistNode,
+    parent_id: u32,
+    sort_order: u32,
+    next_id: &mut u32,
+    track_ids: &HashMap<&str, u32>,
+) {
+    let id = *next_id;
+    *next_id += 1;
+    match node {
+        PlaylistNode::Folder { name, children } => {
+            pdb.add_row(
+                TableType::PlaylistTree,
+                pdb::playlist_tree_row(id, parent_id, sort_order, true, name),
+            );
+            for (sort_order, child) in children.iter().enumerate() {
+                add_playlist_node(pdb, child, id, sort_order as u32, next_id, track_ids);
+            }
+        }
+        PlaylistNode::Playlist { name, tracks } => {
+            pdb.add_row(
+                TableType::PlaylistTree,
+                pdb::playlist_tree_row(id, parent_id, sort_order, false, name),
+            );
+            let entries = tracks
+                .iter()
+                .filter_map(|file_path| track_ids.get(file_path.as_str()));
+            for (i, track_id) in entries.enumerate() {
+                pdb.add_row(
+                    TableType::PlaylistEntries,
+                    pdb::playlist_entry_row(i as u32 + 1, *track_id, id),
+                );
+            }
+        }
+    }
+}
+
+/// Assigns ids to names and adds a row for every new name
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<String, u32>,
+}
+
+impl Interner {
+    fn id(
+        &mut self,
+        name: &str,
+        row: impl Fn(u32, &str) -> Vec<u8>,
+        pdb: &mut Pdb,
+        table: TableType,
+    ) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.ids.len() as u32 + 1;
+        self.ids.insert(name.to_string(), id);
+        pdb.add_row(table, row(id, name));
+        id
+    }
+}
+
+/// the file type ids of the track table
+fn file_type(file_path: &str) -> u16 {
+    match file_extension(file_path).as_str() {
+        "mp3" => 0x01,
+        "m4a" => 0x04,
+        "flac" => 0x05,
+        "wav" => 0x0b,
+        "aiff" | "aif" => 0x0c,
+        _ => 0x00,
+    }
+}
+
+/// converts an absolute path on the device into a path below the export directory
+fn device_path(dest: &Path, path: &str) -> PathBuf {
+    dest.join(path.trim_start_matches('/'))
+}
+
+/// the current date as "YYYY-MM-DD" (UTC)
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    // civil date from days since 1970-01-01, see
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use quick_xml::{
+    events::{BytesDecl, Event},
+    Reader, Writer,
+};
+
+use super::{
+    beatgrid_from_tempos, close_node, file_extension, file_url_to_path, path_to_file_url,
+    xml_attributes, Import, ImportedTrack, InteropError, Tempo,
+};
+use crate::view::model::{
+    beatgrid::{Beatgrid, BEATS_PER_BAR},
+    cue::{CueColor, HotCue, Loop, MemCue, HOT_CUE_SLOTS},
+    key::Key,
+    playlist::PlaylistNode,
+    track::{Bpm, Track},
+};
+
+//------------------------------------------------------------------//
+//                          Rekordbox XML                           //
+//------------------------------------------------------------------//
+
+/// POSITION_MARK types
+const MARK_TYPE_CUE: u8 = 0;
+const MARK_TYPE_LOOP: u8 = 4;
+
+/// NODE types in the PLAYLISTS tree
+const NODE_TYPE_FOLDER: u8 = 0;
+const NODE_TYPE_PLAYLIST: u8 = 1;
+
+/// Exports tracks and playlists as a rekordbox.xml, which can be imported in rekordbox via
+/// "Preferences > Advanced > rekordbox xml". Playlist entries, that are not part of the exported
+/// tracks, are skipped.
+pub fn export(
+    tracks: &[Arc<Track>],
+    playlists: &[PlaylistNode],
+    path: &Path,
+) -> Result<(), InteropError> {
+    let mut writer = Writer::new_with_indent(BufWriter::new(File::create(path)?), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    // track ids are 1-based positions in the collection
+    let track_ids: HashMap<&str, usize> = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| (track.file_path.as_str(), i + 1))
+        .collect();
+    let root = PlaylistNode::folder(String::from("ROOT"), playlists.to_vec());
+    writer
+        .create_element("DJ_PLAYLISTS")
+        .with_attribute(("Version", "1.0.0"))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("PRODUCT")
+                .with_attributes([("Name", "flow"), ("Version", env!("CARGO_PKG_VERSION"))])
+                .write_empty()?;
+            writer
+                .create_element("COLLECTION")
+                .with_attribute(("Entries", tracks.len().to_string().as_str()))
+                .write_inner_content(|writer| {
+                    for (i, track) in tracks.iter().enumerate() {
+                        write_track(writer, i + 1, track)?;
+                    }
+                    Ok(())
+                })?;
+            writer
+                .create_element("PLAYLISTS")
+                .write_inner_content(|writer| write_node(writer, &root, &track_ids))?;
+            Ok(())
+        })?;
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+fn write_track<W: Write>(
+    writer: &mut Writer<W>,
+    track_id: usize,
+    track: &Track,
+) -> quick_xml::Result<()> {
+    let meta = track.meta.read().unwrap().clone();
+    let extension = file_extension(&track.file_path);
+    let mut attributes = vec![
+        ("TrackID", track_id.to_string()),
+        ("Name", meta.title),
+        ("Artist", meta.artist),
+        ("Album", meta.album),
+        ("Kind", format!("{} File", extension.to_uppercase())),
+        ("Location", path_to_file_url(&track.file_path)),
+    ];
+    if let Ok(metadata) = std::fs::metadata(&track.file_path) {
+        attributes.push(("Size", metadata.len().to_string()));
+    }
+    if let Some(duration) = track.duration() {
+        attributes.push(("TotalTime", format!("{}", duration.round() as u64)));
+    }
+    if let Some(sample_rate) = track.codec_params.sample_rate {
+        attributes.push(("SampleRate", sample_rate.to_string()));
+    }
+    if let Some(bpm) = meta.bpm {
+        attributes.push(("AverageBpm", format!("{:.2}", bpm.value)));
+    }
+    if let Some(key) = meta.key {
+        attributes.push(("Tonality", key.classical()));
+    }
+    let mem_cues = track.mem_cues();
+    let hot_cues = track.hot_cues();
+    let loops = track.loops();
+    let beatgrid = track.beatgrid();
+    writer
+        .create_element("TRACK")
+        .with_attributes(attributes.iter().map(|(k, v)| (*k, v.as_str())))
+        .write_inner_content(|writer| {
+            if let Some(beatgrid) = &beatgrid {
+                write_tempos(writer, beatgrid)?;
+            }
+            for cue in &mem_cues {
+                writer
+                    .create_element("POSITION_MARK")
+                    .with_attributes([
+                        ("Name", cue.label.as_str()),
+                        ("Type", MARK_TYPE_CUE.to_string().as_str()),
+                        ("Start", format!("{:.3}", cue.position).as_str()),
+                        // memory cues have no hot cue slot
+                        ("Num", "-1"),
+                        ("Red", cue.color.r.to_string().as_str()),
+                        ("Green", cue.color.g.to_string().as_str()),
+                        ("Blue", cue.color.b.to_string().as_str()),
+                    ])
+                    .write_empty()?;
+            }
+            for (slot, cue) in &hot_cues {
+                writer
+                    .create_element("POSITION_MARK")
+                    .with_attributes([
+                        ("Name", cue.label.as_str()),
+                        ("Type", MARK_TYPE_CUE.to_string().as_str()),
+                        ("Start", format!("{:.3}", cue.position).as_str()),
+                        ("Num", slot.to_string().as_str()),
+                        ("Red", cue.color.r.to_string().as_str()),
+                        ("Green", cue.color.g.to_string().as_str()),
+                        ("Blue", cue.color.b.to_string().as_str()),
+                    ])
+                    .write_empty()?;
+            }
+            for saved_loop in &loops {
+                writer
+                    .create_element("POSITION_MARK")
+                    .with_attributes([
+                        ("Name", ""),
+                        ("Type", MARK_TYPE_LOOP.to_string().as_str()),
+                        ("Start", format!("{:.3}", saved_loop.start).as_str()),
+                        ("End", format!("{:.3}", saved_loop.end).as_str()),
+                        ("Num", "-1"),
+                    ])
+                    .write_empty()?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// writes one TEMPO node for the start of the grid and one for every tempo change. Rekordbox
+/// grids can not start before the track, so the grid starts at the first beat after 0s.
+fn write_tempos<W: Write>(writer: &mut Writer<W>, beatgrid: &Beatgrid) -> quick_xml::Result<()> {
+    let first_beat = beatgrid.beat_index_at(0.).ceil() as i64;
+    let mut tempos = vec![(first_beat, beatgrid.beat_position(first_beat as f64))];
+    tempos.extend(
+        beatgrid
+            .anchors
+            .iter()
+            .filter(|anchor| anchor.beat > first_beat)
+            .map(|anchor| (anchor.beat, anchor.position)),
+    );
+    for (beat, position) in tempos {
+        writer
+            .create_element("TEMPO")
+            .with_attributes([
+                ("Inizio", format!("{:.3}", position).as_str()),
+                ("Bpm", format!("{:.2}", beatgrid.bpm_at(position)).as_str()),
+                ("Metro", format!("{}/4", BEATS_PER_BAR).as_str()),
+                // number of the beat within the bar
+                (
+                    "Battito",
+                    (beat.rem_euclid(BEATS_PER_BAR) + 1).to_string().as_str(),
+                ),
+            ])
+            .write_empty()?;
+    }
+    Ok(())
+}
+
+fn write_node<W: Write>(
+    writer: &mut Writer<W>,
+    node: &PlaylistNode,
+    track_ids: &HashMap<&str, usize>,
+) -> quick_xml::Result<()> {
+    match node {
+        PlaylistNode::Folder { name, children } => {
+            writer
+                .create_element("NODE")
+                .with_attributes([
+                    ("Type", NODE_TYPE_FOLDER.to_string().as_str()),
+                    ("Name", name.as_str()),
+                    ("Count", children.len().to_string().as_str()),
+                ])
+                .write_inner_content(|writer| {
+                    for child in children {
+                        write_node(writer, child, track_ids)?;
+                    }
+                    Ok(())
+                })?;
+        }
+        PlaylistNode::Playlist { name, tracks } => {
+            let ids: Vec<usize> = tracks
+                .iter()
+                .filter_map(|file_path| track_ids.get(file_path.as_str()).copied())
+                .collect();
+            writer
+                .create_element("NODE")
+                .with_attributes([
+                    ("Type", NODE_TYPE_PLAYLIST.to_string().as_str()),
+                    ("Name", name.as_str()),
+                    // entries reference tracks by their TrackID
+                    ("KeyType", "0"),
+                    ("Entries", ids.len().to_string().as_str()),
+                ])
+                .write_inner_content(|writer| {
+                    for id in &ids {
+                        writer
+                            .create_element("TRACK")
+                            .with_attribute(("Key", id.to_string().as_str()))
+                            .write_empty()?;
+                    }
+                    Ok(())
+                })?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a rekordbox.xml, as exported via "File > Export Collection in xml format". Tracks, that
+/// are not local files, are skipped.
+pub fn import(path: &Path) -> Result<Import, InteropError> {
+    let mut reader = Reader::from_file(path)?;
+    reader.trim_text(true);
+    let mut buf = vec![];
+    let mut import = Import::default();
+    // TrackID -> file path, for resolving playlist entries
+    let mut track_paths: HashMap<String, String> = HashMap::new();
+    let mut in_collection = false;
+    // the collection track, that is currently read: (TrackID, track, TEMPO nodes)
+    let mut current: Option<(String, ImportedTrack, Vec<Tempo>)> = None;
+    // folders and playlists, that are currently open, the innermost last
+    let mut nodes: Vec<PlaylistNode> = vec![];
+    // whether the entries of the open playlist reference the TrackID ("0") or the Location ("1")
+    // of a track. Playlists have no child nodes, so the KeyType of the latest node applies.
+    let mut key_type = String::new();
+    loop {
+        let (element, is_empty) = match reader.read_event(&mut buf)? {
+            Event::Start(element) => (element.into_owned(), false),
+            Event::Empty(element) => (element.into_owned(), true),
+            Event::End(element) => {
+                match element.name() {
+                    b"COLLECTION" => in_collection = false,
+                    b"TRACK" => {
+                        if let Some(track) = current.take() {
+                            finish_track(track, &mut track_paths, &mut import);
+                        }
+                    }
+                    b"NODE" => close_node(&mut nodes, &mut import),
+                    _ => {}
+                }
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        let attributes = xml_attributes(&element, &reader)?;
+        let attribute = |key: &str| attributes.get(key).cloned().unwrap_or_default();
+        let number = |key: &str| {
+            attributes
+                .get(key)
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+        match element.name() {
+            b"COLLECTION" => in_collection = !is_empty,
+            b"TRACK" if in_collection => {
+                if let Some(file_path) = file_url_to_path(&attribute("Location")) {
+                    let mut track = ImportedTrack::new(file_path);
+                    track.size = number("Size").map(|size| size as u64);
+                    track.meta.title = attribute("Name");
+                    track.meta.artist = attribute("Artist");
+                    track.meta.album = attribute("Album");
+                    track.meta.bpm = number("AverageBpm")
+                        .filter(|bpm| *bpm > 0.)
+                        .map(|bpm| Bpm::new(bpm, 1.));
+                    track.meta.key = Key::parse(&attribute("Tonality"));
+                    current = Some((attribute("TrackID"), track, vec![]));
+                    if is_empty {
+                        finish_track(current.take().unwrap(), &mut track_paths, &mut import);
+                    }
+                }
+            }
+            // a playlist entry
+            b"TRACK" => {
+                if let Some(PlaylistNode::Playlist { tracks, .. }) = nodes.last_mut() {
+                    let key = attribute("Key");
+                    let file_path = match key_type.as_str() {
+                        "1" => file_url_to_path(&key),
+                        _ => track_paths.get(&key).cloned(),
+                    };
+                    tracks.extend(file_path);
+                }
+            }
+            b"TEMPO" => {
+                if let (Some((_, _, tempos)), Some(position), Some(bpm)) =
+                    (&mut current, number("Inizio"), number("Bpm"))
+                {
+                    let beat_in_bar = number("Battito").unwrap_or(1.) as i64;
+                    tempos.push((position, bpm, beat_in_bar));
+                }
+            }
+            b"POSITION_MARK" => {
+                let mark_type = attribute("Type").parse::<u8>().ok();
+                let color = match (number("Red"), number("Green"), number("Blue")) {
+                    (Some(r), Some(g), Some(b)) => Some(CueColor::new(r as u8, g as u8, b as u8)),
+                    _ => None,
+                };
+                if let (Some((_, track, _)), Some(start)) = (&mut current, number("Start")) {
+                    match (mark_type, number("End")) {
+                        // memory and hot loops are both imported as saved loops
+                        (Some(MARK_TYPE_LOOP), Some(end)) if end > start => {
+                            track.loops.push(Loop::new(start, end))
+                        }
+                        // memory cues have the number -1, hot cues their slot
+                        (Some(MARK_TYPE_CUE), _) => match number("Num").filter(|num| *num >= 0.) {
+                            Some(num) if (num as usize) < HOT_CUE_SLOTS => {
+                                let slot = num as usize;
+                                let mut cue = HotCue::new(slot, start);
+                                cue.label = attribute("Name");
+                                if let Some(color) = color {
+                                    cue.color = color;
+                                }
+                                track.hot_cues.push((slot, cue));
+                            }
+                            Some(_) => {}
+                            None => {
+                                let mut cue = MemCue::new(start);
+                                cue.label = attribute("Name");
+                                if let Some(color) = color {
+                                    cue.color = color;
+                                }
+                                track.mem_cues.push(cue);
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+            }
+            b"NODE" => {
+                key_type = attribute("KeyType");
+                let name = attribute("Name");
+                let node = if attribute("Type").parse::<u8>().ok() == Some(NODE_TYPE_PLAYLIST) {
+                    PlaylistNode::playlist(name, vec![])
+                } else {
+                    PlaylistNode::folder(name, vec![])
+                };
+                nodes.push(node);
+                if is_empty {
+                    close_node(&mut nodes, &mut import);
+                }
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(import)
+}
+
+fn finish_track(
+    (track_id, mut track, tempos): (String, ImportedTrack, Vec<Tempo>),
+    track_paths: &mut HashMap<String, String>,
+    import: &mut Import,
+) {
+    track.beatgrid = beatgrid_from_tempos(tempos);
+    track_paths.insert(track_id, track.file_path.clone());
+    import.tracks.push(track);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::codecs::CodecParameters;
+
+    fn track(file_path: &str) -> Arc<Track> {
+        Arc::new(Track::new(String::from(file_path), CodecParameters::new()))
+    }
+
+    #[test]
+    fn round_trips_grid_cues_and_playlists() {
+        let a = track("/music/a.mp3");
+        {
+            let mut meta = a.meta.write().unwrap();
+            meta.title = String::from("A");
+            meta.bpm = Some(Bpm::new(120., 1.));
+            meta.key = Key::parse("Am");
+        }
+        // the grid starts before the track, its first beat in the track is the second of a bar
+        let mut beatgrid = Beatgrid::new(-0.2, 120.);
+        beatgrid.add_anchor(33, 125.);
+        a.set_beatgrid(beatgrid);
+        let mut mem_cue = MemCue::new(1.5);
+        mem_cue.label = String::from("Intro");
+        a.add_mem_cue(mem_cue.clone());
+        let mut hot_cue = HotCue::new(2, 8.3);
+        hot_cue.label = String::from("Drop");
+        hot_cue.color = CueColor::new(1, 2, 3);
+        a.set_hot_cue(2, hot_cue.clone());
+        a.add_loop(Loop::new(16.3, 24.3));
+        let b = track("/music/b b/é.flac");
+        let playlists = vec![
+            PlaylistNode::folder(
+                String::from("Sets"),
+                vec![PlaylistNode::playlist(
+                    String::from("Friday"),
+                    vec![
+                        String::from("/music/b b/é.flac"),
+                        String::from("/music/a.mp3"),
+                        String::from("/music/missing.mp3"),
+                    ],
+                )],
+            ),
+            PlaylistNode::playlist(String::from("Empty"), vec![]),
+        ];
+        let path = std::env::temp_dir().join(format!("flow-{}-rekordbox.xml", std::process::id()));
+        export(&[a, b], &playlists, &path).unwrap();
+        let xml = std::fs::read_to_string(&path).unwrap();
+        let import = import(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(xml.contains(r#"<TEMPO Inizio="0.300" Bpm="120.00" Metro="4/4" Battito="2"/>"#));
+        assert!(xml.contains(r#"<TEMPO Inizio="16.300" Bpm="125.00" Metro="4/4" Battito="2"/>"#));
+        assert!(xml.contains(r#"Type="0" Start="1.500" Num="-1""#));
+        assert!(xml.contains(r#"Type="0" Start="8.300" Num="2""#));
+        assert!(xml.contains(r#"Type="4" Start="16.300" End="24.300" Num="-1""#));
+
+        assert_eq!(import.tracks.len(), 2);
+        let a = &import.tracks[0];
+        assert_eq!(a.file_path, "/music/a.mp3");
+        assert_eq!(a.meta.title, "A");
+        assert_eq!(a.meta.bpm, Some(Bpm::new(120., 1.)));
+        assert_eq!(a.meta.key, Some(Key::parse("Am").unwrap()));
+        let beatgrid = a.beatgrid.as_ref().unwrap();
+        assert!((beatgrid.first_downbeat + 0.2).abs() < 1e-9);
+        assert_eq!(beatgrid.bpm, 120.);
+        assert_eq!(beatgrid.anchors.len(), 1);
+        assert_eq!(beatgrid.anchors[0].beat, 33);
+        assert_eq!(beatgrid.anchors[0].bpm, 125.);
+        assert_eq!(a.mem_cues, vec![mem_cue]);
+        assert_eq!(a.hot_cues, vec![(2, hot_cue)]);
+        assert_eq!(a.loops, vec![Loop::new(16.3, 24.3)]);
+        let b = &import.tracks[1];
+        assert_eq!(b.file_path, "/music/b b/é.flac");
+        assert!(b.beatgrid.is_none());
+        assert!(b.mem_cues.is_empty() && b.hot_cues.is_empty() && b.loops.is_empty());
+
+        // entries of tracks, that were not exported, are dropped
+        let mut expected = playlists;
+        if let PlaylistNode::Folder { children, .. } = &mut expected[0] {
+            if let PlaylistNode::Playlist { tracks, .. } = &mut children[0] {
+                tracks.pop();
+            }
+        }
+        assert_eq!(import.playlists, expected);
+    }
+}
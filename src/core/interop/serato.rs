@@ -0,0 +1,443 @@
+use std::path::Path;
+
+use id3::{frame::EncapsulatedObject, Tag, TagLike, Version};
+
+use super::{anlz::resample, file_extension, ImportedTrack, InteropError};
+use crate::{
+    core::analyzer::PreviewSample,
+    view::model::{
+        beatgrid::Beatgrid,
+        cue::{CueColor, HotCue, Loop, HOT_CUE_SLOTS},
+        track::{Bpm, Track},
+    },
+};
+
+//------------------------------------------------------------------//
+//                            Serato Tags                           //
+//------------------------------------------------------------------//
+
+/// descriptions of the GEOB frames, that Serato DJ stores its analysis in
+const MARKERS2: &str = "Serato Markers2";
+const BEATGRID: &str = "Serato BeatGrid";
+const OVERVIEW: &str = "Serato Overview";
+/// number of columns and frequency bins of the overview
+const OVERVIEW_COLUMNS: usize = 240;
+const OVERVIEW_BINS: usize = 16;
+/// Serato pads the Markers2 frame with zeros to at least this size
+const MARKERS2_MIN_SIZE: usize = 470;
+/// number of loop slots in Serato
+const LOOP_SLOTS: usize = 8;
+/// the default color of loops, Serato stores it as argb
+const LOOP_COLOR: [u8; 4] = [0x00, 0x27, 0xaa, 0xe1];
+
+/// Reads the Serato Markers2 and BeatGrid frames from the ID3 tag of an audio file. Returns None,
+/// if the file has no tag or no Serato data. Cues become hot cues, loops saved loops and the tempo
+/// of the grid becomes the bpm. The overview is not imported, because the analyzer computes a
+/// finer preview.
+pub fn read(file_path: &str) -> Result<Option<ImportedTrack>, InteropError> {
+    if !has_id3(file_path) {
+        return Ok(None);
+    }
+    let tag = match id3::no_tag_ok(Tag::read_from_path(file_path))? {
+        Some(tag) => tag,
+        None => return Ok(None),
+    };
+    let mut imported = ImportedTrack::new(file_path.to_string());
+    let mut found = false;
+    for object in tag.encapsulated_objects() {
+        match object.description.as_str() {
+            MARKERS2 => {
+                let entries = decode_markers2(&object.data)?;
+                imported.hot_cues = entries
+                    .iter()
+                    .filter_map(|(name, data)| parse_cue(name, data))
+                    .collect();
+                imported.loops = entries
+                    .iter()
+                    .filter_map(|(name, data)| parse_loop(name, data))
+                    .collect();
+                found = true;
+            }
+            BEATGRID => {
+                if let Some(beatgrid) = parse_beatgrid(&object.data)? {
+                    imported.meta.bpm = Some(Bpm::new(beatgrid.bpm, 1.));
+                    imported.beatgrid = Some(beatgrid);
+                }
+                found = true;
+            }
+            _ => {}
+        }
+    }
+    Ok(if found { Some(imported) } else { None })
+}
+
+/// Writes hot cues, loops, beatgrid and overview of a track into Serato's GEOB frames of the audio
+/// file. Entries of the Markers2 frame, that flow does not know (track color, ...), are kept.
+/// Serato has no memory cues, so they are not written, and only the first eight loops fit.
+pub fn write(track: &Track) -> Result<(), InteropError> {
+    let mut tag = match id3::no_tag_ok(Tag::read_from_path(&track.file_path))? {
+        Some(tag) => tag,
+        None => Tag::new(),
+    };
+    let version = match tag.version() {
+        Version::Id3v22 => Version::Id3v23,
+        version => version,
+    };
+    // markers
+    let mut entries: Vec<(String, Vec<u8>)> = match tag
+        .encapsulated_objects()
+        .find(|object| object.description == MARKERS2)
+    {
+        Some(object) => decode_markers2(&object.data)?,
+        None => vec![],
+    };
+    entries.retain(|(name, _)| name != "CUE" && name != "LOOP");
+    for (slot, cue) in track.hot_cues() {
+        entries.push((String::from("CUE"), cue_entry(slot as u8, &cue)));
+    }
+    for (slot, saved_loop) in track.loops().iter().take(LOOP_SLOTS).enumerate() {
+        entries.push((String::from("LOOP"), loop_entry(slot as u8, saved_loop)));
+    }
+    set_object(&mut tag, MARKERS2, encode_markers2(&entries));
+    // beatgrid
+    tag.remove_encapsulated_object(Some(BEATGRID), None, None, None);
+    if let Some(beatgrid) = track.beatgrid() {
+        set_object(&mut tag, BEATGRID, beatgrid_data(&beatgrid));
+    }
+    // overview
+    let preview = track.preview_samples();
+    if !preview.is_empty() {
+        set_object(&mut tag, OVERVIEW, overview_data(&preview));
+    }
+    write_tag(&tag, &track.file_path, version)
+}
+
+//------------------------------------------------------------------//
+//                             Markers2                             //
+//------------------------------------------------------------------//
+
+/// Markers2 is a base64 encoded list of named entries behind a two byte version. The base64 text
+/// has a line break every 72 characters and no padding.
+fn decode_markers2(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, InteropError> {
+    let format_error = || InteropError::Format(String::from("invalid Serato Markers2"));
+    if data.len() < 2 || data[..2] != [0x01, 0x01] {
+        return Err(format_error());
+    }
+    let end = data[2..]
+        .iter()
+        .position(|b| *b == 0)
+        .map_or(data.len(), |i| i + 2);
+    let mut text: Vec<u8> = data[2..end]
+        .iter()
+        .copied()
+        .filter(|b| *b != b'\n')
+        .collect();
+    // Serato cuts off the padding
+    match text.len() % 4 {
+        1 => text.extend(b"A=="),
+        2 => text.extend(b"=="),
+        3 => text.push(b'='),
+        _ => {}
+    }
+    let payload = base64::decode(&text).map_err(|_| format_error())?;
+    if payload.len() < 2 || payload[..2] != [0x01, 0x01] {
+        return Err(format_error());
+    }
+    // every entry is a null terminated name, the length of the data and the data
+    let mut entries = vec![];
+    let mut rest = &payload[2..];
+    loop {
+        let name_len = match rest.iter().position(|b| *b == 0) {
+            Some(0) | None => break,
+            Some(name_len) => name_len,
+        };
+        let name = String::from_utf8_lossy(&rest[..name_len]).into_owned();
+        rest = &rest[name_len + 1..];
+        if rest.len() < 4 {
+            return Err(format_error());
+        }
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        rest = &rest[4..];
+        if rest.len() < len {
+            return Err(format_error());
+        }
+        entries.push((name, rest[..len].to_vec()));
+        rest = &rest[len..];
+    }
+    Ok(entries)
+}
+
+fn encode_markers2(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut payload = vec![0x01, 0x01];
+    for (name, data) in entries {
+        payload.extend(name.as_bytes());
+        payload.push(0);
+        payload.extend((data.len() as u32).to_be_bytes());
+        payload.extend(data);
+    }
+    payload.push(0);
+    let text = base64::encode_config(&payload, base64::STANDARD_NO_PAD);
+    let mut data = vec![0x01, 0x01];
+    for (i, line) in text.as_bytes().chunks(72).enumerate() {
+        if i > 0 {
+            data.push(b'\n');
+        }
+        data.extend(line);
+    }
+    data.push(0);
+    if data.len() < MARKERS2_MIN_SIZE {
+        data.resize(MARKERS2_MIN_SIZE, 0);
+    }
+    data
+}
+
+/// the hot cue of a CUE entry: a zero byte, the slot, the position in milliseconds, a zero byte,
+/// the rgb color, two zero bytes and the null terminated label
+fn parse_cue(name: &str, data: &[u8]) -> Option<(usize, HotCue)> {
+    if name != "CUE" || data.len() < 12 {
+        return None;
+    }
+    let slot = data[1] as usize;
+    if slot >= HOT_CUE_SLOTS {
+        return None;
+    }
+    let millis = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+    let label = data[12..].split(|b| *b == 0).next().unwrap_or_default();
+    Some((
+        slot,
+        HotCue {
+            position: millis as f64 / 1000.,
+            label: String::from_utf8_lossy(label).into_owned(),
+            color: CueColor::new(data[7], data[8], data[9]),
+        },
+    ))
+}
+
+fn cue_entry(slot: u8, cue: &HotCue) -> Vec<u8> {
+    let mut data = vec![0, slot];
+    data.extend(((cue.position * 1000.).round() as u32).to_be_bytes());
+    data.push(0);
+    data.extend([cue.color.r, cue.color.g, cue.color.b]);
+    data.extend([0, 0]);
+    data.extend(cue.label.as_bytes());
+    data.push(0);
+    data
+}
+
+/// the saved loop of a LOOP entry: a zero byte, the slot, start and end in milliseconds, four
+/// 0xff bytes, the argb color, a zero byte, the locked flag and the null terminated label
+fn parse_loop(name: &str, data: &[u8]) -> Option<Loop> {
+    if name != "LOOP" || data.len() < 20 {
+        return None;
+    }
+    let start = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+    let end = u32::from_be_bytes([data[6], data[7], data[8], data[9]]);
+    if end <= start {
+        return None;
+    }
+    Some(Loop::new(start as f64 / 1000., end as f64 / 1000.))
+}
+
+fn loop_entry(slot: u8, saved_loop: &Loop) -> Vec<u8> {
+    let mut data = vec![0, slot];
+    data.extend(((saved_loop.start * 1000.).round() as u32).to_be_bytes());
+    data.extend(((saved_loop.end * 1000.).round() as u32).to_be_bytes());
+    data.extend([0xff; 4]);
+    data.extend(LOOP_COLOR);
+    // not locked, no label
+    data.extend([0, 0]);
+    data.push(0);
+    data
+}
+
+//------------------------------------------------------------------//
+//                             BeatGrid                             //
+//------------------------------------------------------------------//
+
+/// BeatGrid starts with a two byte version and the number of markers. Every marker but the last
+/// has its position in seconds and the number of beats until the next marker, the last one has
+/// its position and the tempo.
+fn parse_beatgrid(data: &[u8]) -> Result<Option<Beatgrid>, InteropError> {
+    let format_error = || InteropError::Format(String::from("invalid Serato BeatGrid"));
+    if data.len() < 6 || data[..2] != [0x01, 0x00] {
+        return Err(format_error());
+    }
+    let count = u32::from_be_bytes([data[2], data[3], data[4], data[5]]) as usize;
+    if count == 0 {
+        return Ok(None);
+    }
+    if data.len() < 6 + count * 8 {
+        return Err(format_error());
+    }
+    let markers: Vec<(f64, [u8; 4])> = data[6..6 + count * 8]
+        .chunks(8)
+        .map(|marker| {
+            let position = f32::from_be_bytes([marker[0], marker[1], marker[2], marker[3]]);
+            (
+                position as f64,
+                [marker[4], marker[5], marker[6], marker[7]],
+            )
+        })
+        .collect();
+    let (last_position, last_bpm) = markers[count - 1];
+    let last_bpm = f32::from_be_bytes(last_bpm) as f64;
+    // the tempo of a segment follows from the distance to the next marker
+    let mut segments = vec![];
+    let mut beat = 0;
+    for (i, (position, beats_until_next)) in markers[..count - 1].iter().enumerate() {
+        let beats_until_next = u32::from_be_bytes(*beats_until_next) as i64;
+        let next_position = markers[i + 1].0;
+        if beats_until_next == 0 || next_position <= *position {
+            return Err(format_error());
+        }
+        let bpm = beats_until_next as f64 * 60. / (next_position - position);
+        segments.push((beat, *position, bpm));
+        beat += beats_until_next;
+    }
+    segments.push((beat, last_position, last_bpm));
+    let (_, first_downbeat, bpm) = segments[0];
+    let mut beatgrid = Beatgrid::new(first_downbeat, bpm);
+    for (beat, _, bpm) in segments.into_iter().skip(1) {
+        beatgrid.add_anchor(beat, bpm);
+    }
+    Ok(Some(beatgrid))
+}
+
+fn beatgrid_data(beatgrid: &Beatgrid) -> Vec<u8> {
+    let mut segments = vec![(0, beatgrid.first_downbeat, beatgrid.bpm)];
+    segments.extend(
+        beatgrid
+            .anchors
+            .iter()
+            .map(|anchor| (anchor.beat, anchor.position, anchor.bpm)),
+    );
+    let mut data = vec![0x01, 0x00];
+    data.extend((segments.len() as u32).to_be_bytes());
+    for (i, (beat, position, bpm)) in segments.iter().enumerate() {
+        data.extend((*position as f32).to_be_bytes());
+        match segments.get(i + 1) {
+            Some((next_beat, _, _)) => data.extend(((next_beat - beat) as u32).to_be_bytes()),
+            None => data.extend((*bpm as f32).to_be_bytes()),
+        }
+    }
+    // footer
+    data.push(0);
+    data
+}
+
+//------------------------------------------------------------------//
+//                             Overview                             //
+//------------------------------------------------------------------//
+
+/// Overview has a two byte version and 240 columns of 16 frequency bins from low to high. The
+/// lows fill the lower bins, mids the middle and highs the upper ones.
+fn overview_data(preview: &[PreviewSample]) -> Vec<u8> {
+    let mut data = vec![0x01, 0x05];
+    for column in resample(preview, OVERVIEW_COLUMNS) {
+        for bin in 0..OVERVIEW_BINS {
+            let band = match bin * 3 / OVERVIEW_BINS {
+                0 => column.lows,
+                1 => column.mids,
+                _ => column.highs,
+            };
+            data.push((band.min(1.) * 255.).round() as u8);
+        }
+    }
+    data
+}
+
+//------------------------------------------------------------------//
+//                             Helpers                              //
+//------------------------------------------------------------------//
+
+/// replaces the GEOB frame with the given description
+fn set_object(tag: &mut Tag, description: &str, data: Vec<u8>) {
+    tag.remove_encapsulated_object(Some(description), None, None, None);
+    tag.add_frame(EncapsulatedObject {
+        mime_type: String::from("application/octet-stream"),
+        filename: String::new(),
+        description: description.to_string(),
+        data,
+    });
+}
+
+/// whether the file type can hold an ID3 tag. AIFF and WAV files keep it in a chunk.
+fn has_id3(file_path: &str) -> bool {
+    matches!(
+        file_extension(file_path).as_str(),
+        "mp3" | "aiff" | "aif" | "wav"
+    )
+}
+
+fn write_tag(tag: &Tag, file_path: &str, version: Version) -> Result<(), InteropError> {
+    if !has_id3(file_path) {
+        return Err(InteropError::Format(format!(
+            "can not write Serato tags to {} files",
+            file_extension(file_path)
+        )));
+    }
+    tag.write_to_path(Path::new(file_path), version)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_markers2() {
+        let cue = HotCue {
+            position: 12.345,
+            label: String::from("Drop"),
+            color: CueColor::new(0xcc, 0x00, 0x00),
+        };
+        let saved_loop = Loop::new(1.5, 3.);
+        let entries = vec![
+            (String::from("COLOR"), vec![0, 0xff, 0xff, 0xff]),
+            (String::from("CUE"), cue_entry(3, &cue)),
+            (String::from("LOOP"), loop_entry(0, &saved_loop)),
+        ];
+        let data = encode_markers2(&entries);
+        assert_eq!(data.len(), MARKERS2_MIN_SIZE);
+        // longer payloads are split into lines of 72 characters
+        assert_eq!(data[2 + 72], b'\n');
+        let decoded = decode_markers2(&data).unwrap();
+        assert_eq!(decoded, entries);
+        assert_eq!(parse_cue(&decoded[1].0, &decoded[1].1), Some((3, cue)));
+        assert_eq!(parse_loop(&decoded[2].0, &decoded[2].1), Some(saved_loop));
+    }
+
+    #[test]
+    fn round_trips_markers2_without_padding() {
+        // the names shift the length of the payload through all remainders of the base64 blocks
+        for name in ["A", "AB", "ABC", "ABCD"] {
+            let entries = vec![(String::from(name), vec![1, 2, 3])];
+            assert_eq!(
+                decode_markers2(&encode_markers2(&entries)).unwrap(),
+                entries
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_beatgrids() {
+        let mut beatgrid = Beatgrid::new(0.5, 128.);
+        assert_eq!(
+            parse_beatgrid(&beatgrid_data(&beatgrid)).unwrap(),
+            Some(beatgrid.clone())
+        );
+        // positions and tempos, that are exact as 32 bit floats
+        beatgrid.add_anchor(64, 120.);
+        beatgrid.add_anchor(128, 140.);
+        assert_eq!(
+            parse_beatgrid(&beatgrid_data(&beatgrid)).unwrap(),
+            Some(beatgrid)
+        );
+    }
+
+    #[test]
+    fn parses_empty_beatgrids() {
+        assert_eq!(parse_beatgrid(&[0x01, 0x00, 0, 0, 0, 0, 0]).unwrap(), None);
+        assert!(parse_beatgrid(&[0x01, 0x00, 0, 0, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,703 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use quick_xml::{
+    events::{BytesDecl, Event},
+    ElementWriter, Reader, Writer,
+};
+
+use super::{
+    beatgrid_from_tempos, close_node, xml_attributes, Import, ImportedTrack, InteropError, Tempo,
+};
+use crate::view::model::{
+    beatgrid::{Beatgrid, BEATS_PER_BAR},
+    cue::{HotCue, Loop, MemCue, HOT_CUE_SLOTS},
+    key::{Key, Mode},
+    playlist::PlaylistNode,
+    track::{Bpm, Track},
+};
+
+//------------------------------------------------------------------//
+//                            Traktor NML                           //
+//------------------------------------------------------------------//
+
+/// version of the NML format, that Traktor Pro 3 writes
+const NML_VERSION: &str = "19";
+/// CUE_V2 types
+const CUE_TYPE_CUE: u8 = 0;
+const CUE_TYPE_GRID: u8 = 4;
+const CUE_TYPE_LOOP: u8 = 5;
+/// the NAME of cues without a label
+const NO_NAME: &str = "n.n.";
+/// name of the root folder of the PLAYLISTS tree
+const ROOT_NODE: &str = "$ROOT";
+
+/// Exports tracks and playlists as a collection.nml, which Traktor imports via "Import Collection"
+/// or opens in place of its own collection. Playlist entries, that are not part of the exported
+/// tracks, are skipped.
+pub fn export(
+    tracks: &[Arc<Track>],
+    playlists: &[PlaylistNode],
+    path: &Path,
+) -> Result<(), InteropError> {
+    let mut writer = Writer::new_with_indent(BufWriter::new(File::create(path)?), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(
+        b"1.0",
+        Some(b"UTF-8"),
+        Some(b"no"),
+    )))?;
+    // playlist entries reference tracks by their primary key
+    let primary_keys: HashMap<&str, String> = tracks
+        .iter()
+        .map(|track| (track.file_path.as_str(), primary_key(&track.file_path)))
+        .collect();
+    let root = PlaylistNode::folder(String::from(ROOT_NODE), playlists.to_vec());
+    writer
+        .create_element("NML")
+        .with_attribute(("VERSION", NML_VERSION))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("HEAD")
+                .with_attributes([
+                    ("COMPANY", "www.native-instruments.com"),
+                    ("PROGRAM", "Traktor"),
+                ])
+                .write_empty()?;
+            writer.create_element("MUSICFOLDERS").write_empty()?;
+            writer
+                .create_element("COLLECTION")
+                .with_attribute(("ENTRIES", tracks.len().to_string().as_str()))
+                .write_inner_content(|writer| {
+                    for track in tracks {
+                        write_entry(writer, track)?;
+                    }
+                    Ok(())
+                })?;
+            writer
+                .create_element("SETS")
+                .with_attribute(("ENTRIES", "0"))
+                .write_empty()?;
+            writer
+                .create_element("PLAYLISTS")
+                .write_inner_content(|writer| write_node(writer, &root, &primary_keys))?;
+            Ok(())
+        })?;
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+fn write_entry<W: Write>(writer: &mut Writer<W>, track: &Track) -> quick_xml::Result<()> {
+    let meta = track.meta.read().unwrap().clone();
+    let (volume, dir, file) = location(&track.file_path);
+    let mut info = vec![];
+    let file_size = std::fs::metadata(&track.file_path).map(|m| m.len()).ok();
+    if let Some(duration) = track.duration() {
+        info.push(("PLAYTIME", format!("{}", duration.round() as u64)));
+        info.push(("PLAYTIME_FLOAT", format!("{:.6}", duration)));
+        if let Some(file_size) = file_size.filter(|_| duration > 0.) {
+            info.push((
+                "BITRATE",
+                format!("{}", (file_size as f64 * 8. / duration).round() as u64),
+            ));
+        }
+    }
+    if let Some(file_size) = file_size {
+        // in kilobytes
+        info.push(("FILESIZE", format!("{}", file_size / 1024)));
+    }
+    if let Some(key) = meta.key {
+        info.push(("KEY", key.open_key()));
+    }
+    let mem_cues = track.mem_cues();
+    let hot_cues = track.hot_cues();
+    let loops = track.loops();
+    let beatgrid = track.beatgrid();
+    writer
+        .create_element("ENTRY")
+        .with_attributes([
+            ("TITLE", meta.title.as_str()),
+            ("ARTIST", meta.artist.as_str()),
+        ])
+        .write_inner_content(|writer| {
+            writer
+                .create_element("LOCATION")
+                .with_attributes([
+                    ("DIR", dir.as_str()),
+                    ("FILE", file.as_str()),
+                    ("VOLUME", volume.as_str()),
+                ])
+                .write_empty()?;
+            writer
+                .create_element("ALBUM")
+                .with_attribute(("TITLE", meta.album.as_str()))
+                .write_empty()?;
+            writer
+                .create_element("INFO")
+                .with_attributes(info.iter().map(|(k, v)| (*k, v.as_str())))
+                .write_empty()?;
+            if let Some(bpm) = meta.bpm {
+                writer
+                    .create_element("TEMPO")
+                    .with_attributes([
+                        ("BPM", format!("{:.6}", bpm.value).as_str()),
+                        (
+                            "BPM_QUALITY",
+                            format!("{:.6}", bpm.confidence * 100.).as_str(),
+                        ),
+                    ])
+                    .write_empty()?;
+            }
+            if let Some(key) = meta.key {
+                writer
+                    .create_element("MUSICAL_KEY")
+                    .with_attribute(("VALUE", musical_key_value(key).to_string().as_str()))
+                    .write_empty()?;
+            }
+            if let Some(beatgrid) = &beatgrid {
+                write_grid_markers(writer, beatgrid)?;
+            }
+            for cue in &mem_cues {
+                write_cue(writer, CUE_TYPE_CUE, cue.position, 0., &cue.label, None)
+                    .write_empty()?;
+            }
+            for (slot, cue) in &hot_cues {
+                write_cue(
+                    writer,
+                    CUE_TYPE_CUE,
+                    cue.position,
+                    0.,
+                    &cue.label,
+                    Some(*slot),
+                )
+                .write_empty()?;
+            }
+            for saved_loop in &loops {
+                let length = saved_loop.length();
+                write_cue(writer, CUE_TYPE_LOOP, saved_loop.start, length, "", None)
+                    .write_empty()?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// writes a grid marker at the first downbeat after 0s and one for every tempo change. Traktor
+/// treats every grid marker as the first beat of a bar.
+fn write_grid_markers<W: Write>(
+    writer: &mut Writer<W>,
+    beatgrid: &Beatgrid,
+) -> quick_xml::Result<()> {
+    let first_beat = beatgrid.beat_index_at(0.).ceil() as i64;
+    let first_downbeat =
+        first_beat + (BEATS_PER_BAR - first_beat.rem_euclid(BEATS_PER_BAR)) % BEATS_PER_BAR;
+    let mut markers = vec![beatgrid.beat_position(first_downbeat as f64)];
+    markers.extend(
+        beatgrid
+            .anchors
+            .iter()
+            .filter(|anchor| anchor.beat > first_downbeat)
+            .map(|anchor| anchor.position),
+    );
+    for position in markers {
+        let bpm = beatgrid.bpm_at(position);
+        write_cue(writer, CUE_TYPE_GRID, position, 0., "AutoGrid", None).write_inner_content(
+            |writer| {
+                writer
+                    .create_element("GRID")
+                    .with_attribute(("BPM", format!("{:.6}", bpm).as_str()))
+                    .write_empty()?;
+                Ok(())
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// a CUE_V2 element, position and length are in milliseconds. Cues without a hot cue slot have
+/// the HOTCUE -1.
+fn write_cue<'a, W: Write>(
+    writer: &'a mut Writer<W>,
+    cue_type: u8,
+    position: f64,
+    length: f64,
+    name: &str,
+    slot: Option<usize>,
+) -> ElementWriter<'a, W> {
+    let name = if name.is_empty() { NO_NAME } else { name };
+    writer.create_element("CUE_V2").with_attributes([
+        ("NAME", name),
+        ("DISPL_ORDER", "0"),
+        ("TYPE", cue_type.to_string().as_str()),
+        ("START", format!("{:.6}", position * 1000.).as_str()),
+        ("LEN", format!("{:.6}", length * 1000.).as_str()),
+        ("REPEATS", "-1"),
+        (
+            "HOTCUE",
+            slot.map_or(-1, |slot| slot as i64).to_string().as_str(),
+        ),
+    ])
+}
+
+fn write_node<W: Write>(
+    writer: &mut Writer<W>,
+    node: &PlaylistNode,
+    primary_keys: &HashMap<&str, String>,
+) -> quick_xml::Result<()> {
+    match node {
+        PlaylistNode::Folder { name, children } => {
+            writer
+                .create_element("NODE")
+                .with_attributes([("TYPE", "FOLDER"), ("NAME", name.as_str())])
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("SUBNODES")
+                        .with_attribute(("COUNT", children.len().to_string().as_str()))
+                        .write_inner_content(|writer| {
+                            for child in children {
+                                write_node(writer, child, primary_keys)?;
+                            }
+                            Ok(())
+                        })?;
+                    Ok(())
+                })?;
+        }
+        PlaylistNode::Playlist { name, tracks } => {
+            let keys: Vec<&String> = tracks
+                .iter()
+                .filter_map(|file_path| primary_keys.get(file_path.as_str()))
+                .collect();
+            writer
+                .create_element("NODE")
+                .with_attributes([("TYPE", "PLAYLIST"), ("NAME", name.as_str())])
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("PLAYLIST")
+                        .with_attributes([
+                            ("ENTRIES", keys.len().to_string().as_str()),
+                            ("TYPE", "LIST"),
+                        ])
+                        .write_inner_content(|writer| {
+                            for key in &keys {
+                                writer
+                                    .create_element("ENTRY")
+                                    .write_inner_content(|writer| {
+                                        writer
+                                            .create_element("PRIMARYKEY")
+                                            .with_attributes([
+                                                ("TYPE", "TRACK"),
+                                                ("KEY", key.as_str()),
+                                            ])
+                                            .write_empty()?;
+                                        Ok(())
+                                    })?;
+                            }
+                            Ok(())
+                        })?;
+                    Ok(())
+                })?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a collection.nml of Traktor.
+pub fn import(path: &Path) -> Result<Import, InteropError> {
+    let mut reader = Reader::from_file(path)?;
+    reader.trim_text(true);
+    let mut buf = vec![];
+    let mut import = Import::default();
+    // primary key -> file path, for resolving playlist entries
+    let mut track_paths: HashMap<String, String> = HashMap::new();
+    let mut in_collection = false;
+    // the collection entry, that is currently read, with its grid markers
+    let mut current: Option<(ImportedTrack, Vec<Tempo>)> = None;
+    // position of the grid marker, whose GRID element is expected next
+    let mut grid_marker: Option<f64> = None;
+    // folders and playlists, that are currently open, the innermost last
+    let mut nodes: Vec<PlaylistNode> = vec![];
+    loop {
+        let element = match reader.read_event(&mut buf)? {
+            Event::Start(element) | Event::Empty(element) => element.into_owned(),
+            Event::End(element) => {
+                match element.name() {
+                    b"COLLECTION" => in_collection = false,
+                    b"ENTRY" if in_collection => {
+                        if let Some(entry) = current.take() {
+                            finish_entry(entry, &mut track_paths, &mut import);
+                        }
+                    }
+                    b"CUE_V2" => grid_marker = None,
+                    b"NODE" => close_node(&mut nodes, &mut import),
+                    _ => {}
+                }
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        let attributes = xml_attributes(&element, &reader)?;
+        let attribute = |key: &str| attributes.get(key).cloned().unwrap_or_default();
+        let number = |key: &str| {
+            attributes
+                .get(key)
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+        match element.name() {
+            b"COLLECTION" => in_collection = true,
+            b"ENTRY" if in_collection => {
+                let mut track = ImportedTrack::new(String::new());
+                track.meta.title = attribute("TITLE");
+                track.meta.artist = attribute("ARTIST");
+                current = Some((track, vec![]));
+            }
+            b"LOCATION" => {
+                if let Some((track, _)) = &mut current {
+                    track.file_path = location_to_path(
+                        &attribute("VOLUME"),
+                        &attribute("DIR"),
+                        &attribute("FILE"),
+                    );
+                }
+            }
+            b"ALBUM" => {
+                if let Some((track, _)) = &mut current {
+                    track.meta.album = attribute("TITLE");
+                }
+            }
+            b"INFO" => {
+                if let Some((track, _)) = &mut current {
+                    if track.meta.key.is_none() {
+                        track.meta.key = Key::parse(&attribute("KEY"));
+                    }
+                    // in kilobytes
+                    track.size = number("FILESIZE").map(|size| size as u64 * 1024);
+                }
+            }
+            b"TEMPO" => {
+                if let (Some((track, _)), Some(bpm)) = (&mut current, number("BPM")) {
+                    if bpm > 0. {
+                        track.meta.bpm = Some(Bpm::new(bpm, 1.));
+                    }
+                }
+            }
+            // the numeric key is more reliable than the text in INFO, which depends on the
+            // notation setting of Traktor
+            b"MUSICAL_KEY" => {
+                if let (Some((track, _)), Some(value)) = (&mut current, number("VALUE")) {
+                    track.meta.key = key_from_musical_key_value(value as u8);
+                }
+            }
+            b"CUE_V2" => {
+                let cue_type = attribute("TYPE").parse::<u8>().ok();
+                if let (Some((track, _)), Some(start)) = (&mut current, number("START")) {
+                    let position = start / 1000.;
+                    match cue_type {
+                        Some(CUE_TYPE_CUE) => {
+                            let mut label = attribute("NAME");
+                            if label == NO_NAME {
+                                label.clear();
+                            }
+                            match attribute("HOTCUE").parse::<usize>() {
+                                Ok(slot) if slot < HOT_CUE_SLOTS => {
+                                    let mut cue = HotCue::new(slot, position);
+                                    cue.label = label;
+                                    track.hot_cues.push((slot, cue));
+                                }
+                                Ok(_) => {}
+                                // -1 for cues without a slot
+                                Err(_) => {
+                                    let mut cue = MemCue::new(position);
+                                    cue.label = label;
+                                    track.mem_cues.push(cue);
+                                }
+                            }
+                        }
+                        Some(CUE_TYPE_GRID) => grid_marker = Some(position),
+                        // hot loops are imported as saved loops as well
+                        Some(CUE_TYPE_LOOP) => {
+                            if let Some(length) = number("LEN").filter(|length| *length > 0.) {
+                                track
+                                    .loops
+                                    .push(Loop::new(position, position + length / 1000.));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            b"GRID" => {
+                if let (Some((_, tempos)), Some(position), Some(bpm)) =
+                    (&mut current, grid_marker, number("BPM"))
+                {
+                    // every grid marker is a downbeat
+                    tempos.push((position, bpm, 1));
+                }
+            }
+            b"PRIMARYKEY" => {
+                if let Some(PlaylistNode::Playlist { tracks, .. }) = nodes.last_mut() {
+                    let key = attribute("KEY");
+                    let file_path = track_paths
+                        .get(&key)
+                        .cloned()
+                        .unwrap_or_else(|| primary_key_to_path(&key));
+                    tracks.push(file_path);
+                }
+            }
+            b"NODE" => {
+                let name = attribute("NAME");
+                let node = if attribute("TYPE") == "PLAYLIST" {
+                    PlaylistNode::playlist(name, vec![])
+                } else {
+                    PlaylistNode::folder(name, vec![])
+                };
+                nodes.push(node);
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(import)
+}
+
+fn finish_entry(
+    (mut track, tempos): (ImportedTrack, Vec<Tempo>),
+    track_paths: &mut HashMap<String, String>,
+    import: &mut Import,
+) {
+    // streaming tracks have no location
+    if track.file_path.is_empty() {
+        return;
+    }
+    track.beatgrid = beatgrid_from_tempos(tempos);
+    track_paths.insert(primary_key(&track.file_path), track.file_path.clone());
+    import.tracks.push(track);
+}
+
+//------------------------------------------------------------------//
+//                             Helpers                              //
+//------------------------------------------------------------------//
+
+/// splits a path into the VOLUME, DIR and FILE attributes of a LOCATION. Traktor separates the
+/// directories with "/:", e.g. "/:music/:house/:".
+fn location(file_path: &str) -> (String, String, String) {
+    let path = Path::new(file_path);
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut dir = String::from("/:");
+    if let Some(parent) = path.parent() {
+        for component in parent.iter().skip(1) {
+            dir.push_str(&component.to_string_lossy());
+            dir.push_str("/:");
+        }
+    }
+    (String::new(), dir, file)
+}
+
+/// the inverse of location. Windows volumes are drive letters, on macOS the volume is the name
+/// of the disk, which is not part of the path.
+fn location_to_path(volume: &str, dir: &str, file: &str) -> String {
+    let path = format!("{}{}", dir.replace("/:", "/"), file);
+    if volume.ends_with(':') {
+        format!("{}{}", volume, path)
+    } else {
+        path
+    }
+}
+
+/// playlist entries reference tracks by volume, dir and file name
+fn primary_key(file_path: &str) -> String {
+    let (volume, dir, file) = location(file_path);
+    format!("{}{}{}", volume, dir, file)
+}
+
+fn primary_key_to_path(key: &str) -> String {
+    match key.find("/:") {
+        Some(i) => location_to_path(&key[..i], &key[i..], ""),
+        None => key.to_string(),
+    }
+}
+
+/// MUSICAL_KEY values: 0 to 11 are the major keys from C to B, 12 to 23 the minor keys
+fn musical_key_value(key: Key) -> u8 {
+    match key.mode {
+        Mode::Major => key.tonic,
+        Mode::Minor => key.tonic + 12,
+    }
+}
+
+fn key_from_musical_key_value(value: u8) -> Option<Key> {
+    match value {
+        0..=11 => Some(Key::new(value, Mode::Major)),
+        12..=23 => Some(Key::new(value - 12, Mode::Minor)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::codecs::CodecParameters;
+
+    fn track(file_path: &str) -> Arc<Track> {
+        Arc::new(Track::new(String::from(file_path), CodecParameters::new()))
+    }
+
+    /// exports to a temporary collection.nml and imports it again
+    fn round_trip(
+        tracks: &[Arc<Track>],
+        playlists: &[PlaylistNode],
+        name: &str,
+    ) -> (String, Import) {
+        let path = std::env::temp_dir().join(format!("flow-{}-{}.nml", std::process::id(), name));
+        export(tracks, playlists, &path).unwrap();
+        let nml = std::fs::read_to_string(&path).unwrap();
+        let import = import(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (nml, import)
+    }
+
+    #[test]
+    fn round_trips_grid_cues_and_playlists() {
+        let a = track("/music/a.mp3");
+        {
+            let mut meta = a.meta.write().unwrap();
+            meta.title = String::from("A");
+            meta.bpm = Some(Bpm::new(120., 1.));
+        }
+        // the grid starts before the track, so the first marker is on the next downbeat
+        let mut beatgrid = Beatgrid::new(-0.25, 120.);
+        beatgrid.add_anchor(36, 125.);
+        a.set_beatgrid(beatgrid);
+        a.add_mem_cue(MemCue::new(1.5));
+        let mut hot_cue = HotCue::new(2, 8.25);
+        hot_cue.label = String::from("Drop");
+        a.set_hot_cue(2, hot_cue.clone());
+        a.add_loop(Loop::new(16.5, 24.5));
+        let b = track("/music/b b/é.flac");
+        let playlists = vec![
+            PlaylistNode::folder(
+                String::from("Sets"),
+                vec![PlaylistNode::playlist(
+                    String::from("Friday"),
+                    vec![
+                        String::from("/music/b b/é.flac"),
+                        String::from("/music/a.mp3"),
+                        String::from("/music/missing.mp3"),
+                    ],
+                )],
+            ),
+            PlaylistNode::playlist(String::from("Empty"), vec![]),
+        ];
+        let (nml, import) = round_trip(&[a, b], &playlists, "grid");
+
+        let has_cue = |cue_type: u8, start: f64, length: f64, slot: i64| {
+            nml.contains(&format!(
+                r#"TYPE="{}" START="{:.6}" LEN="{:.6}" REPEATS="-1" HOTCUE="{}""#,
+                cue_type, start, length, slot
+            ))
+        };
+        assert!(has_cue(CUE_TYPE_GRID, 1750., 0., -1));
+        assert!(has_cue(CUE_TYPE_GRID, 17750., 0., -1));
+        assert!(nml.contains(r#"<GRID BPM="120.000000"/>"#));
+        assert!(nml.contains(r#"<GRID BPM="125.000000"/>"#));
+        assert!(has_cue(CUE_TYPE_CUE, 1500., 0., -1));
+        assert!(has_cue(CUE_TYPE_CUE, 8250., 0., 2));
+        assert!(has_cue(CUE_TYPE_LOOP, 16500., 8000., -1));
+        assert!(nml.contains(r#"NAME="n.n." DISPL_ORDER="0" TYPE="0" START="1500.000000""#));
+
+        assert_eq!(import.tracks.len(), 2);
+        let a = &import.tracks[0];
+        assert_eq!(a.file_path, "/music/a.mp3");
+        assert_eq!(a.meta.title, "A");
+        assert_eq!(a.meta.bpm, Some(Bpm::new(120., 1.)));
+        // the grid starts at the first marker, the beats stay in place
+        let mut beatgrid = Beatgrid::new(1.75, 120.);
+        beatgrid.add_anchor(32, 125.);
+        assert_eq!(a.beatgrid, Some(beatgrid));
+        // labels, that Traktor shows as "n.n.", are empty
+        assert_eq!(a.mem_cues, vec![MemCue::new(1.5)]);
+        assert_eq!(a.hot_cues, vec![(2, hot_cue)]);
+        assert_eq!(a.loops, vec![Loop::new(16.5, 24.5)]);
+        let b = &import.tracks[1];
+        assert_eq!(b.file_path, "/music/b b/é.flac");
+        assert!(b.beatgrid.is_none());
+        assert!(b.mem_cues.is_empty() && b.hot_cues.is_empty() && b.loops.is_empty());
+
+        // entries of tracks, that were not exported, are dropped
+        let mut expected = playlists;
+        if let PlaylistNode::Folder { children, .. } = &mut expected[0] {
+            if let PlaylistNode::Playlist { tracks, .. } = &mut children[0] {
+                tracks.pop();
+            }
+        }
+        assert_eq!(import.playlists, expected);
+    }
+
+    #[test]
+    fn round_trips_musical_keys() {
+        let tracks: Vec<Arc<Track>> = (0..24)
+            .map(|value| {
+                let track = track(&format!("/music/{}.mp3", value));
+                track.meta.write().unwrap().key = key_from_musical_key_value(value);
+                track
+            })
+            .collect();
+        let (nml, import) = round_trip(&tracks, &[], "keys");
+        for (value, track) in import.tracks.iter().enumerate() {
+            assert!(nml.contains(&format!(r#"<MUSICAL_KEY VALUE="{}"/>"#, value)));
+            let key = track.meta.key.unwrap();
+            assert_eq!(musical_key_value(key), value as u8);
+        }
+        assert_eq!(import.tracks.len(), 24);
+        assert_eq!(
+            key_from_musical_key_value(0),
+            Some(Key::new(0, Mode::Major))
+        );
+        assert_eq!(
+            key_from_musical_key_value(21),
+            Some(Key::new(9, Mode::Minor))
+        );
+        assert_eq!(key_from_musical_key_value(24), None);
+    }
+
+    #[test]
+    fn round_trips_locations() {
+        for path in ["/music/house/track.mp3", "/track.mp3", "/a b/Café/ü.flac"] {
+            let (volume, dir, file) = location(path);
+            assert_eq!(location_to_path(&volume, &dir, &file), path);
+            assert_eq!(primary_key_to_path(&primary_key(path)), path);
+        }
+        assert_eq!(
+            location("/music/house/track.mp3"),
+            (
+                String::new(),
+                String::from("/:music/:house/:"),
+                String::from("track.mp3")
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_windows_volumes() {
+        assert_eq!(
+            location_to_path("C:", "/:Music/:", "track.mp3"),
+            "C:/Music/track.mp3"
+        );
+        // macOS volumes are disk names, that are not part of the path
+        assert_eq!(
+            location_to_path("Macintosh HD", "/:Users/:dj/:", "track.mp3"),
+            "/Users/dj/track.mp3"
+        );
+    }
+}
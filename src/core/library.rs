@@ -0,0 +1,385 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::config::{Config, ConfigError},
+    view::model::{
+        playlist::PlaylistNode,
+        track::{Track, TrackRecord},
+    },
+};
+
+//------------------------------------------------------------------//
+//                             Library                              //
+//------------------------------------------------------------------//
+
+/// Version of the on-disk record format. Records with a different version are ignored, which
+/// means the corresponding tracks simply get analyzed again.
+pub const LIBRARY_VERSION: u32 = 8;
+
+/// Version of the playlist file format. It changes independently of the track records, so
+/// playlists survive a re-analysis of the library.
+const PLAYLISTS_VERSION: u32 = 4;
+
+/// name of the file, that holds the playlist tree
+const PLAYLISTS_FILE: &str = "playlists.bin";
+
+/// Number of bytes from the start and the end of a file, that go into its content hash
+const CONTENT_HASH_WINDOW: u64 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Io(io::Error),
+    Serialization(bincode::Error),
+}
+
+impl From<io::Error> for LibraryError {
+    fn from(err: io::Error) -> Self {
+        LibraryError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for LibraryError {
+    fn from(err: bincode::Error) -> Self {
+        LibraryError::Serialization(err)
+    }
+}
+
+/// On-disk store for analysis results. Every track is stored in its own file, named after a hash
+/// of the track's path, together with a fingerprint of the audio file. A record is only used if
+/// the fingerprint still matches, so changed files get analyzed again.
+#[derive(Clone, Debug)]
+pub struct Library {
+    /// directory, that holds all records
+    dir: PathBuf,
+}
+
+impl Library {
+    /// opens the library in the user's data directory (e.g. ~/.local/share/flow/library)
+    pub fn open_default() -> Result<Self, LibraryError> {
+        let data_dir = dirs::data_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory found"))?;
+        Library::open(data_dir.join("flow").join("library"))
+    }
+
+    /// opens the library in the given directory and creates it if necessary
+    pub fn open(dir: PathBuf) -> Result<Self, LibraryError> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// loads the stored analysis for a file. Returns None, if the file was never analyzed, was
+    /// changed since, or the record can not be read.
+    pub fn load(&self, file_path: &str) -> Option<Track> {
+        let record_path = self.record_path(file_path);
+        if !record_path.exists() {
+            return None;
+        }
+        let record = match self.read_record(&record_path) {
+            Ok(Some(record)) => record,
+            // record of an older format version
+            Ok(None) => return None,
+            Err(err) => {
+                warn!("failed to read library record for {}: {:?}", file_path, err);
+                return None;
+            }
+        };
+        if record.file_path != file_path {
+            return None;
+        }
+        match Fingerprint::of(Path::new(file_path)) {
+            Ok(fingerprint) if fingerprint == record.fingerprint => {
+                Some(Track::from_record(record))
+            }
+            _ => None,
+        }
+    }
+
+    /// loads all stored tracks, whose files did not change since they were analyzed
+    pub fn load_all(&self) -> Vec<Track> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("failed to list library records: {}", err);
+                return vec![];
+            }
+        };
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "bin")
+                    && path
+                        .file_name()
+                        .map_or(false, |name| name != PLAYLISTS_FILE)
+            })
+            .filter_map(|record_path| match self.read_record(&record_path) {
+                Ok(record) => record,
+                Err(err) => {
+                    warn!(
+                        "failed to read library record {}: {:?}",
+                        record_path.display(),
+                        err
+                    );
+                    None
+                }
+            })
+            .filter(|record| {
+                Fingerprint::of(Path::new(&record.file_path))
+                    .map_or(false, |fingerprint| fingerprint == record.fingerprint)
+            })
+            .map(Track::from_record)
+            .collect()
+    }
+
+    /// stores the analysis results of a track
+    pub fn store(&self, track: &Track) -> Result<(), LibraryError> {
+        let fingerprint = Fingerprint::of(Path::new(&track.file_path))?;
+        let record = track.to_record(fingerprint);
+        let record_path = self.record_path(&track.file_path);
+        // write to a temporary file first, so a crash never leaves a half written record behind
+        let tmp_path = record_path.with_extension("tmp");
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(writer, &record)?;
+        fs::rename(tmp_path, record_path)?;
+        Ok(())
+    }
+
+    /// loads the playlist tree. Returns an empty tree, if no playlists were stored yet.
+    pub fn load_playlists(&self) -> Vec<PlaylistNode> {
+        let path = self.dir.join(PLAYLISTS_FILE);
+        if !path.exists() {
+            return vec![];
+        }
+        match self.read_playlists(&path) {
+            Ok(playlists) => playlists.unwrap_or_default(),
+            Err(err) => {
+                warn!("failed to read playlists: {:?}", err);
+                vec![]
+            }
+        }
+    }
+
+    /// stores the playlist tree, replacing the previous one
+    pub fn store_playlists(&self, playlists: &[PlaylistNode]) -> Result<(), LibraryError> {
+        let path = self.dir.join(PLAYLISTS_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(writer, &(PLAYLISTS_VERSION, playlists))?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn read_record(&self, record_path: &Path) -> Result<Option<TrackRecord>, LibraryError> {
+        let bytes = fs::read(record_path)?;
+        // the version is the first field of every record format, so it can be read on its own
+        let version: u32 = bincode::deserialize(&bytes)?;
+        if version != LIBRARY_VERSION {
+            return Ok(None);
+        }
+        Ok(Some(bincode::deserialize(&bytes)?))
+    }
+
+    fn read_playlists(&self, path: &Path) -> Result<Option<Vec<PlaylistNode>>, LibraryError> {
+        let bytes = fs::read(path)?;
+        let version: u32 = bincode::deserialize(&bytes)?;
+        if version != PLAYLISTS_VERSION {
+            return Ok(None);
+        }
+        let (_, playlists): (u32, Vec<PlaylistNode>) = bincode::deserialize(&bytes)?;
+        Ok(Some(playlists))
+    }
+
+    fn record_path(&self, file_path: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{:016x}.bin",
+            fnv1a(file_path.as_bytes(), FNV_OFFSET)
+        ))
+    }
+}
+
+//------------------------------------------------------------------//
+//                             Scanning                             //
+//------------------------------------------------------------------//
+
+/// file types, that can be played
+pub const SUPPORTED_EXTENSIONS: [&str; 3] = ["mp3", "wav", "flac"];
+
+/// Finds the tracks in the library roots, filtered by the include and exclude patterns of the
+/// config
+pub struct Scanner {
+    /// None, if all supported files are included
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    follow_symlinks: bool,
+}
+
+impl Scanner {
+    pub fn new(config: &Config) -> Result<Self, ConfigError> {
+        let include = if config.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&config.include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: glob_set(&config.exclude)?,
+            follow_symlinks: config.follow_symlinks,
+        })
+    }
+
+    /// scans all roots. Roots, that can not be read, e.g. unmounted external drives, are skipped.
+    /// Tracks in overlapping roots are only listed once.
+    pub fn scan_roots(&self, roots: &[PathBuf]) -> Vec<String> {
+        let mut listed = HashSet::new();
+        let mut res = vec![];
+        for root in roots {
+            match self.scan(root) {
+                Ok(files) => {
+                    res.extend(files.into_iter().filter(|file| listed.insert(file.clone())))
+                }
+                Err(err) => warn!("failed to scan {}: {}", root.display(), err),
+            }
+        }
+        res
+    }
+
+    /// scans a directory and its sub directories for tracks
+    pub fn scan(&self, dir: &Path) -> io::Result<Vec<String>> {
+        let mut res = vec![];
+        // canonical paths of the scanned directories, so symlink cycles are only entered once
+        let mut visited = HashSet::new();
+        self.scan_dir(dir, &mut visited, &mut res)?;
+        Ok(res)
+    }
+
+    fn scan_dir(
+        &self,
+        dir: &Path,
+        visited: &mut HashSet<PathBuf>,
+        res: &mut Vec<String>,
+    ) -> io::Result<()> {
+        if !visited.insert(dir.canonicalize()?) {
+            return Ok(());
+        }
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if self.exclude.is_match(&path) {
+                continue;
+            }
+            let mut file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                if !self.follow_symlinks {
+                    continue;
+                }
+                // broken links are skipped
+                match fs::metadata(&path) {
+                    Ok(metadata) => file_type = metadata.file_type(),
+                    Err(_) => continue,
+                }
+            }
+            if file_type.is_dir() {
+                // an unreadable directory should not hide the rest of the library
+                if let Err(err) = self.scan_dir(&path, visited, res) {
+                    warn!("failed to scan {}: {}", path.display(), err);
+                }
+            } else if self.is_track(&path) {
+                match path.into_os_string().into_string() {
+                    Ok(file_path) => res.push(file_path),
+                    Err(path) => warn!("skipping path, that is no valid UTF-8: {:?}", path),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// whether a file is supported and matches the include patterns
+    fn is_track(&self, path: &Path) -> bool {
+        let supported = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(false, |extension| {
+                SUPPORTED_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+            });
+        supported
+            && self
+                .include
+                .as_ref()
+                .map_or(true, |include| include.is_match(path))
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+//------------------------------------------------------------------//
+//                           Fingerprint                            //
+//------------------------------------------------------------------//
+
+/// Cheap identification of a file's content. Hashing whole audio files would take about as long
+/// as decoding them, so only the beginning and end of the file are hashed, which is where tags
+/// are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// modification time in seconds since the unix epoch
+    mtime: u64,
+    /// file size in bytes
+    size: u64,
+    /// FNV-1a hash of the first and last bytes of the file
+    content_hash: u64,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let size = metadata.len();
+        let mut file = File::open(path)?;
+        let mut head = vec![];
+        (&mut file)
+            .take(CONTENT_HASH_WINDOW)
+            .read_to_end(&mut head)?;
+        let mut content_hash = fnv1a(&head, FNV_OFFSET);
+        if size > CONTENT_HASH_WINDOW {
+            let mut tail = vec![];
+            file.seek(SeekFrom::Start(size - CONTENT_HASH_WINDOW))?;
+            file.take(CONTENT_HASH_WINDOW).read_to_end(&mut tail)?;
+            content_hash = fnv1a(&tail, content_hash);
+        }
+        Ok(Self {
+            mtime,
+            size,
+            content_hash,
+        })
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a, used instead of std's DefaultHasher, because its output has to be stable across
+/// builds
+fn fnv1a(bytes: &[u8], seed: u64) -> u64 {
+    bytes.iter().fold(seed, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
@@ -0,0 +1,162 @@
+use std::f32::consts::PI;
+
+/// corner frequency of the low shelf in Hz
+const LOW_FREQUENCY: f32 = 70.;
+/// center frequency of the mid band in Hz
+const MID_FREQUENCY: f32 = 1000.;
+/// corner frequency of the high shelf in Hz
+const HIGH_FREQUENCY: f32 = 13000.;
+/// width of the mid band
+const MID_Q: f32 = 0.7;
+
+//------------------------------------------------------------------//
+//                            Equalizer                             //
+//------------------------------------------------------------------//
+
+/// A three band equalizer of a mixer channel, with shelves for the lows and highs and a peak
+/// filter for the mids
+pub struct Equalizer {
+    sample_rate: f32,
+    /// gains of the low, mid and high band in dB
+    gains: [f32; 3],
+    low: Biquad,
+    mid: Biquad,
+    high: Biquad,
+}
+
+impl Equalizer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            sample_rate,
+            gains: [0.; 3],
+            low: Biquad::new(low_shelf(sample_rate, LOW_FREQUENCY, 0.), channels),
+            mid: Biquad::new(peak(sample_rate, MID_FREQUENCY, 0.), channels),
+            high: Biquad::new(high_shelf(sample_rate, HIGH_FREQUENCY, 0.), channels),
+        }
+    }
+
+    /// sets the gains of the bands in dB. The filters keep their state, so turning a knob does
+    /// not click.
+    pub fn set_gains(&mut self, low: f32, mid: f32, high: f32) {
+        if self.gains == [low, mid, high] {
+            return;
+        }
+        self.gains = [low, mid, high];
+        self.low.coefficients = low_shelf(self.sample_rate, LOW_FREQUENCY, low);
+        self.mid.coefficients = peak(self.sample_rate, MID_FREQUENCY, mid);
+        self.high.coefficients = high_shelf(self.sample_rate, HIGH_FREQUENCY, high);
+    }
+
+    /// filters interleaved frames in place. At 0 dB on all bands the frames stay untouched.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.gains == [0.; 3] {
+            return;
+        }
+        self.low.process(samples);
+        self.mid.process(samples);
+        self.high.process(samples);
+    }
+}
+
+//------------------------------------------------------------------//
+//                              Biquad                              //
+//------------------------------------------------------------------//
+
+/// A second order filter
+struct Biquad {
+    /// b0, b1, b2, a1 and a2, normalized by a0
+    coefficients: [f32; 5],
+    /// last two inputs and outputs of each channel
+    state: Vec<[f32; 4]>,
+}
+
+impl Biquad {
+    fn new(coefficients: [f32; 5], channels: usize) -> Self {
+        Self {
+            coefficients,
+            state: vec![[0.; 4]; channels.max(1)],
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+        let channels = self.state.len();
+        for frame in samples.chunks_mut(channels) {
+            for (sample, [x1, x2, y1, y2]) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let x = *sample;
+                let y = b0 * x + b1 * *x1 + b2 * *x2 - a1 * *y1 - a2 * *y2;
+                *x2 = *x1;
+                *x1 = x;
+                *y2 = *y1;
+                *y1 = y;
+                *sample = y;
+            }
+        }
+    }
+}
+
+// The coefficients are the ones from the Audio EQ Cookbook by Robert Bristow-Johnson.
+
+/// normalizes coefficients by a0
+fn normalize(b: [f32; 3], a: [f32; 3]) -> [f32; 5] {
+    [
+        b[0] / a[0],
+        b[1] / a[0],
+        b[2] / a[0],
+        a[1] / a[0],
+        a[2] / a[0],
+    ]
+}
+
+/// amplitude, cosine of the angular frequency and alpha of a shelf with a slope of 1
+fn shelf_parameters(sample_rate: f32, frequency: f32, gain: f32) -> (f32, f32, f32) {
+    let a = 10f32.powf(gain / 40.);
+    // shelves above the nyquist frequency are not possible
+    let w0 = 2. * PI * frequency.min(sample_rate * 0.45) / sample_rate;
+    (a, w0.cos(), w0.sin() / 2. * 2f32.sqrt())
+}
+
+fn low_shelf(sample_rate: f32, frequency: f32, gain: f32) -> [f32; 5] {
+    let (a, cos, alpha) = shelf_parameters(sample_rate, frequency, gain);
+    let sqrt_a_alpha = 2. * a.sqrt() * alpha;
+    normalize(
+        [
+            a * ((a + 1.) - (a - 1.) * cos + sqrt_a_alpha),
+            2. * a * ((a - 1.) - (a + 1.) * cos),
+            a * ((a + 1.) - (a - 1.) * cos - sqrt_a_alpha),
+        ],
+        [
+            (a + 1.) + (a - 1.) * cos + sqrt_a_alpha,
+            -2. * ((a - 1.) + (a + 1.) * cos),
+            (a + 1.) + (a - 1.) * cos - sqrt_a_alpha,
+        ],
+    )
+}
+
+fn high_shelf(sample_rate: f32, frequency: f32, gain: f32) -> [f32; 5] {
+    let (a, cos, alpha) = shelf_parameters(sample_rate, frequency, gain);
+    let sqrt_a_alpha = 2. * a.sqrt() * alpha;
+    normalize(
+        [
+            a * ((a + 1.) + (a - 1.) * cos + sqrt_a_alpha),
+            -2. * a * ((a - 1.) + (a + 1.) * cos),
+            a * ((a + 1.) + (a - 1.) * cos - sqrt_a_alpha),
+        ],
+        [
+            (a + 1.) - (a - 1.) * cos + sqrt_a_alpha,
+            2. * ((a - 1.) - (a + 1.) * cos),
+            (a + 1.) - (a - 1.) * cos - sqrt_a_alpha,
+        ],
+    )
+}
+
+fn peak(sample_rate: f32, frequency: f32, gain: f32) -> [f32; 5] {
+    let a = 10f32.powf(gain / 40.);
+    let w0 = 2. * PI * frequency / sample_rate;
+    let alpha = w0.sin() / (2. * MID_Q);
+    normalize(
+        [1. + alpha * a, -2. * w0.cos(), 1. - alpha * a],
+        [1. + alpha / a, -2. * w0.cos(), 1. - alpha / a],
+    )
+}
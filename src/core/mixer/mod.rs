@@ -0,0 +1,501 @@
+pub mod eq;
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{spawn, JoinHandle},
+    time::Duration,
+};
+
+use log::warn;
+use samplerate::{ConverterType, Samplerate};
+use symphonia::core::audio::{Channels, SignalSpec};
+
+use self::eq::Equalizer;
+use super::output::{null::NullOutput, AudioOutput, OutputBackend, OutputError};
+
+/// sample rate, at which all decks are mixed
+pub const MIXER_SAMPLE_RATE: u32 = 44100;
+/// the mixer and its outputs are always stereo
+const MIXER_CHANNELS: usize = 2;
+/// number of frames, that are mixed at once
+const BLOCK_FRAMES: usize = 1024;
+/// number of frames a deck can write ahead of the mixer, before its writes block
+const CHANNEL_CAPACITY: usize = 4 * BLOCK_FRAMES;
+/// number of failed writes in a row to the master output, after which the mixer stops
+const MAX_WRITE_ERRORS: usize = 100;
+/// range of the trim knobs in dB
+pub const MIN_TRIM: f32 = -12.;
+pub const MAX_TRIM: f32 = 12.;
+/// range of the equalizer knobs in dB, turning a knob all the way down nearly kills the band
+pub const MIN_EQ_GAIN: f32 = -26.;
+pub const MAX_EQ_GAIN: f32 = 6.;
+
+/// converts a gain in dB into an amplitude factor
+fn amplitude(gain: f32) -> f32 {
+    10f32.powf(gain / 20.)
+}
+
+//------------------------------------------------------------------//
+//                          MixerSettings                           //
+//------------------------------------------------------------------//
+
+/// Curve of the crossfader, that determines how the two sides get faded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrossfaderCurve {
+    /// constant power, both sides are at -3 dB in the center
+    Smooth,
+    /// both sides are at -6 dB in the center
+    Linear,
+    /// both sides are at full volume, except at the very ends, e.g. for scratching
+    Sharp,
+}
+
+impl CrossfaderCurve {
+    /// the next curve, for cycling through all curves
+    pub fn next(self) -> Self {
+        match self {
+            CrossfaderCurve::Smooth => CrossfaderCurve::Linear,
+            CrossfaderCurve::Linear => CrossfaderCurve::Sharp,
+            CrossfaderCurve::Sharp => CrossfaderCurve::Smooth,
+        }
+    }
+
+    /// amplitude factors of side A and side B at a crossfader position between 0 (A) and 1 (B)
+    pub fn gains(self, position: f32) -> (f32, f32) {
+        let position = position.clamp(0., 1.);
+        match self {
+            CrossfaderCurve::Smooth => (
+                (position * std::f32::consts::FRAC_PI_2).cos(),
+                (position * std::f32::consts::FRAC_PI_2).sin(),
+            ),
+            CrossfaderCurve::Linear => (1. - position, position),
+            CrossfaderCurve::Sharp => (((1. - position) * 20.).min(1.), (position * 20.).min(1.)),
+        }
+    }
+}
+
+/// The side of the crossfader a channel is assigned to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrossfaderSide {
+    A,
+    /// the channel ignores the crossfader
+    Thru,
+    B,
+}
+
+impl CrossfaderSide {
+    /// the next side, for cycling through all sides
+    pub fn next(self) -> Self {
+        match self {
+            CrossfaderSide::A => CrossfaderSide::Thru,
+            CrossfaderSide::Thru => CrossfaderSide::B,
+            CrossfaderSide::B => CrossfaderSide::A,
+        }
+    }
+}
+
+/// A knob of a mixer channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Knob {
+    Trim,
+    High,
+    Mid,
+    Low,
+}
+
+impl Knob {
+    /// the next knob, for cycling through all knobs
+    pub fn next(self) -> Self {
+        match self {
+            Knob::Trim => Knob::High,
+            Knob::High => Knob::Mid,
+            Knob::Mid => Knob::Low,
+            Knob::Low => Knob::Trim,
+        }
+    }
+}
+
+/// Settings of a mixer channel. All gains are in dB.
+#[derive(Clone, Debug)]
+pub struct ChannelSettings {
+    pub trim: f32,
+    pub high: f32,
+    pub mid: f32,
+    pub low: f32,
+    /// position of the channel fader between 0 and 1
+    pub fader: f32,
+    pub side: CrossfaderSide,
+    /// whether the channel is on the headphone cue bus
+    pub cue: bool,
+}
+
+impl ChannelSettings {
+    /// the settings of a channel of a freshly started mixer. Odd channels are assigned to side
+    /// A of the crossfader, even ones to side B.
+    pub fn new(channel: usize) -> Self {
+        Self {
+            trim: 0.,
+            high: 0.,
+            mid: 0.,
+            low: 0.,
+            fader: 1.,
+            side: if channel % 2 == 0 {
+                CrossfaderSide::A
+            } else {
+                CrossfaderSide::B
+            },
+            cue: false,
+        }
+    }
+
+    /// the gain of a knob in dB
+    pub fn knob(&self, knob: Knob) -> f32 {
+        match knob {
+            Knob::Trim => self.trim,
+            Knob::High => self.high,
+            Knob::Mid => self.mid,
+            Knob::Low => self.low,
+        }
+    }
+
+    /// turns a knob by a gain in dB, it stops at the end of its range
+    pub fn turn(&mut self, knob: Knob, gain: f32) {
+        match knob {
+            Knob::Trim => self.trim = (self.trim + gain).clamp(MIN_TRIM, MAX_TRIM),
+            Knob::High => self.high = (self.high + gain).clamp(MIN_EQ_GAIN, MAX_EQ_GAIN),
+            Knob::Mid => self.mid = (self.mid + gain).clamp(MIN_EQ_GAIN, MAX_EQ_GAIN),
+            Knob::Low => self.low = (self.low + gain).clamp(MIN_EQ_GAIN, MAX_EQ_GAIN),
+        }
+    }
+}
+
+/// Settings of the whole mixer, shared between the app and the mixing thread
+#[derive(Clone, Debug)]
+pub struct MixerSettings {
+    pub channels: Vec<ChannelSettings>,
+    /// position of the crossfader between 0 (A) and 1 (B)
+    pub crossfader: f32,
+    pub curve: CrossfaderCurve,
+}
+
+impl MixerSettings {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels: (0..channels).map(ChannelSettings::new).collect(),
+            crossfader: 0.5,
+            curve: CrossfaderCurve::Smooth,
+        }
+    }
+
+    /// amplitude factor of a channel on the master bus
+    fn master_gain(&self, channel: &ChannelSettings) -> f32 {
+        let (a, b) = self.curve.gains(self.crossfader);
+        let crossfader = match channel.side {
+            CrossfaderSide::A => a,
+            CrossfaderSide::Thru => 1.,
+            CrossfaderSide::B => b,
+        };
+        channel.fader.clamp(0., 1.) * crossfader
+    }
+}
+
+//------------------------------------------------------------------//
+//                              Mixer                               //
+//------------------------------------------------------------------//
+
+/// Mixes the decks into a master output and a pre-fader cue output for headphones. Each deck
+/// writes into its own `MixerChannel`, the mixer takes a block of frames from every channel,
+/// applies trim, equalizer, channel fader and crossfader, and writes the sums to its outputs.
+/// Writing to the master output paces the mixer, which in turn paces the decks.
+pub struct Mixer {
+    settings: Arc<Mutex<MixerSettings>>,
+    channels: Vec<MixerChannel>,
+    equalizers: Vec<Equalizer>,
+    master: Box<dyn AudioOutput>,
+    cue: Option<Box<dyn AudioOutput>>,
+}
+
+impl Mixer {
+    /// opens the outputs and starts mixing in a new thread. Returns the channels in the order
+    /// of the channel settings, they can be opened by players through `OutputBackend::Mixer`.
+    /// The thread ends, when all channels and their outputs are dropped, or when the master
+    /// output keeps failing.
+    pub fn spawn(
+        settings: Arc<Mutex<MixerSettings>>,
+        master_backend: OutputBackend,
+        cue_backend: Option<OutputBackend>,
+    ) -> (Vec<MixerChannel>, JoinHandle<()>) {
+        let count = settings.lock().unwrap().channels.len();
+        let channels: Vec<MixerChannel> = (0..count).map(MixerChannel::new).collect();
+        let handle = {
+            let channels = channels.clone();
+            spawn(move || {
+                let spec = SignalSpec::new(
+                    MIXER_SAMPLE_RATE,
+                    Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+                );
+                let master = master_backend.open(spec).unwrap_or_else(|err| {
+                    warn!("failed to open {} master output: {}", master_backend, err);
+                    // keep mixing in real time, so the decks do not run away
+                    Box::new(NullOutput::open(spec))
+                });
+                let cue = cue_backend.and_then(|cue_backend| match cue_backend.open(spec) {
+                    Ok(cue) => Some(cue),
+                    Err(err) => {
+                        warn!("failed to open {} cue output: {}", cue_backend, err);
+                        None
+                    }
+                });
+                let mut mixer = Mixer {
+                    settings,
+                    equalizers: (0..count)
+                        .map(|_| Equalizer::new(MIXER_SAMPLE_RATE, MIXER_CHANNELS))
+                        .collect(),
+                    channels,
+                    master,
+                    cue,
+                };
+                let mut errors = 0;
+                while !mixer.channels.iter().all(MixerChannel::is_abandoned) {
+                    match mixer.mix_block() {
+                        Ok(()) => errors = 0,
+                        Err(err) => {
+                            // only the first error in a row is logged
+                            if errors == 0 {
+                                warn!("failed to write to master output: {}", err);
+                            }
+                            errors += 1;
+                            if errors == MAX_WRITE_ERRORS {
+                                warn!("stopping the mixer after {} failed writes", errors);
+                                break;
+                            }
+                        }
+                    }
+                }
+                // decks, that wait for space in their channel, must not block forever
+                for channel in &mixer.channels {
+                    channel.close();
+                }
+            })
+        };
+        (channels, handle)
+    }
+
+    /// mixes a block of frames of all channels and writes it to the outputs. Only errors of the
+    /// master output are returned, the cue output is optional.
+    fn mix_block(&mut self) -> Result<(), OutputError> {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut master = vec![0.; BLOCK_FRAMES * MIXER_CHANNELS];
+        let mut cue = vec![0.; BLOCK_FRAMES * MIXER_CHANNELS];
+        for ((channel, equalizer), channel_settings) in self
+            .channels
+            .iter()
+            .zip(self.equalizers.iter_mut())
+            .zip(settings.channels.iter())
+        {
+            let mut block = channel.take(BLOCK_FRAMES * MIXER_CHANNELS);
+            equalizer.set_gains(
+                channel_settings.low,
+                channel_settings.mid,
+                channel_settings.high,
+            );
+            equalizer.process(&mut block);
+            let trim = amplitude(channel_settings.trim);
+            let gain = settings.master_gain(channel_settings);
+            for ((sample, master), cue) in block.iter().zip(master.iter_mut()).zip(cue.iter_mut()) {
+                let sample = sample * trim;
+                *master += sample * gain;
+                // the cue bus is taken before the channel fader and the crossfader
+                if channel_settings.cue {
+                    *cue += sample;
+                }
+            }
+        }
+        if let Some(out) = &mut self.cue {
+            if let Err(err) = out.write(&cue) {
+                warn!("failed to write to cue output: {}", err);
+            }
+        }
+        self.master.write(&master)
+    }
+}
+
+//------------------------------------------------------------------//
+//                           MixerChannel                           //
+//------------------------------------------------------------------//
+
+/// Input of a mixer channel. Clones refer to the same buffer, so a channel can be given to the
+/// player of a deck while the mixer reads from it.
+#[derive(Clone, Debug)]
+pub struct MixerChannel {
+    /// index of the channel in the mixer
+    index: usize,
+    /// interleaved stereo frames at the mixer's sample rate, that were not mixed yet
+    buffer: Arc<(Mutex<VecDeque<f32>>, Condvar)>,
+    /// set, when the mixer stopped and does not take frames anymore
+    closed: Arc<AtomicBool>,
+}
+
+impl MixerChannel {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            buffer: Arc::new((Mutex::new(VecDeque::new()), Condvar::new())),
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// opens the channel for frames in the given format. Mono is played on both sides and
+    /// other sample rates get resampled.
+    pub fn open(&self, spec: SignalSpec) -> Result<ChannelOutput, OutputError> {
+        let channels = spec.channels.count();
+        if channels == 0 || channels > MIXER_CHANNELS {
+            return Err(OutputError::UnsupportedSpec(spec));
+        }
+        let resampler = if spec.rate == MIXER_SAMPLE_RATE {
+            None
+        } else {
+            Some(
+                Samplerate::new(
+                    ConverterType::SincFastest,
+                    spec.rate,
+                    MIXER_SAMPLE_RATE,
+                    channels,
+                )
+                .map_err(|err| OutputError::Backend(err.to_string()))?,
+            )
+        };
+        self.buffer.0.lock().unwrap().clear();
+        Ok(ChannelOutput {
+            buffer: Arc::clone(&self.buffer),
+            closed: Arc::clone(&self.closed),
+            channels,
+            resampler,
+        })
+    }
+
+    /// takes a number of interleaved samples, missing ones are silent. This wakes up the writer,
+    /// if it waits for space in the buffer.
+    fn take(&self, len: usize) -> Vec<f32> {
+        let (buffer, space) = &*self.buffer;
+        let mut buffer = buffer.lock().unwrap();
+        let available = len.min(buffer.len());
+        let mut samples: Vec<f32> = buffer.drain(..available).collect();
+        samples.resize(len, 0.);
+        space.notify_all();
+        samples
+    }
+
+    /// whether nobody but the mixer itself can write into the channel anymore
+    fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.buffer) == 1
+    }
+
+    /// lets all current and future writes fail. This wakes up the writer, if it waits for space
+    /// in the buffer.
+    fn close(&self) {
+        let (buffer, space) = &*self.buffer;
+        let _buffer = buffer.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        space.notify_all();
+    }
+}
+
+impl fmt::Display for MixerChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mixer channel {}", self.index + 1)
+    }
+}
+
+/// `AudioOutput`, that writes into a `MixerChannel`
+pub struct ChannelOutput {
+    buffer: Arc<(Mutex<VecDeque<f32>>, Condvar)>,
+    /// set, when the mixer stopped
+    closed: Arc<AtomicBool>,
+    /// channels of the written frames
+    channels: usize,
+    /// converts the written frames to the mixer's sample rate
+    resampler: Option<Samplerate>,
+}
+
+impl AudioOutput for ChannelOutput {
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError> {
+        let resampled = match &self.resampler {
+            Some(resampler) => Some(
+                resampler
+                    .process(frames)
+                    .map_err(|err| OutputError::Backend(err.to_string()))?,
+            ),
+            None => None,
+        };
+        let frames = resampled.as_deref().unwrap_or(frames);
+        let (buffer, space) = &*self.buffer;
+        let mut buffer = space
+            .wait_while(buffer.lock().unwrap(), |buffer| {
+                buffer.len() >= CHANNEL_CAPACITY * MIXER_CHANNELS
+                    && !self.closed.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(OutputError::Backend("the mixer has stopped".to_string()));
+        }
+        if self.channels == 1 {
+            buffer.extend(frames.iter().flat_map(|sample| [*sample, *sample]));
+        } else {
+            buffer.extend(frames);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.buffer.0.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        let frames = self.buffer.0.lock().unwrap().len() / MIXER_CHANNELS;
+        Some(Duration::from_secs_f64(
+            frames as f64 / MIXER_SAMPLE_RATE as f64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> SignalSpec {
+        SignalSpec::new(
+            MIXER_SAMPLE_RATE,
+            Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+        )
+    }
+
+    #[test]
+    fn stops_when_all_channels_are_dropped() {
+        let settings = Arc::new(Mutex::new(MixerSettings::new(2)));
+        let (channels, handle) = Mixer::spawn(settings, OutputBackend::Null, None);
+        let mut output = channels[0].open(spec()).unwrap();
+        output.write(&[0.; BLOCK_FRAMES * MIXER_CHANNELS]).unwrap();
+        drop(channels);
+        drop(output);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn writes_fail_after_the_mixer_stopped() {
+        let channel = MixerChannel::new(0);
+        let mut output = channel.open(spec()).unwrap();
+        // the buffer is full, so the write would wait for the mixer forever
+        output
+            .write(&[0.; CHANNEL_CAPACITY * MIXER_CHANNELS])
+            .unwrap();
+        channel.close();
+        assert!(output.write(&[0.; MIXER_CHANNELS]).is_err());
+    }
+}
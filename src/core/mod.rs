@@ -1,2 +1,8 @@
 pub mod analyzer;
+pub mod config;
+pub mod interop;
+pub mod library;
+pub mod mixer;
+pub mod output;
 pub mod player;
+pub mod time_stretch;
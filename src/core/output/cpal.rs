@@ -0,0 +1,122 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, HostId, Sample, SampleFormat, SampleRate, Stream, StreamConfig,
+};
+use log::warn;
+use symphonia::core::audio::SignalSpec;
+
+use super::{AudioOutput, OutputError};
+
+/// number of seconds of audio the ring buffer between player and audio callback can hold
+const RING_BUFFER_SECONDS: f64 = 0.2;
+
+/// Output to a cpal host (ALSA or JACK). cpal pulls samples from a callback, so written frames
+/// are pushed into a bounded ring buffer, that the callback drains.
+pub struct CpalOutput {
+    ring: Arc<RingBuffer>,
+    spec: SignalSpec,
+    /// the stream has to be kept alive for the callback to run
+    _stream: Stream,
+}
+
+struct RingBuffer {
+    samples: Mutex<VecDeque<f32>>,
+    /// notified whenever the callback consumed samples
+    consumed: Condvar,
+    capacity: usize,
+}
+
+impl CpalOutput {
+    pub fn open(host_id: HostId, spec: SignalSpec) -> Result<Self, OutputError> {
+        let host = cpal::host_from_id(host_id)
+            .map_err(|_| OutputError::BackendUnavailable(format!("{:?}", host_id)))?;
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| OutputError::BackendUnavailable(format!("{:?}", host_id)))?;
+        let sample_format = device
+            .default_output_config()
+            .map_err(|err| OutputError::Backend(err.to_string()))?
+            .sample_format();
+        let config = StreamConfig {
+            channels: spec.channels.count() as u16,
+            sample_rate: SampleRate(spec.rate),
+            buffer_size: BufferSize::Default,
+        };
+        let capacity = (spec.rate as f64 * RING_BUFFER_SECONDS) as usize * spec.channels.count();
+        let ring = Arc::new(RingBuffer {
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+            consumed: Condvar::new(),
+            capacity,
+        });
+        let stream = match sample_format {
+            SampleFormat::F32 => CpalOutput::build_stream::<f32>(&device, &config, &ring),
+            SampleFormat::I16 => CpalOutput::build_stream::<i16>(&device, &config, &ring),
+            SampleFormat::U16 => CpalOutput::build_stream::<u16>(&device, &config, &ring),
+        }
+        .map_err(|_| OutputError::UnsupportedSpec(spec))?;
+        stream
+            .play()
+            .map_err(|err| OutputError::Backend(err.to_string()))?;
+        Ok(Self {
+            ring,
+            spec,
+            _stream: stream,
+        })
+    }
+
+    fn build_stream<T: Sample>(
+        device: &cpal::Device,
+        config: &StreamConfig,
+        ring: &Arc<RingBuffer>,
+    ) -> Result<Stream, cpal::BuildStreamError> {
+        let ring = Arc::clone(ring);
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut samples = ring.samples.lock().unwrap();
+                for out in data.iter_mut() {
+                    // play silence on buffer underruns
+                    let sample = samples.pop_front().unwrap_or(0.);
+                    *out = Sample::from(&sample);
+                }
+                ring.consumed.notify_one();
+            },
+            |err| warn!("audio stream error: {}", err),
+        )
+    }
+}
+
+impl AudioOutput for CpalOutput {
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError> {
+        let mut written = 0;
+        while written < frames.len() {
+            let mut samples = self.ring.samples.lock().unwrap();
+            while samples.len() >= self.ring.capacity {
+                samples = self.ring.consumed.wait(samples).unwrap();
+            }
+            let free = self.ring.capacity - samples.len();
+            let end = std::cmp::min(written + free, frames.len());
+            samples.extend(&frames[written..end]);
+            written = end;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.ring.samples.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        let buffered = self.ring.samples.lock().unwrap().len() / self.spec.channels.count();
+        Some(Duration::from_secs_f64(
+            buffered as f64 / self.spec.rate as f64,
+        ))
+    }
+}
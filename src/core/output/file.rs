@@ -0,0 +1,88 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use symphonia::core::audio::SignalSpec;
+
+use super::{AudioOutput, OutputError};
+
+/// size of the RIFF/WAVE header written by `FileOutput`
+const WAV_HEADER_LEN: u32 = 44;
+
+/// An output that writes all frames into a 32 bit float WAV file. Frames are written as fast as
+/// they are decoded, so this is only useful for rendering and inspecting what the player emitted.
+pub struct FileOutput {
+    writer: BufWriter<File>,
+    spec: SignalSpec,
+    /// number of bytes of sample data written so far
+    data_len: u32,
+}
+
+impl FileOutput {
+    pub fn open(path: &Path, spec: SignalSpec) -> Result<Self, OutputError> {
+        let file = File::create(path)?;
+        let mut output = Self {
+            writer: BufWriter::new(file),
+            spec,
+            data_len: 0,
+        };
+        output.write_header()?;
+        Ok(output)
+    }
+
+    /// (re)writes the WAV header for the current amount of sample data and returns to the end of
+    /// the file
+    fn write_header(&mut self) -> Result<(), OutputError> {
+        let channels = self.spec.channels.count() as u16;
+        let bytes_per_frame = channels as u32 * 4;
+        let w = &mut self.writer;
+        w.seek(SeekFrom::Start(0))?;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        // format tag 3: IEEE float
+        w.write_all(&3u16.to_le_bytes())?;
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&self.spec.rate.to_le_bytes())?;
+        w.write_all(&(self.spec.rate * bytes_per_frame).to_le_bytes())?;
+        w.write_all(&(bytes_per_frame as u16).to_le_bytes())?;
+        w.write_all(&32u16.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&self.data_len.to_le_bytes())?;
+        w.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl AudioOutput for FileOutput {
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError> {
+        for sample in frames {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += frames.len() as u32 * 4;
+        Ok(())
+    }
+
+    /// nothing is buffered for playback, so this only makes sure the file is valid on disk
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+}
+
+impl Drop for FileOutput {
+    fn drop(&mut self) {
+        // best effort, there is nobody left to report the error to
+        let _ = self.flush();
+    }
+}
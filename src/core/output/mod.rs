@@ -0,0 +1,239 @@
+#[cfg(feature = "cpal")]
+pub mod cpal;
+pub mod file;
+pub mod null;
+#[cfg(feature = "pulseaudio")]
+pub mod pulse;
+
+use std::{
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use symphonia::core::audio::SignalSpec;
+
+use super::mixer::MixerChannel;
+
+//------------------------------------------------------------------//
+//                           AudioOutput                            //
+//------------------------------------------------------------------//
+
+/// A sink for decoded audio. Implementations are opened for one `SignalSpec` and receive
+/// interleaved f32 frames in that format.
+pub trait AudioOutput {
+    /// write interleaved frames to the output. This blocks until the backend accepted the frames,
+    /// which is what paces the player.
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError>;
+    /// drop everything that is buffered but not yet played
+    fn flush(&mut self) -> Result<(), OutputError>;
+    /// the time it takes until a written frame is audible, if the backend knows it
+    fn latency(&self) -> Option<Duration>;
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    /// the backend was not compiled in or is not available on this machine
+    BackendUnavailable(String),
+    /// the backend can not play audio in the given format
+    UnsupportedSpec(SignalSpec),
+    /// opening or writing the output failed
+    Io(std::io::Error),
+    /// the backend reported an error while writing or flushing
+    Backend(String),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::BackendUnavailable(name) => {
+                write!(f, "audio backend {} is not available", name)
+            }
+            OutputError::UnsupportedSpec(spec) => write!(
+                f,
+                "unsupported signal spec: {} channels at {} Hz",
+                spec.channels.count(),
+                spec.rate
+            ),
+            OutputError::Io(err) => write!(f, "io error: {}", err),
+            OutputError::Backend(msg) => write!(f, "backend error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+impl From<std::io::Error> for OutputError {
+    fn from(err: std::io::Error) -> Self {
+        OutputError::Io(err)
+    }
+}
+
+//------------------------------------------------------------------//
+//                          OutputBackend                           //
+//------------------------------------------------------------------//
+
+/// Selects which `AudioOutput` implementation the player opens when a track gets loaded
+#[derive(Clone, Debug)]
+pub enum OutputBackend {
+    /// PulseAudio (or pipewire-pulse) via the simple API
+    PulseAudio,
+    /// ALSA via cpal
+    Alsa,
+    /// JACK via cpal
+    Jack,
+    /// discards all frames, but consumes them in real time
+    Null,
+    /// writes everything into a 32 bit float WAV file as fast as possible
+    File(PathBuf),
+    /// keeps everything in memory, so it can be inspected afterwards
+    Capture(CaptureHandle),
+    /// feeds a channel of the software mixer
+    Mixer(MixerChannel),
+}
+
+impl Default for OutputBackend {
+    fn default() -> Self {
+        if cfg!(feature = "pulseaudio") {
+            OutputBackend::PulseAudio
+        } else if cfg!(feature = "cpal") {
+            OutputBackend::Alsa
+        } else {
+            OutputBackend::Null
+        }
+    }
+}
+
+impl OutputBackend {
+    /// opens an output for the given signal spec
+    pub fn open(&self, spec: SignalSpec) -> Result<Box<dyn AudioOutput>, OutputError> {
+        match self {
+            #[cfg(feature = "pulseaudio")]
+            OutputBackend::PulseAudio => Ok(Box::new(pulse::PulseOutput::open(spec)?)),
+            #[cfg(all(feature = "cpal", target_os = "linux"))]
+            OutputBackend::Alsa => Ok(Box::new(cpal::CpalOutput::open(
+                ::cpal::HostId::Alsa,
+                spec,
+            )?)),
+            #[cfg(all(feature = "jack", target_os = "linux"))]
+            OutputBackend::Jack => Ok(Box::new(cpal::CpalOutput::open(
+                ::cpal::HostId::Jack,
+                spec,
+            )?)),
+            OutputBackend::Null => Ok(Box::new(null::NullOutput::open(spec))),
+            OutputBackend::File(path) => Ok(Box::new(file::FileOutput::open(path, spec)?)),
+            OutputBackend::Capture(handle) => Ok(Box::new(handle.open(spec))),
+            OutputBackend::Mixer(channel) => Ok(Box::new(channel.open(spec)?)),
+            #[allow(unreachable_patterns)]
+            backend => Err(OutputError::BackendUnavailable(backend.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for OutputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputBackend::PulseAudio => write!(f, "pulse"),
+            OutputBackend::Alsa => write!(f, "alsa"),
+            OutputBackend::Jack => write!(f, "jack"),
+            OutputBackend::Null => write!(f, "null"),
+            OutputBackend::File(path) => write!(f, "file:{}", path.display()),
+            OutputBackend::Capture(_) => write!(f, "capture"),
+            OutputBackend::Mixer(channel) => write!(f, "{}", channel),
+        }
+    }
+}
+
+impl FromStr for OutputBackend {
+    type Err = OutputError;
+
+    /// parses backend names like "pulse", "alsa", "jack", "null" or "file:/tmp/out.wav"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pulse" | "pulseaudio" => Ok(OutputBackend::PulseAudio),
+            "alsa" => Ok(OutputBackend::Alsa),
+            "jack" => Ok(OutputBackend::Jack),
+            "null" | "none" => Ok(OutputBackend::Null),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(OutputBackend::File(PathBuf::from(path))),
+                _ => Err(OutputError::BackendUnavailable(String::from(s))),
+            },
+        }
+    }
+}
+
+//------------------------------------------------------------------//
+//                          CaptureHandle                           //
+//------------------------------------------------------------------//
+
+/// Shared buffer of a capturing output. Clones refer to the same buffer, so a handle can be given
+/// to the player and inspected afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureHandle {
+    captured: Arc<Mutex<Captured>>,
+}
+
+#[derive(Debug, Default)]
+struct Captured {
+    spec: Option<SignalSpec>,
+    samples: Vec<f32>,
+    flushes: usize,
+}
+
+impl CaptureHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the spec the output was last opened with
+    pub fn spec(&self) -> Option<SignalSpec> {
+        self.captured.lock().unwrap().spec
+    }
+
+    /// all interleaved samples written since the output was opened
+    pub fn samples(&self) -> Vec<f32> {
+        self.captured.lock().unwrap().samples.clone()
+    }
+
+    /// number of times the output was flushed
+    pub fn flushes(&self) -> usize {
+        self.captured.lock().unwrap().flushes
+    }
+
+    fn open(&self, spec: SignalSpec) -> CaptureOutput {
+        let mut captured = self.captured.lock().unwrap();
+        captured.spec = Some(spec);
+        captured.samples.clear();
+        captured.flushes = 0;
+        CaptureOutput {
+            captured: Arc::clone(&self.captured),
+        }
+    }
+}
+
+/// `AudioOutput` that appends everything into a `CaptureHandle`
+struct CaptureOutput {
+    captured: Arc<Mutex<Captured>>,
+}
+
+impl AudioOutput for CaptureOutput {
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError> {
+        self.captured
+            .lock()
+            .unwrap()
+            .samples
+            .extend_from_slice(frames);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.captured.lock().unwrap().flushes += 1;
+        Ok(())
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use symphonia::core::audio::SignalSpec;
+
+use super::{AudioOutput, OutputError};
+
+/// An output that throws all frames away, but only as fast as a sound card would play them.
+/// This keeps the playhead moving in real time on machines without any sound server.
+pub struct NullOutput {
+    spec: SignalSpec,
+    /// point in time, at which all written frames would have been played
+    played_until: Instant,
+}
+
+impl NullOutput {
+    pub fn open(spec: SignalSpec) -> Self {
+        Self {
+            spec,
+            played_until: Instant::now(),
+        }
+    }
+}
+
+impl AudioOutput for NullOutput {
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError> {
+        let num_frames = frames.len() / self.spec.channels.count();
+        let duration = Duration::from_secs_f64(num_frames as f64 / self.spec.rate as f64);
+        let now = Instant::now();
+        if self.played_until < now {
+            self.played_until = now;
+        }
+        self.played_until += duration;
+        // block until the previous write is "played", so there is always one write buffered
+        let wait_until = self.played_until - duration;
+        if wait_until > now {
+            sleep(wait_until - now);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.played_until = Instant::now();
+        Ok(())
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        Some(self.played_until.saturating_duration_since(Instant::now()))
+    }
+}
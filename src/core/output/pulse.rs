@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use libpulse_binding as pulse;
+use libpulse_simple_binding as psimple;
+use log::warn;
+use symphonia::core::audio::{Channels, SignalSpec};
+
+use super::{AudioOutput, OutputError};
+
+/// Output to the default PulseAudio sink using the simple (blocking) API
+pub struct PulseOutput {
+    pa: psimple::Simple,
+}
+
+impl PulseOutput {
+    pub fn open(spec: SignalSpec) -> Result<Self, OutputError> {
+        let pa_spec = pulse::sample::Spec {
+            format: pulse::sample::Format::FLOAT32NE,
+            channels: spec.channels.count() as u8,
+            rate: spec.rate,
+        };
+        if !pa_spec.is_valid() {
+            return Err(OutputError::UnsupportedSpec(spec));
+        }
+
+        let pa_ch_map = PulseOutput::map_channels_to_pa_channelmap(spec.channels);
+        let pa = psimple::Simple::new(
+            None,                               // Use default server
+            "Symphonia Player",                 // Application name
+            pulse::stream::Direction::Playback, // Playback stream
+            None,                               // Default playback device
+            "Music",                            // Description of the stream
+            &pa_spec,                           // Signal specificaiton
+            pa_ch_map.as_ref(),                 // Channel map
+            None,                               // Custom buffering attributes
+        )
+        .map_err(|err| OutputError::Backend(format!("{:?}", err)))?;
+        Ok(Self { pa })
+    }
+
+    /// Maps a set of Symphonia `Channels` to a PulseAudio channel map.
+    fn map_channels_to_pa_channelmap(channels: Channels) -> Option<pulse::channelmap::Map> {
+        let mut map: pulse::channelmap::Map = Default::default();
+        map.init();
+        map.set_len(channels.count() as u8);
+
+        let is_mono = channels.count() == 1;
+
+        for (i, channel) in channels.iter().enumerate() {
+            map.get_mut()[i] = match channel {
+                Channels::FRONT_LEFT if is_mono => pulse::channelmap::Position::Mono,
+                Channels::FRONT_LEFT => pulse::channelmap::Position::FrontLeft,
+                Channels::FRONT_RIGHT => pulse::channelmap::Position::FrontRight,
+                Channels::FRONT_CENTRE => pulse::channelmap::Position::FrontCenter,
+                Channels::REAR_LEFT => pulse::channelmap::Position::RearLeft,
+                Channels::REAR_CENTRE => pulse::channelmap::Position::RearCenter,
+                Channels::REAR_RIGHT => pulse::channelmap::Position::RearRight,
+                Channels::LFE1 => pulse::channelmap::Position::Lfe,
+                Channels::FRONT_LEFT_CENTRE => pulse::channelmap::Position::FrontLeftOfCenter,
+                Channels::FRONT_RIGHT_CENTRE => pulse::channelmap::Position::FrontRightOfCenter,
+                Channels::SIDE_LEFT => pulse::channelmap::Position::SideLeft,
+                Channels::SIDE_RIGHT => pulse::channelmap::Position::SideRight,
+                Channels::TOP_CENTRE => pulse::channelmap::Position::TopCenter,
+                Channels::TOP_FRONT_LEFT => pulse::channelmap::Position::TopFrontLeft,
+                Channels::TOP_FRONT_CENTRE => pulse::channelmap::Position::TopFrontCenter,
+                Channels::TOP_FRONT_RIGHT => pulse::channelmap::Position::TopFrontRight,
+                Channels::TOP_REAR_LEFT => pulse::channelmap::Position::TopRearLeft,
+                Channels::TOP_REAR_CENTRE => pulse::channelmap::Position::TopRearCenter,
+                Channels::TOP_REAR_RIGHT => pulse::channelmap::Position::TopRearRight,
+                _ => {
+                    // If a Symphonia channel cannot map to a PulseAudio position then return None
+                    // because PulseAudio will not be able to open a stream with invalid channels.
+                    warn!("failed to map channel {:?} to output", channel);
+                    return None;
+                }
+            }
+        }
+
+        Some(map)
+    }
+}
+
+impl AudioOutput for PulseOutput {
+    fn write(&mut self, frames: &[f32]) -> Result<(), OutputError> {
+        // PulseAudio expects native endian bytes
+        let bytes: Vec<u8> = frames.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        self.pa
+            .write(&bytes)
+            .map_err(|err| OutputError::Backend(format!("{:?}", err)))
+    }
+
+    fn flush(&mut self) -> Result<(), OutputError> {
+        self.pa
+            .flush()
+            .map_err(|err| OutputError::Backend(format!("{:?}", err)))
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        self.pa
+            .get_latency()
+            .ok()
+            .map(|latency| Duration::from_micros(latency.0))
+    }
+}
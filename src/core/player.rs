@@ -1,14 +1,17 @@
 use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
 
+use crate::core::output::{AudioOutput, OutputBackend};
 use crate::core::player;
-use libpulse_binding as pulse;
-use libpulse_simple_binding as psimple;
+use crate::core::time_stretch::TimeStretcher;
+use crate::view::model::beatgrid::Beatgrid;
 
 use log::warn;
+use samplerate::{ConverterType, Samplerate};
 use std::sync::mpsc::{Receiver, Sender};
-use symphonia::core::audio::RawSampleBuffer;
-use symphonia::core::audio::{Channels, SignalSpec};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::audio::SignalSpec;
 use symphonia::core::codecs::DecoderOptions;
 use symphonia::core::codecs::{CodecParameters, Decoder};
 use symphonia::core::formats::FormatReader;
@@ -18,6 +21,18 @@ use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 use symphonia::core::units::{Time, TimeStamp};
 
+/// ranges of the pitch fader as fractions of the original tempo
+pub const TEMPO_RANGES: [f64; 4] = [0.06, 0.10, 0.16, 0.50];
+/// resolutions of quantize in beats, from 1/8 beat to a bar
+pub const QUANTIZE_BEATS: [f64; 6] = [0.125, 0.25, 0.5, 1., 2., 4.];
+/// scratches longer than this in seconds are only scrubbed, as they would not be recognizable
+const MAX_SCRATCH: f64 = 2.;
+/// the frames of a scratch are played within the time since the last scratch, but at least
+/// within this duration
+const MIN_SCRATCH_DURATION: Duration = Duration::from_millis(10);
+/// ... and at most within this duration, e.g. for the first scratch after a pause
+const MAX_SCRATCH_DURATION: Duration = Duration::from_millis(100);
+
 pub enum Message {
     /// Load a new file
     Load(String),
@@ -29,14 +44,49 @@ pub enum Message {
     SkipForward(Time),
     /// Skip backwards a number of millis
     SkipBackward(Time),
+    /// Jump to a position in the track, e.g. a hot cue
+    JumpTo(Time),
+    /// Jump to a fraction of the track between 0 and 1, e.g. a click on the overview
+    Seek(f64),
+    /// Move the playhead by a number of seconds without playing the frames in between, e.g.
+    /// when dragging the waveform
+    Scrub(f64),
+    /// Move the playhead by a number of seconds and play the frames in between, backwards for
+    /// negative seconds, like moving a vinyl record by hand
+    Scratch(f64),
+    /// Set the loop in point at the playhead
+    LoopIn,
+    /// Set the loop out point at the playhead and start looping
+    LoopOut,
+    /// Exit the active loop, or reenable the last loop and jump to its start
+    Reloop,
+    /// Loop between two positions, e.g. an auto loop or a saved loop
+    SetLoop(Time, Time),
+    /// Multiply the length of the loop, e.g. by 0.5 for halving it
+    ResizeLoop(f64),
+    /// Set the playback speed relative to the original tempo, e.g. 0.06 for +6%. It is clamped to
+    /// the widest of the TEMPO_RANGES.
+    SetTempo(f64),
+    /// Toggle key lock, which keeps the original key at other tempos
+    ToggleKeyLock,
+    /// Align cue points, loop points and skips to the beatgrid of the loaded track, or stop
+    /// aligning them with None. Loading a track disables quantize.
+    SetQuantize(Option<Quantize>),
     /// Get missing preview Data. The parameter tells the player how many preview samples the app
     /// already has
     GetPreview(usize),
 }
 
-pub enum Event {}
+pub enum Event {
+    /// The tempo, that the player applies after a SetTempo message
+    TempoChanged(f64),
+    /// Whether key lock is enabled, after a ToggleKeyLock message
+    KeyLockChanged(bool),
+    /// The player started or stopped playing, or loaded a track
+    StateChanged(PlayerState),
+}
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PlayerState {
     Unloaded,
     Paused,
@@ -76,6 +126,14 @@ impl TimeMarker {
         Self { ts, codec_params }
     }
 
+    pub fn from_seconds(seconds: f64, codec_params: CodecParameters) -> Self {
+        let ts = codec_params
+            .time_base
+            .unwrap()
+            .calc_timestamp(Time::from(seconds.max(0.)));
+        Self { ts, codec_params }
+    }
+
     fn skip(&mut self, offset: Time, direction: SkipDirection) {
         let current = self.codec_params.time_base.unwrap().calc_time(self.ts);
         let new_time = match direction {
@@ -138,6 +196,41 @@ impl TimeMarker {
     }
 }
 
+/// A beatgrid and the resolution, to which positions are aligned
+#[derive(Clone, Debug)]
+pub struct Quantize {
+    pub beatgrid: Beatgrid,
+    /// resolution in beats, one of QUANTIZE_BEATS
+    pub beats: f64,
+}
+
+impl Quantize {
+    /// the grid position closest to a time
+    pub fn snap(&self, seconds: f64) -> f64 {
+        self.beatgrid.snap(seconds, self.beats).max(0.)
+    }
+
+    /// moves a time by a duration, rounded to whole grid steps, so the position within the beat
+    /// stays. It moves at least one step, a duration of 0 moves one step forward.
+    pub fn skip(&self, seconds: f64, duration: f64) -> f64 {
+        let beats = duration / self.beatgrid.beat_length_at(seconds);
+        let steps = (beats.abs() / self.beats).round().max(1.) * beats.signum();
+        let index = self.beatgrid.beat_index_at(seconds) + steps * self.beats;
+        self.beatgrid.beat_position(index).max(0.)
+    }
+}
+
+/// loop in and out points of the player
+#[derive(Clone, Debug)]
+pub struct LoopMarker {
+    /// loop in point
+    pub start: TimeMarker,
+    /// loop out point, None while only the loop in point is set
+    pub end: Option<TimeMarker>,
+    /// whether the playback repeats between the loop points
+    pub active: bool,
+}
+
 pub struct Player {
     /// player state
     state: PlayerState,
@@ -145,16 +238,35 @@ pub struct Player {
     position_marker: Arc<Mutex<Option<TimeMarker>>>,
     /// cue point as a TimeMarker
     cue_point_marker: Option<TimeMarker>,
+    /// loop points, shared with the app for drawing them
+    loop_marker: Arc<Mutex<Option<LoopMarker>>>,
+    /// frames before this timestamp are dropped after a seek, so that the playback continues
+    /// exactly at the target instead of at the start of its packet
+    seek_target: Option<TimeStamp>,
     /// Formatreader
     reader: Option<Box<dyn FormatReader>>,
     /// Decoder
     decoder: Option<Box<dyn Decoder>>,
-    /// PulseAudio output
-    output: Option<psimple::Simple>,
+    /// the backend, that is used to open an output when a track gets loaded
+    output_backend: OutputBackend,
+    /// Audio output
+    output: Option<Box<dyn AudioOutput>>,
     /// Signal Spec
     spec: Option<SignalSpec>,
     /// Symphonia track information
     track: Option<Track>,
+    /// playback speed relative to the original tempo
+    tempo: f64,
+    /// resamples the decoded frames for tempos other than the original one
+    resampler: Option<Samplerate>,
+    /// whether tempo changes keep the original key
+    key_lock: bool,
+    /// time-stretches the decoded frames instead of resampling them, while key lock is enabled
+    stretcher: Option<TimeStretcher>,
+    /// aligns positions to the beatgrid, None while quantize is disabled
+    quantize: Option<Quantize>,
+    /// when the last scratch was played, which determines the speed of the next one
+    last_scratch: Option<Instant>,
 }
 
 impl Player {
@@ -166,28 +278,43 @@ impl Player {
     /// Returns a Sender, which can be used to send messages to the player
     pub fn spawn(
         player_position: Arc<Mutex<Option<TimeMarker>>>,
+        player_loop: Arc<Mutex<Option<LoopMarker>>>,
+        output_backend: OutputBackend,
         player_message_in: Receiver<player::Message>,
         player_event_out: Sender<player::Event>,
     ) -> JoinHandle<()> {
         // The async channel for Events from the reader
         // Start the command handler thread
         spawn(move || {
-            let mut player = Player::new(player_position);
+            let mut player = Player::new(player_position, player_loop, output_backend);
             player.event_loop(player_message_in, player_event_out)
         })
     }
 
-    fn new(position: Arc<Mutex<Option<TimeMarker>>>) -> Self {
+    fn new(
+        position: Arc<Mutex<Option<TimeMarker>>>,
+        loop_marker: Arc<Mutex<Option<LoopMarker>>>,
+        output_backend: OutputBackend,
+    ) -> Self {
         // the frame buffer. TODO: use sensible vector sizes
         Self {
             state: PlayerState::Unloaded,
             reader: None,
             decoder: None,
+            output_backend,
             output: None,
             spec: None,
             track: None,
             cue_point_marker: None,
+            loop_marker,
+            seek_target: None,
             position_marker: position,
+            tempo: 0.,
+            resampler: None,
+            key_lock: false,
+            stretcher: None,
+            quantize: None,
+            last_scratch: None,
         }
     }
 
@@ -196,6 +323,7 @@ impl Player {
         player_message_in: Receiver<Message>,
         player_event_out: Sender<player::Event>,
     ) {
+        let mut reported_state = self.state;
         while self.state != PlayerState::Closed {
             // command handlers
             match player_message_in.try_recv() {
@@ -218,6 +346,48 @@ impl Player {
                 Ok(Message::SkipBackward(time)) => {
                     self.skip(time, SkipDirection::Backward);
                 }
+                Ok(Message::JumpTo(time)) => {
+                    self.jump_to(time);
+                }
+                Ok(Message::Seek(progress)) => {
+                    self.seek(progress);
+                }
+                Ok(Message::Scrub(seconds)) => {
+                    self.scrub(seconds);
+                }
+                Ok(Message::Scratch(seconds)) => {
+                    self.scratch(seconds);
+                }
+                Ok(Message::LoopIn) => {
+                    self.loop_in();
+                }
+                Ok(Message::LoopOut) => {
+                    self.loop_out();
+                }
+                Ok(Message::Reloop) => {
+                    self.reloop();
+                }
+                Ok(Message::SetLoop(start, end)) => {
+                    self.set_loop(start, end);
+                }
+                Ok(Message::ResizeLoop(factor)) => {
+                    self.resize_loop(factor);
+                }
+                Ok(Message::SetTempo(tempo)) => {
+                    self.set_tempo(tempo);
+                    if let Err(err) = player_event_out.send(Event::TempoChanged(self.tempo)) {
+                        warn!("failed to report tempo: {}", err);
+                    }
+                }
+                Ok(Message::SetQuantize(quantize)) => {
+                    self.quantize = quantize;
+                }
+                Ok(Message::ToggleKeyLock) => {
+                    self.toggle_key_lock();
+                    if let Err(err) = player_event_out.send(Event::KeyLockChanged(self.key_lock)) {
+                        warn!("failed to report key lock: {}", err);
+                    }
+                }
                 Ok(_msg) => {
                     todo!()
                 }
@@ -232,13 +402,26 @@ impl Player {
                     self.play();
                 }
             }
+            if self.state != reported_state {
+                reported_state = self.state;
+                if let Err(err) = player_event_out.send(Event::StateChanged(self.state)) {
+                    warn!("failed to report player state: {}", err);
+                }
+            }
         }
     }
     fn load(&mut self, path: String) {
         self.init_reader(path);
         self.init_decoder();
         self.init_output();
+        // the tempo stays, but the sample rate may be a different one
+        self.resampler = None;
+        self.stretcher = None;
+        self.set_tempo(self.tempo);
         self.state = PlayerState::Paused;
+        self.seek_target = None;
+        self.quantize = None;
+        *self.loop_marker.lock().unwrap() = None;
         if let Some(track) = &self.track {
             *self.position_marker.lock().unwrap() =
                 Some(TimeMarker::new(track.codec_params.clone()));
@@ -248,34 +431,37 @@ impl Player {
 
     fn cue(&mut self) {
         if self.state != PlayerState::Playing {
-            let curr_position = &(*self.position_marker.lock().unwrap());
-            if let (Some(curr_position), Some(curr_cue)) = (curr_position, &self.cue_point_marker) {
+            let mut curr_position = (*self.position_marker.lock().unwrap()).clone();
+            if let (Some(curr_position), Some(curr_cue)) = (&curr_position, &self.cue_point_marker)
+            {
                 if curr_position == curr_cue {
                     self.state = PlayerState::Playing;
                 }
             }
+            // a quantized cue point moves the playhead onto the grid
+            if let Some(curr_position) = &mut curr_position {
+                if let Some(ts) = self.quantize_ts(curr_position.ts, Quantize::snap) {
+                    if ts != curr_position.ts {
+                        curr_position.ts = ts;
+                        self.seek_to(ts);
+                    }
+                }
+            }
             // set cue new point
-            self.cue_point_marker = curr_position.to_owned();
+            self.cue_point_marker = curr_position;
         } else {
             // return to last cue point
-            if let (Some(track), Some(reader), Some(cue)) =
-                (&self.track, &mut self.reader, &self.cue_point_marker)
-            {
-                *self.position_marker.lock().unwrap() = self.cue_point_marker.clone();
-                reader.seek(
-                    symphonia::core::formats::SeekMode::Accurate,
-                    symphonia::core::formats::SeekTo::TimeStamp {
-                        ts: cue.ts,
-                        track_id: track.id,
-                    },
-                );
+            if let Some(cue) = &self.cue_point_marker {
+                self.seek_to(cue.ts);
             }
         }
     }
 
     fn pause(&mut self) {
         if let Some(out) = &mut self.output {
-            out.flush();
+            if let Err(err) = out.flush() {
+                warn!("failed to flush output: {}", err);
+            }
         }
     }
 
@@ -300,118 +486,450 @@ impl Player {
         };
     }
 
-    /// skip a given amount of milliseconds, either forward or backwards
+    /// skip a given amount of milliseconds, either forward or backwards. Quantized skips move
+    /// by whole grid steps instead.
     fn skip(&mut self, offset: Time, t: SkipDirection) {
-        if let (Some(track), Some(reader), Some(playhead)) = (
-            &self.track,
-            &mut self.reader,
-            &mut (*self.position_marker.lock().unwrap()),
-        ) {
-            playhead.skip(offset, t);
-            let track_id = track.id;
-            let res = reader.seek(
+        let playhead = (*self.position_marker.lock().unwrap())
+            .as_ref()
+            .map(|playhead| playhead.ts);
+        if let Some(playhead) = playhead {
+            let duration = offset.seconds as f64 + offset.frac;
+            let duration = match t {
+                SkipDirection::Forward => duration,
+                SkipDirection::Backward => -duration,
+            };
+            let target = self.quantize_ts(playhead, |quantize, seconds| {
+                quantize.skip(seconds, duration)
+            });
+            if let Some(ts) = target {
+                self.seek_to(ts);
+                return;
+            }
+        }
+        let target = (*self.position_marker.lock().unwrap())
+            .clone()
+            .map(|mut playhead| {
+                playhead.skip(offset, t);
+                playhead.ts
+            });
+        if let Some(ts) = target {
+            self.seek_to(ts);
+        }
+    }
+
+    /// move the playhead to an absolute position. The playback state does not change.
+    fn jump_to(&mut self, time: Time) {
+        if let Some(track) = &self.track {
+            let ts = track.codec_params.time_base.unwrap().calc_timestamp(time);
+            self.seek_to(ts);
+        }
+    }
+
+    /// move the playhead to a fraction of the track
+    fn seek(&mut self, progress: f64) {
+        let codec_params = match &self.track {
+            Some(track) => track.codec_params.clone(),
+            None => return,
+        };
+        let duration = match (codec_params.n_frames, codec_params.sample_rate) {
+            (Some(n_frames), Some(sample_rate)) => n_frames as f64 / sample_rate as f64,
+            _ => return,
+        };
+        let target = TimeMarker::from_seconds(progress.clamp(0., 1.) * duration, codec_params);
+        self.seek_to(target.ts);
+    }
+
+    /// the timestamps of the playhead and of the position a number of seconds away from it,
+    /// which stays within the track
+    fn scrub_target(&self, seconds: f64) -> Option<(TimeStamp, TimeStamp)> {
+        let playhead = (*self.position_marker.lock().unwrap()).clone()?;
+        let codec_params = &playhead.codec_params;
+        let duration = match (codec_params.n_frames, codec_params.sample_rate) {
+            (Some(n_frames), Some(sample_rate)) => n_frames as f64 / sample_rate as f64,
+            _ => return None,
+        };
+        let target = (playhead.get_time_in_seconds() + seconds).clamp(0., duration);
+        let target = TimeMarker::from_seconds(target, codec_params.clone());
+        Some((playhead.ts, target.ts))
+    }
+
+    /// move the playhead by a number of seconds. The playback state does not change.
+    fn scrub(&mut self, seconds: f64) {
+        if let Some((_, target)) = self.scrub_target(seconds) {
+            self.seek_to(target);
+        }
+    }
+
+    /// move the playhead by a number of seconds and play the frames in between. They are played
+    /// within the time since the last scratch, so the pitch follows the speed of the hand.
+    fn scratch(&mut self, seconds: f64) {
+        let now = Instant::now();
+        let duration = self
+            .last_scratch
+            .map_or(MAX_SCRATCH_DURATION, |last| now - last)
+            .clamp(MIN_SCRATCH_DURATION, MAX_SCRATCH_DURATION);
+        self.last_scratch = Some(now);
+        let (playhead, target) = match self.scrub_target(seconds) {
+            Some(positions) => positions,
+            None => return,
+        };
+        if seconds.abs() > MAX_SCRATCH || playhead == target {
+            self.seek_to(target);
+            return;
+        }
+        let samples = self.decode_between(playhead.min(target), playhead.max(target));
+        if let (Some(spec), Some(out)) = (self.spec, &mut self.output) {
+            let frames = (duration.as_secs_f64() * spec.rate as f64).round() as usize;
+            let samples =
+                scratch_frames(&samples, spec.channels.count(), frames, target < playhead);
+            if let Err(err) = out.write(&samples) {
+                warn!("failed to write to output: {}", err);
+            }
+        }
+        self.seek_to(target);
+    }
+
+    /// decodes the frames between two timestamps. The playhead is left somewhere after the end.
+    fn decode_between(&mut self, from: TimeStamp, to: TimeStamp) -> Vec<f32> {
+        self.seek_to(from);
+        let mut samples = vec![];
+        if let (Some(reader), Some(decoder)) = (&mut self.reader, &mut self.decoder) {
+            while let Ok(packet) = reader.next_packet() {
+                let packet_start = packet.ts();
+                let packet_end = packet.ts() + packet.dur();
+                let decoded = match decoder.decode(&packet) {
+                    Ok(decoded) => decoded,
+                    Err(err) => {
+                        warn!("failed to decode: {}", err);
+                        break;
+                    }
+                };
+                let channels = decoded.spec().channels.count();
+                let mut sample_buf =
+                    SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                sample_buf.copy_interleaved_ref(decoded);
+                // timestamps and frames are not necessarily the same unit
+                let frames = sample_buf.samples().len() / channels.max(1);
+                let frame = |ts: TimeStamp| {
+                    let ts = ts.clamp(packet_start, packet_end);
+                    let offset = (ts - packet_start) as f64 / packet.dur().max(1) as f64;
+                    ((offset * frames as f64).round() as usize).min(frames)
+                };
+                samples.extend_from_slice(
+                    &sample_buf.samples()[frame(from) * channels..frame(to) * channels],
+                );
+                if packet_end >= to {
+                    break;
+                }
+            }
+        }
+        samples
+    }
+
+    /// seeks to a timestamp. The frames between the start of the packet and the timestamp are
+    /// dropped in play, so the playback continues at exactly that frame.
+    fn seek_to(&mut self, ts: TimeStamp) {
+        if let (Some(track), Some(reader), Some(decoder)) =
+            (&self.track, &mut self.reader, &mut self.decoder)
+        {
+            match reader.seek(
                 symphonia::core::formats::SeekMode::Accurate,
                 symphonia::core::formats::SeekTo::TimeStamp {
-                    ts: playhead.ts,
-                    track_id,
+                    ts,
+                    track_id: track.id,
                 },
-            );
+            ) {
+                Ok(seeked_to) => {
+                    decoder.reset();
+                    self.seek_target = Some(seeked_to.required_ts);
+                    if let Some(playhead) = &mut (*self.position_marker.lock().unwrap()) {
+                        playhead.go_to_timestamp(seeked_to.required_ts);
+                    }
+                }
+                Err(err) => warn!("failed to seek: {}", err),
+            }
+        }
+    }
+
+    /// applies a quantize operation to a timestamp, e.g. `Quantize::snap`. None, while quantize
+    /// is disabled.
+    fn quantize_ts(
+        &self,
+        ts: TimeStamp,
+        operation: impl Fn(&Quantize, f64) -> f64,
+    ) -> Option<TimeStamp> {
+        let quantize = self.quantize.as_ref()?;
+        let time_base = self.track.as_ref()?.codec_params.time_base?;
+        let time = time_base.calc_time(ts);
+        let seconds = operation(quantize, time.seconds as f64 + time.frac);
+        Some(time_base.calc_timestamp(Time::from(seconds)))
+    }
+
+    //------------------------------------------------------------------//
+    //                              Loops                               //
+    //------------------------------------------------------------------//
+
+    /// sets the loop in point at the playhead, which replaces the previous loop
+    fn loop_in(&mut self) {
+        let mut start = match &(*self.position_marker.lock().unwrap()) {
+            Some(playhead) => playhead.clone(),
+            None => return,
+        };
+        if let Some(ts) = self.quantize_ts(start.ts, Quantize::snap) {
+            start.ts = ts;
+        }
+        *self.loop_marker.lock().unwrap() = Some(LoopMarker {
+            start,
+            end: None,
+            active: false,
+        });
+    }
+
+    /// sets the loop out point at the playhead and starts looping. If the loop out point is
+    /// already behind the playhead, it jumps back to the loop in point.
+    fn loop_out(&mut self) {
+        let playhead = match &(*self.position_marker.lock().unwrap()) {
+            Some(playhead) => playhead.clone(),
+            None => return,
+        };
+        let start = match &(*self.loop_marker.lock().unwrap()) {
+            Some(loop_marker) => loop_marker.start.ts,
+            None => return,
+        };
+        let mut end = self
+            .quantize_ts(playhead.ts, Quantize::snap)
+            .unwrap_or(playhead.ts);
+        if end <= start {
+            // a quantized loop is at least one grid step long
+            end = match self.quantize_ts(start, |quantize, seconds| quantize.skip(seconds, 0.)) {
+                Some(end) => end,
+                None => return,
+            };
+        }
+        if let Some(loop_marker) = &mut (*self.loop_marker.lock().unwrap()) {
+            loop_marker.end = Some(TimeMarker::from_ts(end, playhead.codec_params.clone()));
+            loop_marker.active = true;
+        }
+        if playhead.ts >= end {
+            self.seek_to(start);
+        }
+    }
+
+    /// exits the active loop, or reenables the last complete loop and jumps to its start
+    fn reloop(&mut self) {
+        let start = match &mut (*self.loop_marker.lock().unwrap()) {
+            Some(loop_marker) if loop_marker.active => {
+                loop_marker.active = false;
+                return;
+            }
+            Some(loop_marker) if loop_marker.end.is_some() => {
+                loop_marker.active = true;
+                loop_marker.start.ts
+            }
+            _ => return,
+        };
+        self.seek_to(start);
+    }
+
+    /// starts looping between two positions. The playhead only jumps to the start, if it is
+    /// outside of the loop, so auto loops at the playhead do not skip.
+    fn set_loop(&mut self, start: Time, end: Time) {
+        let codec_params = match &self.track {
+            Some(track) => track.codec_params.clone(),
+            None => return,
+        };
+        let time_base = codec_params.time_base.unwrap();
+        let start = TimeMarker::from_ts(time_base.calc_timestamp(start), codec_params.clone());
+        let end = TimeMarker::from_ts(time_base.calc_timestamp(end), codec_params);
+        if end.ts <= start.ts {
+            return;
+        }
+        let outside = match &(*self.position_marker.lock().unwrap()) {
+            Some(playhead) => playhead.ts < start.ts || playhead.ts >= end.ts,
+            None => return,
+        };
+        let start_ts = start.ts;
+        *self.loop_marker.lock().unwrap() = Some(LoopMarker {
+            start,
+            end: Some(end),
+            active: true,
+        });
+        if outside {
+            self.seek_to(start_ts);
+        }
+    }
+
+    /// multiplies the length of the loop, the loop in point stays. If the playhead is behind the
+    /// new loop out point, it keeps its phase within the loop.
+    fn resize_loop(&mut self, factor: f64) {
+        let playhead = match &(*self.position_marker.lock().unwrap()) {
+            Some(playhead) => playhead.ts,
+            None => return,
+        };
+        let target = match &mut (*self.loop_marker.lock().unwrap()) {
+            Some(LoopMarker {
+                start,
+                end: Some(end),
+                active,
+            }) => {
+                let length = ((end.ts - start.ts) as f64 * factor).round() as u64;
+                if length == 0 {
+                    return;
+                }
+                end.ts = start.ts + length;
+                if *active && playhead >= end.ts {
+                    Some(start.ts + (playhead - start.ts) % length)
+                } else {
+                    None
+                }
+            }
+            _ => return,
+        };
+        if let Some(ts) = target {
+            self.seek_to(ts);
+        }
+    }
+
+    //------------------------------------------------------------------//
+    //                              Tempo                               //
+    //------------------------------------------------------------------//
+
+    /// changes the playback speed. The frames are resampled as if they had a sample rate, that
+    /// is faster or slower by the tempo, which changes the pitch as well. At the original tempo
+    /// the resampler is skipped. With key lock the frames are time-stretched instead.
+    fn set_tempo(&mut self, tempo: f64) {
+        let max_tempo = TEMPO_RANGES[TEMPO_RANGES.len() - 1];
+        self.tempo = tempo.clamp(-max_tempo, max_tempo);
+        let spec = match self.spec {
+            Some(spec) => spec,
+            None => return,
+        };
+        // the stretcher keeps running at the original tempo, so moving the pitch fader across
+        // zero does not interrupt the playback
+        let speed = 1. + self.tempo;
+        match &mut self.stretcher {
+            Some(stretcher) => stretcher.set_speed(speed),
+            None => {
+                self.stretcher = Some(TimeStretcher::new(spec.rate, spec.channels.count(), speed))
+            }
+        }
+        if self.tempo == 0. {
+            self.resampler = None;
+            return;
+        }
+        let from_rate = (spec.rate as f64 * (1. + self.tempo)).round() as u32;
+        match &mut self.resampler {
+            Some(resampler) => resampler.set_from_rate(from_rate),
+            None => {
+                match Samplerate::new(
+                    ConverterType::SincFastest,
+                    from_rate,
+                    spec.rate,
+                    spec.channels.count(),
+                ) {
+                    Ok(resampler) => self.resampler = Some(resampler),
+                    Err(err) => warn!("failed to create resampler: {}", err),
+                }
+            }
+        }
+    }
+
+    /// switches between resampling and time-stretching. The frames buffered by the stretcher
+    /// belong to an earlier position, so they are dropped.
+    fn toggle_key_lock(&mut self) {
+        self.key_lock = !self.key_lock;
+        if let Some(stretcher) = &mut self.stretcher {
+            stretcher.reset();
         }
     }
 
+    /// decodes the next packet and writes it to the output. Only the frames between a seek
+    /// target and an active loop out point are written, after the loop out point the player
+    /// seeks back to the loop in point.
     fn play(&mut self) -> Result<(), symphonia::core::errors::Error> {
-        match (&mut self.reader, &mut self.decoder, &mut self.output) {
+        let loop_start = match (&mut self.reader, &mut self.decoder, &mut self.output) {
             (Some(reader), Some(decoder), Some(out)) => {
                 let packet = reader.next_packet()?;
+                let packet_start = packet.ts();
+                let packet_end = packet.ts() + packet.dur();
+                let decoded = decoder.decode(&packet).unwrap();
+                let channels = decoded.spec().channels.count();
+                let mut sample_buf =
+                    SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                sample_buf.copy_interleaved_ref(decoded);
+                let mut from = packet_start;
+                if let Some(target) = self.seek_target {
+                    if target >= packet_end {
+                        // the packet only had to be decoded, because the decoder depends on it
+                        return Ok(());
+                    }
+                    from = from.max(target);
+                    self.seek_target = None;
+                }
+                let mut to = packet_end;
+                let mut loop_start = None;
+                if let Some(LoopMarker {
+                    start,
+                    end: Some(end),
+                    active: true,
+                }) = &(*self.loop_marker.lock().unwrap())
+                {
+                    if from < end.ts && end.ts <= packet_end {
+                        to = end.ts;
+                        loop_start = Some(start.ts);
+                    }
+                }
                 if let Some(pos) = &mut (*self.position_marker.lock().unwrap()) {
-                    pos.go_to_timestamp(packet.ts());
+                    pos.go_to_timestamp(from);
                 }
-                let decoded = decoder.decode(&packet).unwrap();
-                let mut raw_sample_buf =
-                    RawSampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
-                raw_sample_buf.copy_interleaved_ref(decoded);
-                match out.write(raw_sample_buf.as_bytes()) {
-                    Ok(_) => {
-                        Ok(())
-                        // successfully wrote buffer
-                        // println!("success");
+                // timestamps and frames are not necessarily the same unit
+                let frames = sample_buf.samples().len() / channels.max(1);
+                let frame = |ts: TimeStamp| {
+                    let offset = (ts - packet_start) as f64 / packet.dur().max(1) as f64;
+                    ((offset * frames as f64).round() as usize).min(frames)
+                };
+                let samples = &sample_buf.samples()[frame(from) * channels..frame(to) * channels];
+                let processed = match (&mut self.stretcher, &self.resampler) {
+                    (Some(stretcher), _) if self.key_lock => Some(stretcher.process(samples)),
+                    (_, Some(resampler)) if !samples.is_empty() => {
+                        match resampler.process(samples) {
+                            Ok(resampled) => Some(resampled),
+                            Err(err) => {
+                                warn!("failed to resample: {}", err);
+                                None
+                            }
+                        }
                     }
-                    Err(err) => {
-                        panic!("Failed to write to output device");
-                        // PAErr
-                        // println!("Error: {}", err);
+                    _ => None,
+                };
+                let samples = processed.as_deref().unwrap_or(samples);
+                if !samples.is_empty() {
+                    if let Err(err) = out.write(samples) {
+                        warn!("failed to write to output: {}", err);
+                        self.state = PlayerState::Paused;
                     }
                 }
+                loop_start
             }
             _ => {
                 panic!("Not everything was initialized");
             }
+        };
+        if let Some(ts) = loop_start {
+            self.seek_to(ts);
         }
-    }
-
-    /// Maps a set of Symphonia `Channels` to a PulseAudio channel map.
-    fn map_channels_to_pa_channelmap(channels: Channels) -> Option<pulse::channelmap::Map> {
-        let mut map: pulse::channelmap::Map = Default::default();
-        map.init();
-        map.set_len(channels.count() as u8);
-
-        let is_mono = channels.count() == 1;
-
-        for (i, channel) in channels.iter().enumerate() {
-            map.get_mut()[i] = match channel {
-                Channels::FRONT_LEFT if is_mono => pulse::channelmap::Position::Mono,
-                Channels::FRONT_LEFT => pulse::channelmap::Position::FrontLeft,
-                Channels::FRONT_RIGHT => pulse::channelmap::Position::FrontRight,
-                Channels::FRONT_CENTRE => pulse::channelmap::Position::FrontCenter,
-                Channels::REAR_LEFT => pulse::channelmap::Position::RearLeft,
-                Channels::REAR_CENTRE => pulse::channelmap::Position::RearCenter,
-                Channels::REAR_RIGHT => pulse::channelmap::Position::RearRight,
-                Channels::LFE1 => pulse::channelmap::Position::Lfe,
-                Channels::FRONT_LEFT_CENTRE => pulse::channelmap::Position::FrontLeftOfCenter,
-                Channels::FRONT_RIGHT_CENTRE => pulse::channelmap::Position::FrontRightOfCenter,
-                Channels::SIDE_LEFT => pulse::channelmap::Position::SideLeft,
-                Channels::SIDE_RIGHT => pulse::channelmap::Position::SideRight,
-                Channels::TOP_CENTRE => pulse::channelmap::Position::TopCenter,
-                Channels::TOP_FRONT_LEFT => pulse::channelmap::Position::TopFrontLeft,
-                Channels::TOP_FRONT_CENTRE => pulse::channelmap::Position::TopFrontCenter,
-                Channels::TOP_FRONT_RIGHT => pulse::channelmap::Position::TopFrontRight,
-                Channels::TOP_REAR_LEFT => pulse::channelmap::Position::TopRearLeft,
-                Channels::TOP_REAR_CENTRE => pulse::channelmap::Position::TopRearCenter,
-                Channels::TOP_REAR_RIGHT => pulse::channelmap::Position::TopRearRight,
-                _ => {
-                    // If a Symphonia channel cannot map to a PulseAudio position then return None
-                    // because PulseAudio will not be able to open a stream with invalid channels.
-                    warn!("failed to map channel {:?} to output", channel);
-                    return None;
-                }
-            }
-        }
-
-        Some(map)
+        Ok(())
     }
 
     pub fn init_output(&mut self) {
         let spec = self.spec.unwrap();
-        let pa_spec = pulse::sample::Spec {
-            format: pulse::sample::Format::FLOAT32NE,
-            channels: spec.channels.count() as u8,
-            rate: spec.rate,
-        };
-        assert!(pa_spec.is_valid());
-
-        let pa_ch_map = Player::map_channels_to_pa_channelmap(spec.channels);
-        let pa = psimple::Simple::new(
-            None,                               // Use default server
-            "Symphonia Player",                 // Application name
-            pulse::stream::Direction::Playback, // Playback stream
-            None,                               // Default playback device
-            "Music",                            // Description of the stream
-            &pa_spec,                           // Signal specificaiton
-            pa_ch_map.as_ref(),                 // Channel map
-            None,                               // Custom buffering attributes
-        )
-        .unwrap();
-        self.output = Some(pa)
+        match self.output_backend.open(spec) {
+            Ok(out) => self.output = Some(out),
+            Err(err) => {
+                warn!("failed to open {} output: {}", self.output_backend, err);
+                self.output = None;
+            }
+        }
     }
 
     fn init_reader(&mut self, path: String) {
@@ -437,6 +955,7 @@ impl Player {
             if let None = self.track {
                 self.track = Some(track.clone());
             }
+            let track_id = track.id;
             let codec_params = &track.codec_params;
             let mut decoder = symphonia::default::get_codecs()
                 .make(&codec_params, &dec_opts)
@@ -446,7 +965,111 @@ impl Player {
             let decoded = decoder.decode(&packet).unwrap();
             let spec = decoded.spec();
             self.spec = Some(*spec);
+            // the first packet was only decoded for its spec, the playback starts before it
+            let start = symphonia::core::formats::SeekTo::TimeStamp { ts: 0, track_id };
+            if let Err(err) = reader.seek(symphonia::core::formats::SeekMode::Accurate, start) {
+                warn!("failed to seek to the start: {}", err);
+            }
+            decoder.reset();
             self.decoder = Some(decoder);
         };
     }
 }
+
+/// stretches interleaved frames to a number of frames by linear interpolation, reversed for
+/// backward scratches
+fn scratch_frames(samples: &[f32], channels: usize, frames: usize, backwards: bool) -> Vec<f32> {
+    let channels = channels.max(1);
+    let input_frames = samples.len() / channels;
+    if input_frames == 0 || frames == 0 {
+        return vec![];
+    }
+    let step = (input_frames - 1) as f64 / (frames.max(2) - 1) as f64;
+    let mut output = Vec::with_capacity(frames * channels);
+    for frame in 0..frames {
+        let position = frame as f64 * step;
+        let position = if backwards {
+            (input_frames - 1) as f64 - position
+        } else {
+            position
+        };
+        let left = position.floor() as usize;
+        let right = (left + 1).min(input_frames - 1);
+        let fraction = (position - left as f64) as f32;
+        for channel in 0..channels {
+            let a = samples[left * channels + channel];
+            let b = samples[right * channels + channel];
+            output.push(a + (b - a) * fraction);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::output::CaptureHandle;
+
+    /// sample rate of the generated tracks
+    const RATE: u32 = 44100;
+
+    /// writes a 16 bit stereo WAV file, whose left channel is a ramp and whose right channel is
+    /// the inverted ramp, so every frame can be recognized
+    fn write_wav(name: &str, frames: usize) -> String {
+        let data_len = (frames * 4) as u32;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, 2 channels
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&RATE.to_le_bytes());
+        wav.extend_from_slice(&(RATE * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for frame in 0..frames {
+            let sample = ramp(frame);
+            wav.extend_from_slice(&sample.to_le_bytes());
+            wav.extend_from_slice(&(-sample).to_le_bytes());
+        }
+        let path = std::env::temp_dir().join(format!("flow-{}-{}", std::process::id(), name));
+        std::fs::write(&path, wav).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn ramp(frame: usize) -> i16 {
+        (frame % 1000) as i16 * 16
+    }
+
+    #[test]
+    fn plays_all_frames_into_the_capture_output() {
+        let frames = RATE as usize / 2;
+        let path = write_wav("capture.wav", frames);
+        let capture = CaptureHandle::new();
+        let mut player = Player::new(
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(None)),
+            OutputBackend::Capture(capture.clone()),
+        );
+        player.load(path.clone());
+        player.toggle_play();
+        assert_eq!(player.state, PlayerState::Playing);
+        // the reader fails at the end of the track
+        while player.play().is_ok() {}
+        std::fs::remove_file(path).unwrap();
+
+        let spec = capture.spec().unwrap();
+        assert_eq!(spec.rate, RATE);
+        assert_eq!(spec.channels.count(), 2);
+        let samples = capture.samples();
+        assert_eq!(samples.len(), frames * 2);
+        for (frame, pair) in samples.chunks(2).enumerate() {
+            let expected = ramp(frame) as f32 / 32768.;
+            assert_eq!(pair, [expected, -expected], "frame {}", frame);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::f32::consts::PI;
+
+/// length of the overlapping windows in seconds
+const WINDOW_LENGTH: f64 = 0.04;
+/// how far an analysis window may be moved to find the most similar waveform, in seconds
+const SEARCH_TOLERANCE: f64 = 0.01;
+/// only every nth frame is used for the similarity search, which keeps it cheap enough for
+/// real time playback
+const SEARCH_DECIMATION: usize = 4;
+
+//------------------------------------------------------------------//
+//                          TimeStretcher                           //
+//------------------------------------------------------------------//
+
+/// Changes the speed of interleaved frames without changing their pitch, using waveform
+/// similarity overlap-add (WSOLA).
+///
+/// Windows of the input are read at a hop, that is faster or slower than the hop at which they
+/// are added to the output. Each window is moved a little, so that it continues the waveform of
+/// the previous window, which avoids phase cancellation between overlapping windows.
+pub struct TimeStretcher {
+    channels: usize,
+    /// length of a window in frames
+    window_len: usize,
+    /// the hop between windows in the output, half a window
+    hop: usize,
+    /// maximum offset of a window from its nominal position in frames
+    tolerance: usize,
+    /// playback speed, e.g. 1.06 for +6%
+    speed: f64,
+    /// hann window, that sums to 1 with half a window overlap
+    window: Vec<f32>,
+    /// buffered interleaved input, frames are dropped once no window can start before them
+    input: Vec<f32>,
+    /// nominal position of the next window in the input in frames
+    position: f64,
+    /// the position in the input, where the waveform of the last window would continue
+    continuation: Option<usize>,
+    /// interleaved output, that still has to be overlapped by the next window
+    output: Vec<f32>,
+}
+
+impl TimeStretcher {
+    pub fn new(sample_rate: u32, channels: usize, speed: f64) -> Self {
+        let hop = ((sample_rate as f64 * WINDOW_LENGTH / 2.).round() as usize).max(1);
+        let window_len = 2 * hop;
+        let window = (0..window_len)
+            .map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / window_len as f32).cos())
+            .collect();
+        Self {
+            channels: channels.max(1),
+            window_len,
+            hop,
+            tolerance: (sample_rate as f64 * SEARCH_TOLERANCE).round() as usize,
+            speed,
+            window,
+            input: Vec::new(),
+            position: 0.,
+            continuation: None,
+            output: vec![0.; window_len * channels.max(1)],
+        }
+    }
+
+    /// changes the speed, buffered frames are kept, so the playback continues seamlessly
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// drops all buffered frames, e.g. when another track is loaded
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.position = 0.;
+        self.continuation = None;
+        self.output.iter_mut().for_each(|sample| *sample = 0.);
+    }
+
+    /// stretches interleaved frames. The output lags behind the input by about a window, so
+    /// frames are returned as soon as enough input is buffered.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        self.input.extend_from_slice(samples);
+        let mut stretched = Vec::with_capacity((samples.len() as f64 / self.speed) as usize);
+        loop {
+            let frames = self.input.len() / channels;
+            let nominal = self.position.round() as usize;
+            if nominal + self.tolerance + self.window_len > frames {
+                break;
+            }
+            let start = match self.continuation {
+                Some(continuation) => self.most_similar(continuation, nominal),
+                None => nominal,
+            };
+            // overlap add the window and emit the frames, that no other window overlaps
+            for (i, weight) in self.window.iter().enumerate() {
+                for c in 0..channels {
+                    self.output[i * channels + c] +=
+                        self.input[(start + i) * channels + c] * weight;
+                }
+            }
+            stretched.extend_from_slice(&self.output[..self.hop * channels]);
+            self.output.drain(..self.hop * channels);
+            self.output.resize(self.window_len * channels, 0.);
+            self.position += self.hop as f64 * self.speed;
+            // drop the input, that neither the search nor the continuation needs anymore
+            let consumed = (self.position.floor() as usize)
+                .saturating_sub(self.tolerance)
+                .min(start + self.hop);
+            self.input.drain(..consumed * channels);
+            self.position -= consumed as f64;
+            self.continuation = Some(start + self.hop - consumed);
+        }
+        stretched
+    }
+
+    /// finds the start of the window around the nominal position, that correlates best with the
+    /// natural continuation of the previous window
+    fn most_similar(&self, continuation: usize, nominal: usize) -> usize {
+        let overlap = self.window_len - self.hop;
+        let first = nominal.saturating_sub(self.tolerance);
+        let last = nominal + self.tolerance;
+        let mut best = nominal;
+        let mut best_correlation = f32::MIN;
+        for candidate in first..=last {
+            let correlation: f32 = (0..overlap)
+                .step_by(SEARCH_DECIMATION)
+                .map(|i| self.mono(continuation + i) * self.mono(candidate + i))
+                .sum();
+            if correlation > best_correlation {
+                best_correlation = correlation;
+                best = candidate;
+            }
+        }
+        best
+    }
+
+    /// the sum of all channels of a buffered frame
+    fn mono(&self, frame: usize) -> f32 {
+        self.input[frame * self.channels..(frame + 1) * self.channels]
+            .iter()
+            .sum()
+    }
+}
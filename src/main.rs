@@ -1,11 +1,54 @@
-use flow::view::app::App;
+use clap::Parser;
+use flow::{
+    cli::{self, Cli},
+    core::analyzer::AnalyzerConfig,
+    view::app::App,
+};
 extern crate crossterm;
 
 #[tokio::main]
 async fn main() {
-    // let tempo = Analyzer::get_tempo("music/bass_symptom.mp3");
-    // println!("{}", tempo);
-    let app = App::default();
+    let args = Cli::parse();
+    // library roots and scan options from the config file and the command line
+    let config_path = args.config_path();
+    let config = match args.load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("failed to load config: {}", err);
+            std::process::exit(cli::EXIT_FAILURE);
+        }
+    };
+    // commands run without the terminal UI, e.g. `flow analyze ~/Music` on a server
+    if let Some(command) = args.command {
+        let mut analyzer_config = AnalyzerConfig::default();
+        if let Some(bpm_range) = args.bpm_range {
+            analyzer_config.bpm_range = bpm_range;
+        }
+        std::process::exit(cli::run(command, args.json, &config, analyzer_config));
+    }
+    let mut app = App::default().with_config(config, config_path);
+    // the audio backend can be chosen at runtime, e.g. --output null on headless machines
+    if let Some(backend) = args.output {
+        app = app.with_output_backend(backend);
+    }
+    if let Some(backend) = args.cue_output {
+        app = app.with_cue_output_backend(backend);
+    }
+    if let Some(decks) = args.decks {
+        app = app.with_decks(decks);
+    }
+    if let Some(bpm_range) = args.bpm_range {
+        app = app.with_bpm_range(bpm_range);
+    }
+    if let Some(seconds) = args.skip {
+        app = app.with_skip(seconds);
+    }
+    if let Some(seconds) = args.long_skip {
+        app = app.with_long_skip(seconds);
+    }
+    if let Some(export_dir) = args.export_dir {
+        app = app.with_export_dir(export_dir);
+    }
     let res = app.run().await.unwrap();
     println!("App closed: {:#?}", res);
 }
@@ -1,19 +1,34 @@
 use crate::core::{
-    analyzer::{self, Analyzer},
-    player::{self, TimeMarker},
+    analyzer::{self, AnalyzerConfig, AnalyzerPool},
+    config::{absolute_root, Config},
+    interop::{
+        engine, rekordbox_device, rekordbox_xml, replace_folder, serato, traktor_nml, Import,
+        InteropError, PendingImports,
+    },
+    library::{Library, Scanner},
+    mixer::{ChannelSettings, Knob, Mixer, MixerSettings},
+    output::OutputBackend,
+    player::{LoopMarker, Message, PlayerState, QUANTIZE_BEATS, TEMPO_RANGES},
 };
 use crossterm::{
-    event::{self, EnableMouseCapture, KeyCode, KeyEvent, KeyModifiers},
+    event::{
+        self, EnableMouseCapture, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent,
+        MouseEventKind,
+    },
     execute,
     terminal::{enable_raw_mode, EnterAlternateScreen},
 };
 use symphonia::core::units::Time;
 
-use std::sync::mpsc::{channel, Receiver, Sender};
+use log::warn;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::{
-    fs, io,
-    path::Path,
+    collections::HashSet,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    thread::spawn,
     time::Duration,
 };
 use tui::{
@@ -21,18 +36,33 @@ use tui::{
     widgets::{Block, Borders, Paragraph},
 };
 use tui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     Frame, Terminal,
 };
 
-use crate::core::player::{Message, Player};
-
+use super::model::{
+    cue::{HotCue, Loop, MemCue, HOT_CUE_SLOTS, MAX_LOOP_BEATS, MEM_CUE_LIMIT, MIN_LOOP_BEATS},
+    deck::{Deck, BEAT_JUMPS, DEFAULT_DECKS},
+    key::KeyNotation,
+    playlist::PlaylistNode,
+    track::Track,
+};
 use super::widgets::{
+    cue_list::{CueList, CueListWidget},
     live_preview::LivePreviewWidget,
+    mixer::MixerWidget,
+    phase_meter::{DeckPhase, PhaseMeterWidget},
+    popup::PopupWidget,
     preview::PreviewWidget,
+    root_list::{RootList, RootListWidget},
     track_table::{TrackList, TrackTableWidget},
 };
 
+/// how far a key press moves a channel fader or the crossfader
+const FADER_STEP: f32 = 0.1;
+/// how far a key press turns a mixer knob in dB
+const KNOB_STEP: f32 = 1.;
+
 #[derive(Clone, Debug)]
 pub enum Event {
     /// Key event for Toggling playback
@@ -49,6 +79,18 @@ pub enum Event {
 enum EventScope {
     Player,
     FileList,
+    CueList,
+    Roots,
+}
+
+/// A drag on the live preview of a deck
+struct Drag {
+    /// index of the deck
+    deck: usize,
+    /// the column of the mouse at the last drag event
+    column: u16,
+    /// whether the deck was playing, when the drag started in vinyl mode
+    resume: bool,
 }
 
 pub struct App {
@@ -59,30 +101,173 @@ pub struct App {
     latest_event: String,
     /// Currently active component
     active_event_scope: EventScope,
+    /// state of the memory cue editor
+    cue_list: CueList,
+    /// state of the library root editor
+    root_list: RootList,
+    /// where the live previews of the decks were last drawn, for mouse events
+    live_preview_areas: Vec<Rect>,
+    /// where the overviews of the decks were last drawn, for mouse events
+    preview_areas: Vec<Rect>,
+    /// the drag on a live preview, that is in progress
+    drag: Option<Drag>,
     //------------------------------------------------------------------//
     //                              Player                              //
     //------------------------------------------------------------------//
-    /// hashmap of tracks, that were found in the music dir
+    /// hashmap of tracks, that were found in the library roots
     tracks: TrackList,
-    /// current player position in number of packets.
-    player_position: Arc<Mutex<Option<TimeMarker>>>,
+    /// library roots and scan options
+    config: Config,
+    /// the config file, that added and removed roots are written to
+    config_path: Option<PathBuf>,
+    /// sends the tracks of scanned roots to the app, None until the app runs
+    analyzer_event_out: Option<Sender<analyzer::Event>>,
+    /// analyzes new or changed tracks, None until the app runs
+    analyzer_pool: Option<AnalyzerPool>,
+    /// the decks, each with its own player
+    decks: Vec<Deck>,
+    /// index of the deck, that receives the player key events
+    active_deck: usize,
+    /// index of the deck, that synced decks follow
+    master_deck: usize,
+    /// settings of the mixer, shared with the mixing thread
+    mixer: Arc<Mutex<MixerSettings>>,
+    /// the mixer knob, that gets turned by key events
+    selected_knob: Knob,
+    /// whether cues, loops and skips are aligned to the beatgrid
+    quantize: bool,
+    /// index of the quantize resolution in QUANTIZE_BEATS
+    quantize_resolution: usize,
+    /// index of the beat jump size in BEAT_JUMPS
+    beat_jump: usize,
+    /// whether drags on the live preview scratch like a vinyl record instead of scrubbing
+    vinyl_mode: bool,
+    /// seconds to skip instead of a beat jump on tracks without a beatgrid
+    skip_seconds: f64,
+    /// seconds to skip instead of a phrase jump on tracks without a beatgrid
+    long_skip_seconds: f64,
     /// zoom amount of live preview
     zoom_level: u32,
+    /// notation, in which musical keys are displayed
+    key_notation: KeyNotation,
+    /// the audio backend the mixer writes the master output to
+    output_backend: OutputBackend,
+    /// the audio backend the mixer writes the headphone cue output to, if any
+    cue_output_backend: Option<OutputBackend>,
+    /// on-disk store of analysis results. None, if the library could not be opened
+    library: Option<Library>,
+    /// settings for analyzing new tracks
+    analyzer_config: AnalyzerConfig,
+    /// the playlist tree
+    playlists: Vec<PlaylistNode>,
+    /// imported cues and grids of tracks, that were not scanned yet
+    pending_imports: PendingImports,
+    /// directory for exports to and imports from other DJ software, e.g. a mounted USB drive
+    export_dir: Option<PathBuf>,
+    /// receives the result of the device export, that is running, if any
+    export_result_in: Option<Receiver<String>>,
 }
 
 impl Default for App {
     fn default() -> Self {
+        let library = Library::open_default()
+            .map_err(|err| warn!("failed to open library: {:?}", err))
+            .ok();
+        let playlists = library
+            .as_ref()
+            .map(|library| library.load_playlists())
+            .unwrap_or_default();
         Self {
-            player_position: Arc::new(Mutex::new(None)),
+            decks: (0..DEFAULT_DECKS).map(|_| Deck::default()).collect(),
+            active_deck: 0,
+            master_deck: 0,
+            mixer: Arc::new(Mutex::new(MixerSettings::new(DEFAULT_DECKS))),
+            selected_knob: Knob::Trim,
+            quantize: false,
+            quantize_resolution: 3,
+            beat_jump: 2,
+            vinyl_mode: false,
+            skip_seconds: 20.,
+            long_skip_seconds: 60.,
             latest_event: String::from(""),
             tracks: TrackList::default(),
+            config: Config::default(),
+            config_path: Config::default_path(),
+            analyzer_event_out: None,
+            analyzer_pool: None,
             active_event_scope: EventScope::FileList,
+            cue_list: CueList::default(),
+            root_list: RootList::default(),
+            live_preview_areas: vec![],
+            preview_areas: vec![],
+            drag: None,
             zoom_level: 50,
+            key_notation: KeyNotation::default(),
+            output_backend: OutputBackend::default(),
+            cue_output_backend: None,
+            library,
+            analyzer_config: AnalyzerConfig::default(),
+            playlists,
+            pending_imports: PendingImports::default(),
+            export_dir: dirs::data_dir().map(|data_dir| data_dir.join("flow")),
+            export_result_in: None,
         }
     }
 }
 
 impl App {
+    /// use a different audio backend than the default one
+    pub fn with_output_backend(mut self, output_backend: OutputBackend) -> Self {
+        self.output_backend = output_backend;
+        self
+    }
+
+    /// write the headphone cue output to a second audio backend
+    pub fn with_cue_output_backend(mut self, cue_output_backend: OutputBackend) -> Self {
+        self.cue_output_backend = Some(cue_output_backend);
+        self
+    }
+
+    /// use a number of decks other than DEFAULT_DECKS, each on its own mixer channel
+    pub fn with_decks(mut self, decks: usize) -> Self {
+        let decks = decks.max(1);
+        self.decks = (0..decks).map(|_| Deck::default()).collect();
+        self.mixer = Arc::new(Mutex::new(MixerSettings::new(decks)));
+        self
+    }
+
+    /// skip the given number of seconds with beat jumps on tracks without a beatgrid
+    pub fn with_skip(mut self, seconds: f64) -> Self {
+        self.skip_seconds = seconds;
+        self
+    }
+
+    /// skip the given number of seconds with phrase jumps on tracks without a beatgrid
+    pub fn with_long_skip(mut self, seconds: f64) -> Self {
+        self.long_skip_seconds = seconds;
+        self
+    }
+
+    /// fold detected tempos into the given range, e.g. 160.0..180.0 for drum and bass
+    pub fn with_bpm_range(mut self, bpm_range: Range<f64>) -> Self {
+        self.analyzer_config.bpm_range = bpm_range;
+        self
+    }
+
+    /// scan the roots of the given config. Roots, that are added or removed in the ui, are
+    /// written to the config file at config_path.
+    pub fn with_config(mut self, config: Config, config_path: Option<PathBuf>) -> Self {
+        self.config = config;
+        self.config_path = config_path;
+        self
+    }
+
+    /// export to and import from the given directory instead of the data directory
+    pub fn with_export_dir(mut self, export_dir: PathBuf) -> Self {
+        self.export_dir = Some(export_dir);
+        self
+    }
+
     /// start the app
     pub async fn run(mut self) -> io::Result<()> {
         // init terminal
@@ -92,64 +277,56 @@ impl App {
         let backend = CrosstermBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
         // create message passing channels
-        let (player_events_out, mut player_events_in) = channel::<player::Event>();
-        let (player_messages_out, player_messages_in) = channel::<player::Message>();
         let (analyzer_event_out, mut analyzer_event_in) = channel::<analyzer::Event>();
-        // spawn player
-        let player_handle = Player::spawn(
-            Arc::clone(&self.player_position),
-            player_messages_in,
-            player_events_out,
+        // spawn the mixer and a player for each of its channels
+        let (mixer_channels, _mixer_handle) = Mixer::spawn(
+            Arc::clone(&self.mixer),
+            self.output_backend.clone(),
+            self.cue_output_backend.clone(),
         );
-        // list tracks
-        let files = self
-            .scan_dir(Path::new("/home/data01/Music/test/"))
-            .unwrap();
-        // spawn analyzers
-        for file in files {
-            Analyzer::spawn(file, analyzer_event_out.clone());
-        }
+        let _player_handles: Vec<_> = self
+            .decks
+            .iter_mut()
+            .zip(mixer_channels)
+            .map(|(deck, channel)| deck.spawn(OutputBackend::Mixer(channel)))
+            .collect();
+        // list the tracks of all library roots
+        self.analyzer_pool = Some(AnalyzerPool::spawn(
+            None,
+            self.analyzer_config.clone(),
+            analyzer_event_out.clone(),
+        ));
+        self.analyzer_event_out = Some(analyzer_event_out);
+        self.scan(self.config.roots.clone());
         loop {
             terminal.draw(|f| self.render(f))?;
             // only take key events every 250 milliseconds
-            self.update(
-                player_messages_out.clone(),
-                &mut player_events_in,
-                &mut analyzer_event_in,
-            )
-            .await;
+            self.update(&mut analyzer_event_in).await;
         }
     }
 
     ///update the app's model
-    async fn update(
-        &mut self,
-        player_messages_out: Sender<player::Message>,
-        player_events_in: &mut Receiver<player::Event>,
-        analyzer_event_in: &mut Receiver<analyzer::Event>,
-    ) -> () {
+    async fn update(&mut self, analyzer_event_in: &mut Receiver<analyzer::Event>) -> () {
         //------------------------------------------------------------------//
         //                            Key Events                            //
         //------------------------------------------------------------------//
         if let Ok(true) = event::poll(Duration::from_micros(1)) {
-            if let event::Event::Key(key) = event::read().unwrap() {
-                if let KeyModifiers::NONE = key.modifiers {
+            let event = event::read().unwrap();
+            if let event::Event::Mouse(mouse) = event {
+                self.handle_mouse(mouse);
+            }
+            if let event::Event::Key(key) = event {
+                if self.active_event_scope == EventScope::CueList {
+                    self.handle_cue_list_key(key);
+                } else if self.active_event_scope == EventScope::Roots {
+                    self.handle_root_list_key(key);
+                } else if let KeyModifiers::NONE = key.modifiers {
                     // Events with no modifiers (local)
                     match key.code {
                         // zoom live preview in
-                        KeyCode::Char('+') => {
-                            let zl = self.zoom_level + 10;
-                            if zl <= 500 {
-                                self.zoom_level = zl;
-                            }
-                        }
+                        KeyCode::Char('+') => self.zoom(true),
                         // zoom live preview out
-                        KeyCode::Char('-') => {
-                            let zl = self.zoom_level - 10;
-                            if zl >= 50 {
-                                self.zoom_level = zl;
-                            }
-                        }
+                        KeyCode::Char('-') => self.zoom(false),
                         // go up a track
                         KeyCode::Char('j') => {
                             self.tracks.focus_next();
@@ -158,69 +335,253 @@ impl App {
                         KeyCode::Char('k') => {
                             self.tracks.focus_previous();
                         }
-                        // skip backwards
-                        KeyCode::Char('h') => {
-                            player_messages_out
-                                .send(Message::SkipBackward(Time::new(20, 0.)))
-                                .unwrap();
-                        }
-                        // skip forward
-                        KeyCode::Char('l') => player_messages_out
-                            .send(Message::SkipForward(Time::new(20, 0.)))
-                            .unwrap(),
+                        // beat jump backwards
+                        KeyCode::Char('h') => self.beat_jump(false),
+                        // beat jump forward
+                        KeyCode::Char('l') => self.beat_jump(true),
                         // Toggle Play
                         KeyCode::Char(' ') => {
-                            player_messages_out.send(Message::TogglePlay).unwrap();
+                            self.deck().send(Message::TogglePlay);
                             self.latest_event = String::from("TogglePlay");
                         }
+                        // cycle through key notations
+                        KeyCode::Char('n') => {
+                            self.key_notation = self.key_notation.next();
+                            self.latest_event = format!("Key notation: {:?}", self.key_notation);
+                        }
                         // press cue
-                        KeyCode::Char('c') => player_messages_out.send(Message::Cue).unwrap(),
+                        KeyCode::Char('c') => self.deck().send(Message::Cue),
                         // new cue marker
                         KeyCode::Char('m') => {
-                            let player_pos = &(*self.player_position.lock().unwrap());
-                            if let (Some(track), Some(tm)) = (self.tracks.get_loaded(), player_pos)
+                            let deck = self.deck();
+                            if let (Some(track), Some(position)) =
+                                (deck.track.clone(), deck.position_in_seconds())
                             {
-                                let cue_marker = TimeMarker::from_ts(
-                                    tm.get_timestamp(),
-                                    track.codec_params.clone(),
-                                );
-                                track.add_mem_cue(cue_marker);
+                                let position = self.quantized(position);
+                                if track.add_mem_cue(MemCue::new(position)) {
+                                    self.store_track(&track);
+                                } else {
+                                    self.latest_event =
+                                        format!("Limit of {} memory cues reached", MEM_CUE_LIMIT);
+                                }
+                            }
+                        }
+                        // open the memory cue editor
+                        KeyCode::Char('e') => {
+                            if self.deck().track.is_some() {
+                                self.cue_list = CueList::default();
+                                self.active_event_scope = EventScope::CueList;
                             }
                         }
+                        // jump to a hot cue or set it, if the slot is empty
+                        KeyCode::Char(c @ '1'..='8') => {
+                            let slot = c as usize - '1' as usize;
+                            self.trigger_hot_cue(slot);
+                        }
+                        // set the loop in point
+                        KeyCode::Char('i') => self.deck().send(Message::LoopIn),
+                        // set the loop out point and start looping
+                        KeyCode::Char('o') => self.deck().send(Message::LoopOut),
+                        // exit or reenable the loop
+                        KeyCode::Char('r') => self.deck().send(Message::Reloop),
+                        // loop the next beats from the playhead
+                        KeyCode::Char('a') => self.auto_loop(),
+                        // halve the loop length
+                        KeyCode::Char('[') => self.resize_loop(0.5),
+                        // double the loop length
+                        KeyCode::Char(']') => self.resize_loop(2.),
+                        // save the loop with the track
+                        KeyCode::Char('s') => self.save_loop(),
+                        // loop the next saved loop after the playhead
+                        KeyCode::Char('g') => self.next_saved_loop(),
+                        // slow down
+                        KeyCode::Char(',') => {
+                            let deck = self.deck();
+                            let step = TEMPO_RANGES[deck.tempo_range] / 100.;
+                            self.set_tempo(deck.tempo - step);
+                        }
+                        // speed up
+                        KeyCode::Char('.') => {
+                            let deck = self.deck();
+                            let step = TEMPO_RANGES[deck.tempo_range] / 100.;
+                            self.set_tempo(deck.tempo + step);
+                        }
+                        // back to the original tempo
+                        KeyCode::Char('0') => self.set_tempo(0.),
+                        // cycle through the pitch fader ranges
+                        KeyCode::Char('t') => {
+                            let deck = self.deck_mut();
+                            deck.tempo_range = (deck.tempo_range + 1) % TEMPO_RANGES.len();
+                            let tempo = deck.tempo;
+                            self.set_tempo(tempo);
+                        }
                         // Load Track
                         KeyCode::Enter => {
                             if self.active_event_scope != EventScope::FileList {
                                 ()
                             };
-                            let focused = self.tracks.load_focused();
-                            if let Some(track) = focused {
-                                player_messages_out
-                                    .send(Message::Load(track.file_path.clone()))
-                                    .unwrap();
-                                self.latest_event =
-                                    String::from(format!("Loaded {}", track.file_path));
+                            if let Some(track) = self.tracks.get_focused() {
+                                self.latest_event = format!(
+                                    "Loaded {} into deck {}",
+                                    track.file_path,
+                                    self.active_deck + 1
+                                );
+                                self.deck_mut().load(track);
+                                self.deck().set_quantize(self.quantize_beats());
                             }
                         }
+                        // switch to the next deck
+                        KeyCode::Tab => {
+                            self.active_deck = (self.active_deck + 1) % self.decks.len();
+                            self.latest_event = format!("Deck {}", self.active_deck + 1);
+                        }
+                        // move the channel fader up
+                        KeyCode::Up => self.change_channel(|channel| {
+                            channel.fader = (channel.fader + FADER_STEP).min(1.)
+                        }),
+                        // move the channel fader down
+                        KeyCode::Down => self.change_channel(|channel| {
+                            channel.fader = (channel.fader - FADER_STEP).max(0.)
+                        }),
+                        // move the crossfader towards side A
+                        KeyCode::Left => {
+                            let mut mixer = self.mixer.lock().unwrap();
+                            mixer.crossfader = (mixer.crossfader - FADER_STEP).max(0.);
+                        }
+                        // move the crossfader towards side B
+                        KeyCode::Right => {
+                            let mut mixer = self.mixer.lock().unwrap();
+                            mixer.crossfader = (mixer.crossfader + FADER_STEP).min(1.);
+                        }
+                        // select the next knob of the channel
+                        KeyCode::Char('q') => self.selected_knob = self.selected_knob.next(),
+                        // turn the selected knob up
+                        KeyCode::PageUp => {
+                            let knob = self.selected_knob;
+                            self.change_channel(|channel| channel.turn(knob, KNOB_STEP));
+                        }
+                        // turn the selected knob down
+                        KeyCode::PageDown => {
+                            let knob = self.selected_knob;
+                            self.change_channel(|channel| channel.turn(knob, -KNOB_STEP));
+                        }
+                        // put the channel on the headphone cue bus or take it off
+                        KeyCode::Char('p') => {
+                            self.change_channel(|channel| channel.cue = !channel.cue)
+                        }
+                        // assign the channel to the next side of the crossfader
+                        KeyCode::Char('b') => {
+                            self.change_channel(|channel| channel.side = channel.side.next())
+                        }
+                        // align cues, loops and skips to the beatgrid or stop aligning them
+                        KeyCode::Char('z') => {
+                            self.quantize = !self.quantize;
+                            self.update_quantize();
+                        }
+                        // scratch instead of scrubbing, when dragging the live preview
+                        KeyCode::Char('v') => {
+                            self.vinyl_mode = !self.vinyl_mode;
+                            self.latest_event = format!("Vinyl mode: {}", self.vinyl_mode);
+                        }
+                        // follow the master deck or stop following it
+                        KeyCode::Char('y') => self.toggle_sync(),
+                        // cycle through the crossfader curves
+                        KeyCode::Char('x') => {
+                            let mut mixer = self.mixer.lock().unwrap();
+                            mixer.curve = mixer.curve.next();
+                        }
                         _ => self.latest_event = String::from("Unknown Command"),
                     }
                 } else {
                     // Events with modifier (global)
                     match key {
-                        // big skip backward
+                        // phrase jump backwards
                         KeyEvent {
                             code: KeyCode::Char('h'),
                             modifiers: KeyModifiers::ALT,
-                        } => player_messages_out
-                            .send(Message::SkipBackward(Time::new(0, 0.01)))
-                            .unwrap(),
+                        } => self.phrase_jump(false),
+                        // phrase jump forward
                         KeyEvent {
                             code: KeyCode::Char('l'),
                             modifiers: KeyModifiers::ALT,
+                        } => self.phrase_jump(true),
+                        // halve the beat jump size
+                        KeyEvent {
+                            code: KeyCode::Char('H'),
+                            modifiers: KeyModifiers::SHIFT,
+                        } => self.resize_beat_jump(false),
+                        // double the beat jump size
+                        KeyEvent {
+                            code: KeyCode::Char('L'),
+                            modifiers: KeyModifiers::SHIFT,
+                        } => self.resize_beat_jump(true),
+                        // export the library as rekordbox.xml
+                        KeyEvent {
+                            code: KeyCode::Char('e'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.export_rekordbox_xml(),
+                        // export for Pioneer DJ players
+                        KeyEvent {
+                            code: KeyCode::Char('u'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.export_rekordbox_device(),
+                        // export for Denon DJ players
+                        KeyEvent {
+                            code: KeyCode::Char('d'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.export_engine(),
+                        // write Serato tags into the audio files
+                        KeyEvent {
+                            code: KeyCode::Char('s'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.export_serato(),
+                        // import a rekordbox.xml
+                        KeyEvent {
+                            code: KeyCode::Char('i'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.import_rekordbox_xml(),
+                        // export the library as Traktor collection
+                        KeyEvent {
+                            code: KeyCode::Char('t'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.export_traktor_nml(),
+                        // import a Traktor collection
+                        KeyEvent {
+                            code: KeyCode::Char('o'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.import_traktor_nml(),
+                        // delete a hot cue
+                        KeyEvent {
+                            code: KeyCode::Char(c @ '1'..='8'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.delete_hot_cue(c as usize - '1' as usize),
+                        // toggle key lock
+                        KeyEvent {
+                            code: KeyCode::Char('k'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.deck().send(Message::ToggleKeyLock),
+                        // cycle through the quantize resolutions
+                        KeyEvent {
+                            code: KeyCode::Char('z'),
+                            modifiers: KeyModifiers::ALT,
                         } => {
-                            player_messages_out
-                                .send(Message::SkipForward(Time::new(0, 0.01)))
-                                .unwrap();
+                            self.quantize_resolution =
+                                (self.quantize_resolution + 1) % QUANTIZE_BEATS.len();
+                            self.update_quantize();
                         }
+                        // open the library root editor
+                        KeyEvent {
+                            code: KeyCode::Char('r'),
+                            modifiers: KeyModifiers::ALT,
+                        } => {
+                            self.root_list = RootList::default();
+                            self.active_event_scope = EventScope::Roots;
+                        }
+                        // make the active deck the sync master
+                        KeyEvent {
+                            code: KeyCode::Char('m'),
+                            modifiers: KeyModifiers::ALT,
+                        } => self.make_master(),
                         KeyEvent {
                             code: KeyCode::Char('q'),
                             modifiers: KeyModifiers::ALT,
@@ -234,58 +595,114 @@ impl App {
         //------------------------------------------------------------------//
         //                          Player Events                           //
         //------------------------------------------------------------------//
-        // if let Ok(ev) = player_events_in.try_recv() {
-        //     match ev {
-        //         player::Event::PlayedPackages(num_packets) => {
-        //             self.player_position += num_packets;
-        //         }
-        //     }
-        // }
+        for deck in &mut self.decks {
+            deck.handle_events();
+        }
+        self.sync_decks();
         //------------------------------------------------------------------//
         //                         Analyzer Events                          //
         //------------------------------------------------------------------//
         if let Ok(ev) = analyzer_event_in.try_recv() {
             match ev {
-                analyzer::Event::DoneAnalyzing(track) => {
-                    self.latest_event = String::from(format!("Analyzed: {}", track));
+                analyzer::Event::DoneAnalyzing(file_path) => {
+                    self.latest_event = String::from(format!("Analyzed: {}", file_path));
+                    // the beatgrid for quantize may be new
+                    for deck in &self.decks {
+                        if deck.track.as_ref().map(|track| &track.file_path) == Some(&file_path) {
+                            deck.set_quantize(self.quantize_beats());
+                        }
+                    }
+                    if let Some(track) = self.tracks.get(&file_path) {
+                        self.store_track(&track);
+                        let suggestions = track.suggested_cues().len();
+                        if suggestions > 0 {
+                            self.latest_event =
+                                format!("Analyzed: {} ({} suggested cues)", file_path, suggestions);
+                        }
+                    }
+                }
+                analyzer::Event::NewTrack(track) => {
+                    // the root of the track may have been removed, while it was scanned
+                    if self.config.contains(&track.file_path) {
+                        if let Some(imported) = self.pending_imports.take(&track.file_path) {
+                            imported.apply_to(&track);
+                            self.store_track(&track);
+                        }
+                        self.tracks.insert(track)
+                    }
+                }
+                analyzer::Event::Failed(file_path) => {
+                    self.latest_event = format!("Failed to analyze: {}", file_path);
                 }
-                analyzer::Event::NewTrack(track) => self.tracks.insert(track),
             }
         }
+        //------------------------------------------------------------------//
+        //                             Exports                              //
+        //------------------------------------------------------------------//
+        let export_result = self.export_result_in.as_ref().map(|rx| rx.try_recv());
+        match export_result {
+            Some(Ok(result)) => {
+                self.latest_event = result;
+                self.export_result_in = None;
+            }
+            // the export thread panicked
+            Some(Err(TryRecvError::Disconnected)) => {
+                self.latest_event = String::from("Export failed");
+                self.export_result_in = None;
+            }
+            _ => (),
+        }
     }
 
     /// define how the app should look like
     fn render<B: Backend>(&mut self, f: &mut Frame<B>) {
         // TODO: refactor
+        let decks = self.decks.len() as u16;
         let window = Layout::default()
             .direction(Direction::Vertical)
             .constraints(
                 [
-                    // split for the live preview
-                    Constraint::Percentage(10),
-                    // split for the waveform overview
-                    Constraint::Percentage(5),
+                    // split for the live previews
+                    Constraint::Percentage(5 * decks),
+                    // split for the waveform overviews
+                    Constraint::Percentage(3 * decks),
                     // split for the main body
-                    Constraint::Percentage(83),
+                    Constraint::Percentage(98 - 8 * decks),
                     // split for the footer
                     Constraint::Percentage(2),
                 ]
                 .as_ref(),
             )
             .split(f.size());
-        let player_position = (*self.player_position.lock().unwrap()).clone();
-        if let Some(track) = self.tracks.get_loaded() {
-            let live_preview = LivePreviewWidget::new(&track, &player_position, self.zoom_level);
-            let preview = PreviewWidget::new(&track, &player_position);
+        // a row for each deck
+        let deck_rows = vec![Constraint::Ratio(1, decks as u32); decks as usize];
+        let live_previews = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(deck_rows.clone())
+            .split(window[0]);
+        let previews = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(deck_rows)
+            .split(window[1]);
+        for (index, deck) in self.decks.iter().enumerate() {
+            if let Some(track) = &deck.track {
+                let player_position = (*deck.position.lock().unwrap()).clone();
+                let player_loop = (*deck.player_loop.lock().unwrap()).clone();
+                let live_preview =
+                    LivePreviewWidget::new(track, &player_position, &player_loop, self.zoom_level);
+                let preview = PreviewWidget::new(track, &player_position, &player_loop);
 
-            f.render_widget(preview, window[1]);
-            f.render_widget(live_preview, window[0]);
+                f.render_widget(preview, previews[index]);
+                f.render_widget(live_preview, live_previews[index]);
+            }
         }
+        self.live_preview_areas = live_previews;
+        self.preview_areas = previews;
 
         let status_bar = Paragraph::new(self.latest_event.clone())
             .block(
                 Block::default()
-                    // .title("Status")
+                    .title(self.tempo_display())
                     .title_alignment(tui::layout::Alignment::Center)
                     .borders(Borders::TOP),
             )
@@ -294,36 +711,879 @@ impl App {
         let track_table = TrackTableWidget::new(
             &self.tracks,
             self.active_event_scope == EventScope::FileList,
+            self.key_notation,
         );
-        f.render_widget(track_table, window[2]);
-        // let block = Block::default().title("popup").borders(Borders::ALL);
-        // let popup = PopupWidget::new(block, 10, 90);
-        // f.render_widget(popup, f.size());
-    }
-
-    /// scans a directory for tracks
-    /// Supported file types are .mp3 .flac .wav
-    fn scan_dir(&mut self, dir: &Path) -> io::Result<Vec<String>> {
-        let mut res = vec![];
-        if dir.is_dir() {
-            for entry in fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    let mut sub_dirs = self.scan_dir(&path)?;
-                    res.append(&mut sub_dirs);
+        let body = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+            .split(window[2]);
+        f.render_widget(track_table, body[0]);
+        // the mixer needs a line for the title, the header and its margin and each channel
+        let side_bar = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(decks + 3), Constraint::Min(0)].as_ref())
+            .split(body[1]);
+        let mixer = self.mixer.lock().unwrap().clone();
+        let mixer = MixerWidget::new(&mixer, self.active_deck, self.selected_knob);
+        f.render_widget(mixer, side_bar[0]);
+        let phases = self.deck_phases();
+        f.render_widget(PhaseMeterWidget::new(&phases), side_bar[1]);
+        if self.active_event_scope == EventScope::CueList {
+            if let Some(track) = self.deck().track.clone() {
+                let cues = track.mem_cues();
+                let suggested_cues = track.suggested_cues();
+                let cue_list = CueListWidget::new(&cues, &suggested_cues, &self.cue_list);
+                f.render_widget(PopupWidget::new(cue_list, 60, 50), f.size());
+            }
+        }
+        if self.active_event_scope == EventScope::Roots {
+            let roots: Vec<(PathBuf, usize)> = self
+                .config
+                .roots
+                .iter()
+                .map(|root| {
+                    let tracks = self
+                        .tracks
+                        .values()
+                        .iter()
+                        .filter(|track| Path::new(&track.file_path).starts_with(root))
+                        .count();
+                    (root.clone(), tracks)
+                })
+                .collect();
+            let root_list = RootListWidget::new(&roots, &self.root_list);
+            f.render_widget(PopupWidget::new(root_list, 60, 40), f.size());
+        }
+    }
+
+    /// the deck, that receives the player key events
+    fn deck(&self) -> &Deck {
+        &self.decks[self.active_deck]
+    }
+
+    fn deck_mut(&mut self) -> &mut Deck {
+        &mut self.decks[self.active_deck]
+    }
+
+    /// seeks with clicks on the overviews, scrubs or scratches with drags on the live previews
+    /// and zooms the live previews with the mouse wheel
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        let at = |areas: &[Rect]| {
+            areas.iter().position(|area| {
+                (area.x..area.x + area.width).contains(&mouse.column)
+                    && (area.y..area.y + area.height).contains(&mouse.row)
+            })
+        };
+        let on_preview = at(&self.preview_areas);
+        let on_live_preview = at(&self.live_preview_areas);
+        match (mouse.kind, &mut self.drag) {
+            // drags, that do not start on a live preview, seek like clicks
+            (
+                MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left),
+                None,
+            ) if on_preview.is_some() => {
+                if let Some(index) = on_preview {
+                    let area = self.preview_areas[index];
+                    let progress = (mouse.column - area.x) as f64 / area.width.max(1) as f64;
+                    self.decks[index].send(Message::Seek(progress));
+                }
+            }
+            (MouseEventKind::Down(MouseButton::Left), _) => {
+                if let Some(index) = on_live_preview {
+                    // like touching a vinyl record, which stops it until it is released
+                    let resume = self.vinyl_mode && self.decks[index].state == PlayerState::Playing;
+                    if resume {
+                        self.decks[index].send(Message::TogglePlay);
+                    }
+                    self.drag = Some(Drag {
+                        deck: index,
+                        column: mouse.column,
+                        resume,
+                    });
+                }
+            }
+            (MouseEventKind::Drag(MouseButton::Left), Some(drag)) => {
+                // moving the waveform to the left moves forward in the track. One column holds
+                // two live preview samples, of which there are zoom_level per second.
+                let columns = drag.column as f64 - mouse.column as f64;
+                let seconds = columns * 2. / self.zoom_level as f64;
+                drag.column = mouse.column;
+                self.decks[drag.deck].send(if self.vinyl_mode {
+                    Message::Scratch(seconds)
                 } else {
-                    //TODO: use path object for hashmap
-                    let extension = path.extension().unwrap().to_str().unwrap();
-                    let supported_extensions = ["mp3", "wav", "flac"];
-                    if supported_extensions.contains(&extension) {
-                        let file_path = entry.path().into_os_string().into_string().unwrap();
-                        // let file_name = entry.file_name().into_string().unwrap();
-                        res.push(file_path);
-                    };
+                    Message::Scrub(seconds)
+                });
+            }
+            (MouseEventKind::Up(MouseButton::Left), _) => {
+                if let Some(drag) = self.drag.take() {
+                    if drag.resume {
+                        self.decks[drag.deck].send(Message::TogglePlay);
+                    }
+                }
+            }
+            (MouseEventKind::ScrollUp, _) if on_live_preview.or(on_preview).is_some() => {
+                self.zoom(true)
+            }
+            (MouseEventKind::ScrollDown, _) if on_live_preview.or(on_preview).is_some() => {
+                self.zoom(false)
+            }
+            _ => {}
+        }
+    }
+
+    /// zooms the live previews in or out, between 50 and 500 samples per second
+    fn zoom(&mut self, zoom_in: bool) {
+        self.zoom_level = if zoom_in {
+            (self.zoom_level + 10).min(500)
+        } else {
+            self.zoom_level.saturating_sub(10).max(50)
+        };
+    }
+
+    /// jumps the active deck by the beat jump size. Tracks without a beatgrid skip skip_seconds.
+    fn beat_jump(&mut self, forward: bool) {
+        let beats = BEAT_JUMPS[self.beat_jump];
+        if self.deck().beat_jump(if forward { beats } else { -beats }) {
+            return;
+        }
+        let skip = Time::from(self.skip_seconds);
+        self.deck().send(if forward {
+            Message::SkipForward(skip)
+        } else {
+            Message::SkipBackward(skip)
+        });
+    }
+
+    /// jumps the active deck to a phrase start. Tracks without a beatgrid skip long_skip_seconds.
+    fn phrase_jump(&mut self, forward: bool) {
+        if self.deck().phrase_jump(forward) {
+            return;
+        }
+        let skip = Time::from(self.long_skip_seconds);
+        self.deck().send(if forward {
+            Message::SkipForward(skip)
+        } else {
+            Message::SkipBackward(skip)
+        });
+    }
+
+    /// doubles or halves the beat jump size within BEAT_JUMPS
+    fn resize_beat_jump(&mut self, double: bool) {
+        self.beat_jump = if double {
+            (self.beat_jump + 1).min(BEAT_JUMPS.len() - 1)
+        } else {
+            self.beat_jump.saturating_sub(1)
+        };
+        self.latest_event = format!("Beat jump {} beats", BEAT_JUMPS[self.beat_jump]);
+    }
+
+    /// the quantize resolution in beats, None while quantize is disabled
+    fn quantize_beats(&self) -> Option<f64> {
+        self.quantize
+            .then(|| QUANTIZE_BEATS[self.quantize_resolution])
+    }
+
+    /// sends the quantize setting to all players
+    fn update_quantize(&mut self) {
+        let beats = self.quantize_beats();
+        for deck in &self.decks {
+            deck.set_quantize(beats);
+        }
+        self.latest_event = match beats {
+            Some(beats) => format!("Quantize {} beats", Loop::beats_name(beats)),
+            None => String::from("Quantize off"),
+        };
+    }
+
+    /// snaps a position of the active deck's track to its beatgrid, while quantize is enabled
+    fn quantized(&self, position: f64) -> f64 {
+        let beatgrid = self
+            .deck()
+            .track
+            .as_ref()
+            .and_then(|track| track.beatgrid());
+        match (self.quantize_beats(), beatgrid) {
+            (Some(beats), Some(beatgrid)) => beatgrid.snap(position, beats).max(0.),
+            _ => position,
+        }
+    }
+
+    /// lets all synced decks follow the master deck
+    fn sync_decks(&mut self) {
+        let master = match self.decks[self.master_deck].sync_master() {
+            Some(master) => master,
+            None => return,
+        };
+        let master_deck = self.master_deck;
+        for (index, deck) in self.decks.iter_mut().enumerate() {
+            if index != master_deck && deck.sync {
+                deck.follow(&master);
+            }
+        }
+    }
+
+    /// enables or disables sync of the active deck
+    fn toggle_sync(&mut self) {
+        if self.active_deck == self.master_deck {
+            self.latest_event = format!("Deck {} is the sync master", self.active_deck + 1);
+            return;
+        }
+        let master = self.decks[self.master_deck].sync_master();
+        let deck = self.deck_mut();
+        let sync = !deck.sync;
+        deck.set_sync(sync, master.as_ref());
+        self.latest_event = match (sync, master) {
+            (false, _) => String::from("Sync off"),
+            (true, Some(_)) => format!("Sync to deck {}", self.master_deck + 1),
+            (true, None) => String::from("Sync waits for a master deck with a bpm"),
+        };
+    }
+
+    /// makes the active deck the one, that synced decks follow
+    fn make_master(&mut self) {
+        self.master_deck = self.active_deck;
+        let master = self.decks[self.master_deck].sync_master();
+        // the master can not follow itself
+        if self.deck().sync {
+            self.deck_mut().set_sync(false, master.as_ref());
+        }
+        self.latest_event = format!("Deck {} is the sync master", self.master_deck + 1);
+    }
+
+    /// tempo and beat phase of all decks for the phase meter
+    fn deck_phases(&self) -> Vec<DeckPhase> {
+        let master = self.decks[self.master_deck].sync_master();
+        self.decks
+            .iter()
+            .enumerate()
+            .map(|(index, deck)| DeckPhase {
+                master: index == self.master_deck,
+                sync: deck.sync,
+                bpm: deck.effective_bpm(),
+                beat: deck.beat_index(),
+                offset: match &master {
+                    Some(master) if index != self.master_deck => deck.phase_offset(master),
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+
+    /// changes the settings of the mixer channel of the active deck
+    fn change_channel(&self, change: impl FnOnce(&mut ChannelSettings)) {
+        if let Some(channel) = self
+            .mixer
+            .lock()
+            .unwrap()
+            .channels
+            .get_mut(self.active_deck)
+        {
+            change(channel);
+        }
+    }
+
+    /// the active deck with the tempo of its pitch fader and its range, followed by the key lock,
+    /// quantize, beat jump and vinyl mode settings and the effective bpm of the loaded track
+    fn tempo_display(&self) -> String {
+        let deck = self.deck();
+        let mut tempo = format!(
+            "Deck {} Tempo {:+.2}% (±{:.0}%)",
+            self.active_deck + 1,
+            deck.tempo * 100.,
+            TEMPO_RANGES[deck.tempo_range] * 100.
+        );
+        if deck.key_lock {
+            tempo.push_str(" Key Lock");
+        }
+        if let Some(beats) = self.quantize_beats() {
+            tempo.push_str(&format!(" Quantize {}", Loop::beats_name(beats)));
+        }
+        tempo.push_str(&format!(" Jump {}", BEAT_JUMPS[self.beat_jump]));
+        if self.vinyl_mode {
+            tempo.push_str(" Vinyl");
+        }
+        let bpm = deck
+            .track
+            .as_ref()
+            .and_then(|track| track.meta.read().unwrap().bpm);
+        match bpm {
+            Some(bpm) => format!("{} {:.2} BPM", tempo, bpm.value * (1. + deck.tempo)),
+            None => tempo,
+        }
+    }
+
+    /// moves the pitch fader of the active deck, it stops at the ends of the current range
+    fn set_tempo(&mut self, tempo: f64) {
+        let range = TEMPO_RANGES[self.deck().tempo_range];
+        let tempo = tempo.clamp(-range, range);
+        // the display follows, once the player reports the new tempo
+        self.deck().send(Message::SetTempo(tempo));
+    }
+
+    /// persists a track's analysis results and cues in the library. Tracks, that are still being
+    /// analyzed, get stored once the analysis is done.
+    fn store_track(&self, track: &Track) {
+        if !track.is_analyzed() {
+            return;
+        }
+        if let Some(library) = &self.library {
+            if let Err(err) = library.store(track) {
+                warn!("failed to store {} in library: {:?}", track.file_path, err);
+            }
+        }
+    }
+
+    /// handles keys, while the memory cue editor is open. While a cue is being renamed, all
+    /// characters go into its label. Suggested cues follow the memory cues in the list.
+    fn handle_cue_list_key(&mut self, key: KeyEvent) {
+        let track = match self.deck().track.clone() {
+            Some(track) => track,
+            None => {
+                self.active_event_scope = EventScope::FileList;
+                return;
+            }
+        };
+        let cues = track.mem_cues();
+        let suggested_cues = track.suggested_cues();
+        let len = cues.len() + suggested_cues.len();
+        let selected = self.cue_list.selected;
+        // index of the selected suggestion
+        let suggestion = selected.checked_sub(cues.len());
+        if let Some(label) = self.cue_list.renaming.as_mut() {
+            match key.code {
+                KeyCode::Char(c) => label.push(c),
+                KeyCode::Backspace => {
+                    label.pop();
+                }
+                KeyCode::Enter => {
+                    let label = self.cue_list.renaming.take().unwrap_or_default();
+                    if let Some(cue) = cues.get(selected) {
+                        track.set_mem_cue(
+                            selected,
+                            MemCue {
+                                label,
+                                ..cue.clone()
+                            },
+                        );
+                        self.store_track(&track);
+                    }
+                }
+                KeyCode::Esc => self.cue_list.renaming = None,
+                _ => {}
+            }
+            return;
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.cue_list.select_next(len),
+            KeyCode::Char('k') | KeyCode::Up => self.cue_list.select_previous(len),
+            // jump to the selected cue
+            KeyCode::Enter => {
+                let position = match suggestion {
+                    Some(index) => suggested_cues.get(index).map(|cue| cue.position),
+                    None => cues.get(selected).map(|cue| cue.position),
+                };
+                if let Some(position) = position {
+                    self.deck().send(Message::JumpTo(Time::from(position)));
+                }
+            }
+            // turn the selected suggestion into a memory cue
+            KeyCode::Char('a') => {
+                if let Some(index) = suggestion.filter(|index| *index < suggested_cues.len()) {
+                    if track.accept_suggested_cue(index) {
+                        // the memory cue got appended, so the next suggestion moved here
+                        self.cue_list.selected += 1;
+                        self.cue_list.clamp(len);
+                        self.store_track(&track);
+                    } else {
+                        self.latest_event =
+                            format!("Limit of {} memory cues reached", MEM_CUE_LIMIT);
+                    }
+                }
+            }
+            // accept all suggestions
+            KeyCode::Char('A') => {
+                let mut accepted = 0;
+                while track.accept_suggested_cue(0) {
+                    accepted += 1;
+                }
+                if accepted < suggested_cues.len() {
+                    self.latest_event = format!("Limit of {} memory cues reached", MEM_CUE_LIMIT);
+                }
+                if accepted > 0 {
+                    self.store_track(&track);
+                }
+            }
+            // rename the selected cue
+            KeyCode::Char('r') => {
+                if let Some(cue) = cues.get(selected) {
+                    self.cue_list.renaming = Some(cue.label.clone());
+                }
+            }
+            // cycle through the colors
+            KeyCode::Char('c') => {
+                if let Some(cue) = cues.get(selected) {
+                    let color = cue.color.next();
+                    track.set_mem_cue(
+                        selected,
+                        MemCue {
+                            color,
+                            ..cue.clone()
+                        },
+                    );
+                    self.store_track(&track);
                 }
             }
+            // move the selected cue down
+            KeyCode::Char('J') => {
+                if track.move_mem_cue(selected, selected + 1) {
+                    self.cue_list.selected += 1;
+                    self.store_track(&track);
+                }
+            }
+            // move the selected cue up
+            KeyCode::Char('K') => {
+                if selected > 0 && track.move_mem_cue(selected, selected - 1) {
+                    self.cue_list.selected -= 1;
+                    self.store_track(&track);
+                }
+            }
+            // delete the selected cue or discard the selected suggestion
+            KeyCode::Char('d') | KeyCode::Delete => {
+                let deleted = match suggestion {
+                    Some(index) => track.discard_suggested_cue(index).is_some(),
+                    None => track.delete_mem_cue(selected).is_some(),
+                };
+                if deleted {
+                    self.cue_list.clamp(len - 1);
+                    self.store_track(&track);
+                }
+            }
+            // close the editor
+            KeyCode::Esc | KeyCode::Char('e') => self.active_event_scope = EventScope::FileList,
+            _ => {}
+        }
+    }
+
+    /// handles keys, while the library root editor is open. While a new root is being typed, all
+    /// characters go into its path.
+    fn handle_root_list_key(&mut self, key: KeyEvent) {
+        let len = self.config.roots.len();
+        if let Some(path) = self.root_list.adding.as_mut() {
+            match key.code {
+                KeyCode::Char(c) => path.push(c),
+                KeyCode::Backspace => {
+                    path.pop();
+                }
+                KeyCode::Enter => {
+                    let path = self.root_list.adding.take().unwrap_or_default();
+                    self.add_root(Path::new(path.trim()));
+                }
+                KeyCode::Esc => self.root_list.adding = None,
+                _ => {}
+            }
+            return;
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.root_list.select_next(len),
+            KeyCode::Char('k') | KeyCode::Up => self.root_list.select_previous(len),
+            // start typing a new root
+            KeyCode::Char('a') => self.root_list.adding = Some(String::new()),
+            // remove the selected root
+            KeyCode::Char('d') | KeyCode::Delete => self.remove_root(self.root_list.selected),
+            // close the editor
+            KeyCode::Esc => self.active_event_scope = EventScope::FileList,
+            _ => {}
+        }
+    }
+
+    /// lists the tracks in the given roots on another thread, so slow drives do not block the ui.
+    /// Tracks are restored from the library and new or changed files are queued for analysis.
+    /// Tracks, that are already listed, are skipped.
+    fn scan(&mut self, roots: Vec<PathBuf>) {
+        let (analyzer_event_out, analyzer_pool) =
+            match (&self.analyzer_event_out, &self.analyzer_pool) {
+                (Some(analyzer_event_out), Some(analyzer_pool)) => {
+                    (analyzer_event_out.clone(), analyzer_pool.clone())
+                }
+                // the app is not running yet
+                _ => return,
+            };
+        let scanner = match Scanner::new(&self.config) {
+            Ok(scanner) => scanner,
+            Err(err) => {
+                self.latest_event = format!("Failed to scan library: {}", err);
+                return;
+            }
+        };
+        let listed: HashSet<String> = self
+            .tracks
+            .values()
+            .iter()
+            .map(|track| track.file_path.clone())
+            .collect();
+        let library = self.library.clone();
+        spawn(move || {
+            for file in scanner.scan_roots(&roots) {
+                if listed.contains(&file) {
+                    continue;
+                }
+                match library.as_ref().and_then(|library| library.load(&file)) {
+                    Some(track) => analyzer_event_out
+                        .send(analyzer::Event::NewTrack(Arc::new(track)))
+                        .unwrap(),
+                    None => analyzer_pool.analyze(file),
+                }
+            }
+        });
+    }
+
+    /// adds a library root, writes it to the config file and lists its tracks
+    fn add_root(&mut self, root: &Path) {
+        if root.as_os_str().is_empty() {
+            return;
+        }
+        let root = match std::env::current_dir() {
+            Ok(current_dir) => absolute_root(root, &current_dir),
+            Err(err) => {
+                self.latest_event = format!("Invalid library root: {}", err);
+                return;
+            }
+        };
+        if !root.is_dir() {
+            self.latest_event = format!("Not a directory: {}", root.display());
+            return;
+        }
+        if self.config.roots.contains(&root) {
+            self.latest_event = format!("Already a library root: {}", root.display());
+            return;
+        }
+        self.config.roots.push(root.clone());
+        self.root_list.selected = self.config.roots.len() - 1;
+        self.latest_event = format!("Added library root: {}", root.display());
+        self.store_roots(|roots| {
+            if !roots.contains(&root) {
+                roots.push(root.clone());
+            }
+        });
+        self.scan(vec![root]);
+    }
+
+    /// removes a library root from the config file and its tracks from the list. Tracks, that
+    /// are in another root as well, stay listed.
+    fn remove_root(&mut self, index: usize) {
+        if index >= self.config.roots.len() {
+            return;
+        }
+        let root = self.config.roots.remove(index);
+        self.root_list.clamp(self.config.roots.len());
+        let config = &self.config;
+        self.tracks
+            .retain(|track| config.contains(&track.file_path));
+        self.latest_event = format!("Removed library root: {}", root.display());
+        self.store_roots(|roots| roots.retain(|other| *other != root));
+    }
+
+    /// applies a change of the roots to the config file. The file is read again, so options,
+    /// that were only given on the command line, are not written to it. Without a config file,
+    /// the default roots are only kept, if they are still in use.
+    fn store_roots(&mut self, change: impl FnOnce(&mut Vec<PathBuf>)) {
+        let config_path = match &self.config_path {
+            Some(config_path) => config_path,
+            None => {
+                self.latest_event = String::from("No config directory, roots are not saved");
+                return;
+            }
+        };
+        let result = Config::load_file(config_path).and_then(|config| {
+            let mut config = config.unwrap_or_else(|| {
+                let mut config = Config::default();
+                config.roots.retain(|root| self.config.roots.contains(root));
+                config
+            });
+            change(&mut config.roots);
+            config.store(config_path)
+        });
+        if let Err(err) = result {
+            self.latest_event = format!("Failed to store config: {}", err);
+        }
+    }
+
+    /// jumps to the hot cue in a slot of the loaded track, or sets it at the playhead, if the
+    /// slot is empty
+    fn trigger_hot_cue(&mut self, slot: usize) {
+        let track = match self.deck().track.clone() {
+            Some(track) => track,
+            None => return,
+        };
+        match track.hot_cue(slot) {
+            Some(cue) => {
+                self.deck().send(Message::JumpTo(Time::from(cue.position)));
+                self.latest_event = format!("Hot cue {}", HotCue::slot_name(slot));
+            }
+            None => {
+                let position = match self.deck().position_in_seconds() {
+                    Some(position) => position,
+                    None => return,
+                };
+                track.set_hot_cue(slot, HotCue::new(slot, self.quantized(position)));
+                self.store_track(&track);
+                self.latest_event = format!("Set hot cue {}", HotCue::slot_name(slot));
+            }
+        }
+    }
+
+    /// empties a hot cue slot of the loaded track
+    fn delete_hot_cue(&mut self, slot: usize) {
+        if slot >= HOT_CUE_SLOTS {
+            return;
+        }
+        if let Some(track) = self.deck().track.clone() {
+            if track.delete_hot_cue(slot).is_some() {
+                self.store_track(&track);
+                self.latest_event = format!("Deleted hot cue {}", HotCue::slot_name(slot));
+            }
+        }
+    }
+
+    /// loops loop_beats beats from the playhead of the loaded track
+    fn auto_loop(&mut self) {
+        let deck = self.deck();
+        let (track, start) = match (deck.track.clone(), deck.position_in_seconds()) {
+            (Some(track), Some(start)) => (track, self.quantized(start)),
+            _ => return,
+        };
+        let loop_beats = deck.loop_beats;
+        match track.position_after_beats(start, loop_beats) {
+            Some(end) => {
+                self.deck()
+                    .send(Message::SetLoop(Time::from(start), Time::from(end)));
+                self.latest_event = format!("Loop {} beats", Loop::beats_name(loop_beats));
+            }
+            None => self.latest_event = String::from("Auto loop needs a bpm"),
+        }
+    }
+
+    /// multiplies the auto loop length and the length of the current loop, within the limits of
+    /// MIN_LOOP_BEATS and MAX_LOOP_BEATS
+    fn resize_loop(&mut self, factor: f64) {
+        let beats = self.deck().loop_beats * factor;
+        if !(MIN_LOOP_BEATS..=MAX_LOOP_BEATS).contains(&beats) {
+            return;
+        }
+        self.deck_mut().loop_beats = beats;
+        let has_loop = matches!(
+            &(*self.deck().player_loop.lock().unwrap()),
+            Some(LoopMarker { end: Some(_), .. })
+        );
+        if has_loop {
+            self.deck().send(Message::ResizeLoop(factor));
+        }
+        self.latest_event = format!("Loop length: {} beats", Loop::beats_name(beats));
+    }
+
+    /// saves the current loop of the player with the loaded track
+    fn save_loop(&mut self) {
+        let saved_loop = match &(*self.deck().player_loop.lock().unwrap()) {
+            Some(LoopMarker {
+                start,
+                end: Some(end),
+                ..
+            }) => Loop::new(start.get_time_in_seconds(), end.get_time_in_seconds()),
+            _ => return,
+        };
+        if let Some(track) = self.deck().track.clone() {
+            if track.add_loop(saved_loop) {
+                self.store_track(&track);
+                self.latest_event = String::from("Saved loop");
+            }
+        }
+    }
+
+    /// loops the first saved loop, that starts after the playhead, or the first one of the track
+    fn next_saved_loop(&mut self) {
+        let deck = self.deck();
+        let (track, now) = match (deck.track.clone(), deck.position_in_seconds()) {
+            (Some(track), Some(now)) => (track, now),
+            _ => return,
+        };
+        let loops = track.loops();
+        let next = loops
+            .iter()
+            .find(|saved_loop| saved_loop.start > now)
+            .or_else(|| loops.first());
+        if let Some(saved_loop) = next {
+            self.deck().send(Message::SetLoop(
+                Time::from(saved_loop.start),
+                Time::from(saved_loop.end),
+            ));
+        }
+    }
+
+    /// the export directory. Without one, the failed action is shown, e.g. "Export".
+    fn require_export_dir(&mut self, action: &str) -> Option<PathBuf> {
+        if self.export_dir.is_none() {
+            self.latest_event = format!("{} failed: no export directory", action);
+        }
+        self.export_dir.clone()
+    }
+
+    /// exports all tracks and playlists as rekordbox.xml into the export directory
+    fn export_rekordbox_xml(&mut self) {
+        let path = match self.require_export_dir("Export") {
+            Some(export_dir) => export_dir.join("rekordbox.xml"),
+            None => return,
+        };
+        let tracks: Vec<Arc<Track>> = self.tracks.values().iter().cloned().collect();
+        self.latest_event = match rekordbox_xml::export(&tracks, &self.playlists, &path) {
+            Ok(()) => format!("Exported {} tracks to {}", tracks.len(), path.display()),
+            Err(err) => format!("Export failed: {}", err),
+        };
+    }
+
+    /// exports all tracks and playlists for Pioneer DJ players into the export directory
+    fn export_rekordbox_device(&mut self) {
+        self.spawn_export(rekordbox_device::export);
+    }
+
+    /// exports all tracks and playlists as Engine Library into the export directory
+    fn export_engine(&mut self) {
+        self.spawn_export(engine::export);
+    }
+
+    /// runs a device export in the background, because it copies all tracks. The result is shown,
+    /// once it is done.
+    fn spawn_export(
+        &mut self,
+        export: fn(&[Arc<Track>], &[PlaylistNode], &Path) -> Result<(), InteropError>,
+    ) {
+        if self.export_result_in.is_some() {
+            self.latest_event = String::from("Export failed: another export is running");
+            return;
+        }
+        let export_dir = match self.require_export_dir("Export") {
+            Some(export_dir) => export_dir,
+            None => return,
+        };
+        let tracks: Vec<Arc<Track>> = self.tracks.values().iter().cloned().collect();
+        let playlists = self.playlists.clone();
+        let (export_result_out, export_result_in) = channel();
+        self.export_result_in = Some(export_result_in);
+        self.latest_event = format!(
+            "Exporting {} tracks to {}",
+            tracks.len(),
+            export_dir.display()
+        );
+        spawn(move || {
+            let result = match export(&tracks, &playlists, &export_dir) {
+                Ok(()) => format!(
+                    "Exported {} tracks to {}",
+                    tracks.len(),
+                    export_dir.display()
+                ),
+                Err(err) => format!("Export failed: {}", err),
+            };
+            // the app may have been closed
+            let _ = export_result_out.send(result);
+        });
+    }
+
+    /// writes cues, beatgrids and overviews of all tracks into their Serato tags
+    fn export_serato(&mut self) {
+        let tracks: Vec<Arc<Track>> = self.tracks.values().iter().cloned().collect();
+        let mut failed = 0;
+        for track in &tracks {
+            if let Err(err) = serato::write(track) {
+                warn!(
+                    "failed to write Serato tags of {}: {}",
+                    track.file_path, err
+                );
+                failed += 1;
+            }
+        }
+        self.latest_event = if failed == 0 {
+            format!("Wrote Serato tags of {} tracks", tracks.len())
+        } else {
+            format!(
+                "Wrote Serato tags of {} tracks, {} failed",
+                tracks.len() - failed,
+                failed
+            )
+        };
+    }
+
+    /// imports rekordbox.xml from the export directory
+    fn import_rekordbox_xml(&mut self) {
+        let path = match self.require_export_dir("Import") {
+            Some(export_dir) => export_dir.join("rekordbox.xml"),
+            None => return,
+        };
+        match rekordbox_xml::import(&path) {
+            Ok(import) => {
+                self.latest_event = format!(
+                    "Imported {} tracks from {}",
+                    import.tracks.len(),
+                    path.display()
+                );
+                self.apply_import(import, "rekordbox");
+            }
+            Err(err) => self.latest_event = format!("Import failed: {}", err),
+        }
+    }
+
+    /// exports all tracks and playlists as collection.nml into the export directory
+    fn export_traktor_nml(&mut self) {
+        let path = match self.require_export_dir("Export") {
+            Some(export_dir) => export_dir.join("collection.nml"),
+            None => return,
         };
-        Ok(res)
+        let tracks: Vec<Arc<Track>> = self.tracks.values().iter().cloned().collect();
+        self.latest_event = match traktor_nml::export(&tracks, &self.playlists, &path) {
+            Ok(()) => format!("Exported {} tracks to {}", tracks.len(), path.display()),
+            Err(err) => format!("Export failed: {}", err),
+        };
+    }
+
+    /// imports collection.nml from the export directory
+    fn import_traktor_nml(&mut self) {
+        let path = match self.require_export_dir("Import") {
+            Some(export_dir) => export_dir.join("collection.nml"),
+            None => return,
+        };
+        match traktor_nml::import(&path) {
+            Ok(import) => {
+                self.latest_event = format!(
+                    "Imported {} tracks from {}",
+                    import.tracks.len(),
+                    path.display()
+                );
+                self.apply_import(import, "traktor");
+            }
+            Err(err) => self.latest_event = format!("Import failed: {}", err),
+        }
+    }
+
+    /// applies imported tracks and puts the imported playlists into a folder of the given name,
+    /// replacing the one from a previous import. Tracks, that are not in the track list yet, get
+    /// their imported data, once they are scanned.
+    fn apply_import(&mut self, import: Import, folder_name: &str) {
+        for imported in import.tracks {
+            self.pending_imports.insert(imported);
+        }
+        let tracks: Vec<Arc<Track>> = self.tracks.values().iter().cloned().collect();
+        for track in tracks {
+            if let Some(imported) = self.pending_imports.take(&track.file_path) {
+                imported.apply_to(&track);
+                self.store_track(&track);
+            }
+        }
+        if import.playlists.is_empty() {
+            return;
+        }
+        replace_folder(&mut self.playlists, folder_name, import.playlists);
+        if let Some(library) = &self.library {
+            if let Err(err) = library.store_playlists(&self.playlists) {
+                warn!("failed to store playlists: {:?}", err);
+            }
+        }
     }
 }
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+
+//------------------------------------------------------------------//
+//                             Beatgrid                             //
+//------------------------------------------------------------------//
+
+/// number of beats in a bar
+pub const BEATS_PER_BAR: i64 = 4;
+/// number of bars in a phrase
+pub const BARS_PER_PHRASE: i64 = 8;
+
+/// A beatgrid maps between time and beats. Beat 0 is the first downbeat, beats before it have
+/// negative indices. The tempo is constant between tempo anchors.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Beatgrid {
+    /// position of the first downbeat in seconds
+    pub first_downbeat: f64,
+    /// tempo at the first downbeat in beats per minute
+    pub bpm: f64,
+    /// tempo changes after the first downbeat, ordered by beat index
+    pub anchors: Vec<TempoAnchor>,
+}
+
+/// A tempo change at a beat of the grid
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoAnchor {
+    /// index of the beat, at which the tempo changes
+    pub beat: i64,
+    /// position of that beat in seconds
+    pub position: f64,
+    /// tempo from this beat on in beats per minute
+    pub bpm: f64,
+}
+
+/// A single beat of a beatgrid with its musical position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Beat {
+    /// beat index relative to the first downbeat
+    pub index: i64,
+    /// position in seconds
+    pub position: f64,
+    /// bar number, starting at 1 for the bar of the first downbeat
+    pub bar: i64,
+    /// beat number within the bar, from 1 to BEATS_PER_BAR
+    pub beat_in_bar: i64,
+    /// phrase number, starting at 1
+    pub phrase: i64,
+}
+
+impl Beat {
+    fn new(index: i64, position: f64) -> Self {
+        let bar = index.div_euclid(BEATS_PER_BAR) + 1;
+        Self {
+            index,
+            position,
+            bar,
+            beat_in_bar: index.rem_euclid(BEATS_PER_BAR) + 1,
+            phrase: index.div_euclid(BEATS_PER_BAR * BARS_PER_PHRASE) + 1,
+        }
+    }
+
+    /// whether this beat is the first beat of a bar
+    pub fn is_downbeat(&self) -> bool {
+        self.beat_in_bar == 1
+    }
+
+    /// whether this beat is the first beat of a phrase
+    pub fn is_phrase_start(&self) -> bool {
+        self.index.rem_euclid(BEATS_PER_BAR * BARS_PER_PHRASE) == 0
+    }
+}
+
+impl Beatgrid {
+    /// creates a grid with constant tempo
+    pub fn new(first_downbeat: f64, bpm: f64) -> Self {
+        Self {
+            first_downbeat,
+            bpm,
+            anchors: vec![],
+        }
+    }
+
+    /// adds a tempo change at the given beat. Anchors after that beat are removed.
+    pub fn add_anchor(&mut self, beat: i64, bpm: f64) {
+        let position = self.beat_position(beat as f64);
+        self.anchors.retain(|anchor| anchor.beat < beat);
+        self.anchors.push(TempoAnchor {
+            beat,
+            position,
+            bpm,
+        });
+    }
+
+    /// returns (beat index, position, bpm) of the segment, that contains the given beat
+    fn segment_for_beat(&self, beat: f64) -> (i64, f64, f64) {
+        self.anchors
+            .iter()
+            .rev()
+            .find(|anchor| anchor.beat as f64 <= beat)
+            .map(|anchor| (anchor.beat, anchor.position, anchor.bpm))
+            .unwrap_or((0, self.first_downbeat, self.bpm))
+    }
+
+    /// returns (beat index, position, bpm) of the segment, that contains the given time
+    fn segment_for_time(&self, seconds: f64) -> (i64, f64, f64) {
+        self.anchors
+            .iter()
+            .rev()
+            .find(|anchor| anchor.position <= seconds)
+            .map(|anchor| (anchor.beat, anchor.position, anchor.bpm))
+            .unwrap_or((0, self.first_downbeat, self.bpm))
+    }
+
+    /// the tempo at a given time in beats per minute
+    pub fn bpm_at(&self, seconds: f64) -> f64 {
+        self.segment_for_time(seconds).2
+    }
+
+    /// length of one beat at a given time in seconds
+    pub fn beat_length_at(&self, seconds: f64) -> f64 {
+        60. / self.bpm_at(seconds)
+    }
+
+    /// the fractional beat index at a given time
+    pub fn beat_index_at(&self, seconds: f64) -> f64 {
+        let (beat, position, bpm) = self.segment_for_time(seconds);
+        beat as f64 + (seconds - position) * bpm / 60.
+    }
+
+    /// the position of a (fractional) beat index in seconds
+    pub fn beat_position(&self, index: f64) -> f64 {
+        let (beat, position, bpm) = self.segment_for_beat(index);
+        position + (index - beat as f64) * 60. / bpm
+    }
+
+    /// the position of the multiple of a number of beats, that is closest to the given time,
+    /// e.g. the closest bar for 4 beats
+    pub fn snap(&self, seconds: f64, beats: f64) -> f64 {
+        self.beat_position((self.beat_index_at(seconds) / beats).round() * beats)
+    }
+
+    /// the beat, that is closest to the given time
+    pub fn nearest_beat(&self, seconds: f64) -> Beat {
+        self.beat(self.beat_index_at(seconds).round() as i64)
+    }
+
+    /// the beat with the given index
+    pub fn beat(&self, index: i64) -> Beat {
+        Beat::new(index, self.beat_position(index as f64))
+    }
+
+    /// all beats in the time range [from, to]
+    pub fn beats_between(&self, from: f64, to: f64) -> Vec<Beat> {
+        let first = self.beat_index_at(from).ceil() as i64;
+        let last = self.beat_index_at(to).floor() as i64;
+        (first..=last).map(|index| self.beat(index)).collect()
+    }
+
+    /// the bar number at a given time, starting at 1 for the bar of the first downbeat
+    pub fn bar_at(&self, seconds: f64) -> i64 {
+        (self.beat_index_at(seconds).floor() as i64).div_euclid(BEATS_PER_BAR) + 1
+    }
+
+    /// the phrase number at a given time, starting at 1
+    pub fn phrase_at(&self, seconds: f64) -> i64 {
+        (self.beat_index_at(seconds).floor() as i64).div_euclid(BEATS_PER_BAR * BARS_PER_PHRASE) + 1
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+use tui::style::Color;
+
+//------------------------------------------------------------------//
+//                               Cues                               //
+//------------------------------------------------------------------//
+
+/// number of hot cue slots per track
+pub const HOT_CUE_SLOTS: usize = 8;
+
+/// default colors of the hot cue slots, as most DJ software assigns them
+const HOT_CUE_COLORS: [CueColor; HOT_CUE_SLOTS] = [
+    CueColor::new(0x28, 0xe2, 0x14),
+    CueColor::new(0x30, 0x5a, 0xff),
+    CueColor::new(0xff, 0x12, 0x7b),
+    CueColor::new(0x00, 0xe0, 0xff),
+    CueColor::new(0xe0, 0x64, 0x1b),
+    CueColor::new(0xe6, 0x28, 0x28),
+    CueColor::new(0xb4, 0xbe, 0x04),
+    CueColor::new(0xaa, 0x72, 0xff),
+];
+
+/// The color of a cue
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CueColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl CueColor {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// the default color of a hot cue slot
+    pub fn for_slot(slot: usize) -> Self {
+        HOT_CUE_COLORS[slot % HOT_CUE_SLOTS]
+    }
+
+    /// the next color of the palette, colors outside of the palette continue with the first one
+    pub fn next(&self) -> Self {
+        match HOT_CUE_COLORS.iter().position(|color| color == self) {
+            Some(index) => CueColor::for_slot(index + 1),
+            None => CueColor::for_slot(0),
+        }
+    }
+}
+
+impl From<CueColor> for Color {
+    fn from(color: CueColor) -> Self {
+        Color::Rgb(color.r, color.g, color.b)
+    }
+}
+
+/// A cue in one of the numbered hot cue slots of a track
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HotCue {
+    /// position in seconds
+    pub position: f64,
+    pub label: String,
+    pub color: CueColor,
+}
+
+impl HotCue {
+    /// a cue without label in the default color of the slot
+    pub fn new(slot: usize, position: f64) -> Self {
+        Self {
+            position,
+            label: String::new(),
+            color: CueColor::for_slot(slot),
+        }
+    }
+
+    /// the name of a slot as shown in the UI, slots are numbered from 1
+    pub fn slot_name(slot: usize) -> String {
+        (slot + 1).to_string()
+    }
+}
+
+/// maximum number of memory cues per track
+pub const MEM_CUE_LIMIT: usize = 100;
+
+/// A cue, that is not bound to a hot cue slot. Memory cues keep the order of the cue list.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MemCue {
+    /// position in seconds
+    pub position: f64,
+    pub label: String,
+    pub color: CueColor,
+}
+
+impl MemCue {
+    /// a cue without label in the first color of the palette
+    pub fn new(position: f64) -> Self {
+        Self {
+            position,
+            label: String::new(),
+            color: CueColor::for_slot(0),
+        }
+    }
+}
+
+/// The part of a track, that a suggested cue marks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CueKind {
+    FirstDownbeat,
+    Drop,
+    Breakdown,
+    Outro,
+}
+
+impl CueKind {
+    /// the label of accepted cues
+    pub fn label(&self) -> &'static str {
+        match self {
+            CueKind::FirstDownbeat => "First downbeat",
+            CueKind::Drop => "Drop",
+            CueKind::Breakdown => "Breakdown",
+            CueKind::Outro => "Outro",
+        }
+    }
+
+    pub fn color(&self) -> CueColor {
+        match self {
+            CueKind::FirstDownbeat => CueColor::for_slot(0),
+            CueKind::Drop => CueColor::for_slot(5),
+            CueKind::Breakdown => CueColor::for_slot(1),
+            CueKind::Outro => CueColor::for_slot(4),
+        }
+    }
+}
+
+/// A cue, that the analyzer suggests from the structure of a track. It becomes a memory cue,
+/// once the user accepts it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SuggestedCue {
+    /// position in seconds
+    pub position: f64,
+    pub kind: CueKind,
+}
+
+impl SuggestedCue {
+    pub fn new(position: f64, kind: CueKind) -> Self {
+        Self { position, kind }
+    }
+
+    /// the memory cue, that the suggestion becomes, labeled and colored by its kind
+    pub fn to_mem_cue(&self) -> MemCue {
+        MemCue {
+            position: self.position,
+            label: String::from(self.kind.label()),
+            color: self.kind.color(),
+        }
+    }
+}
+
+//------------------------------------------------------------------//
+//                              Loops                               //
+//------------------------------------------------------------------//
+
+/// shortest auto loop in beats
+pub const MIN_LOOP_BEATS: f64 = 1. / 32.;
+/// longest auto loop in beats
+pub const MAX_LOOP_BEATS: f64 = 32.;
+
+/// A loop, that is saved with a track
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Loop {
+    /// loop in point in seconds
+    pub start: f64,
+    /// loop out point in seconds
+    pub end: f64,
+}
+
+impl Loop {
+    pub fn new(start: f64, end: f64) -> Self {
+        Self { start, end }
+    }
+
+    /// length of the loop in seconds
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+
+    /// a loop length in beats as shown in the UI, e.g. 1/4 or 8
+    pub fn beats_name(beats: f64) -> String {
+        if beats >= 1. {
+            format!("{}", beats)
+        } else {
+            format!("1/{}", (1. / beats).round())
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use log::warn;
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex,
+};
+use std::thread::JoinHandle;
+
+use symphonia::core::units::Time;
+
+use super::{
+    beatgrid::{BARS_PER_PHRASE, BEATS_PER_BAR},
+    track::Track,
+};
+use crate::core::{
+    output::OutputBackend,
+    player::{Event, LoopMarker, Message, Player, PlayerState, Quantize, TimeMarker},
+};
+
+/// number of decks, if nothing else is configured
+pub const DEFAULT_DECKS: usize = 2;
+/// sizes of beat jumps in beats
+pub const BEAT_JUMPS: [f64; 6] = [1., 2., 4., 8., 16., 32.];
+/// tempo correction of a synced deck per beat of phase offset to the master deck
+const PHASE_CORRECTION: f64 = 0.1;
+/// limit of the phase correction, so drifting decks get back in phase smoothly
+const MAX_PHASE_CORRECTION: f64 = 0.02;
+/// synced decks only get a new tempo, if it differs by more than this
+const SYNC_TOLERANCE: f64 = 0.0001;
+
+//------------------------------------------------------------------//
+//                            SyncMaster                            //
+//------------------------------------------------------------------//
+
+/// Tempo and beat phase of the deck, that synced decks follow
+#[derive(Clone, Copy, Debug)]
+pub struct SyncMaster {
+    /// effective tempo in beats per minute
+    pub bpm: f64,
+    /// fractional beat index at the playhead, None without a beatgrid
+    pub beat: Option<f64>,
+    /// whether the master deck is playing
+    pub playing: bool,
+}
+
+//------------------------------------------------------------------//
+//                               Deck                               //
+//------------------------------------------------------------------//
+
+/// A player and everything the app keeps track of about it
+pub struct Deck {
+    /// current player position
+    pub position: Arc<Mutex<Option<TimeMarker>>>,
+    /// current loop points of the player
+    pub player_loop: Arc<Mutex<Option<LoopMarker>>>,
+    /// the track, that was last loaded into the player
+    pub track: Option<Arc<Track>>,
+    /// length of auto loops in beats
+    pub loop_beats: f64,
+    /// playback speed relative to the original tempo, as reported by the player
+    pub tempo: f64,
+    /// index of the pitch fader range in TEMPO_RANGES
+    pub tempo_range: usize,
+    /// whether the player keeps the original key at other tempos, as reported by the player
+    pub key_lock: bool,
+    /// state of the player, as reported by the player
+    pub state: PlayerState,
+    /// whether the deck follows the tempo and beat phase of the master deck
+    pub sync: bool,
+    /// whether the beat phase was matched since sync was enabled or the playback started
+    aligned: bool,
+    /// the tempo last sent because of sync
+    sync_tempo: Option<f64>,
+    /// messages to the player, None until the player is spawned
+    messages_out: Option<Sender<Message>>,
+    /// events from the player
+    events_in: Option<Receiver<Event>>,
+}
+
+impl Default for Deck {
+    fn default() -> Self {
+        Self {
+            position: Arc::new(Mutex::new(None)),
+            player_loop: Arc::new(Mutex::new(None)),
+            track: None,
+            loop_beats: 4.,
+            tempo: 0.,
+            tempo_range: 0,
+            key_lock: false,
+            state: PlayerState::Unloaded,
+            sync: false,
+            aligned: false,
+            sync_tempo: None,
+            messages_out: None,
+            events_in: None,
+        }
+    }
+}
+
+impl Deck {
+    /// spawns the player of the deck, which writes to the given output
+    pub fn spawn(&mut self, output_backend: OutputBackend) -> JoinHandle<()> {
+        let (events_out, events_in) = channel::<Event>();
+        let (messages_out, messages_in) = channel::<Message>();
+        self.messages_out = Some(messages_out);
+        self.events_in = Some(events_in);
+        Player::spawn(
+            Arc::clone(&self.position),
+            Arc::clone(&self.player_loop),
+            output_backend,
+            messages_in,
+            events_out,
+        )
+    }
+
+    /// sends a message to the player
+    pub fn send(&self, message: Message) {
+        if let Some(messages_out) = &self.messages_out {
+            if messages_out.send(message).is_err() {
+                warn!("player stopped");
+            }
+        }
+    }
+
+    /// loads a track into the player
+    pub fn load(&mut self, track: Arc<Track>) {
+        self.send(Message::Load(track.file_path.clone()));
+        self.track = Some(track);
+    }
+
+    /// makes the player align positions to the beatgrid of the loaded track at a resolution in
+    /// beats, or stop aligning them with None. Tracks without a beatgrid are not quantized.
+    pub fn set_quantize(&self, beats: Option<f64>) {
+        let beatgrid = self.track.as_ref().and_then(|track| track.beatgrid());
+        let quantize = match (beats, beatgrid) {
+            (Some(beats), Some(beatgrid)) => Some(Quantize { beatgrid, beats }),
+            _ => None,
+        };
+        self.send(Message::SetQuantize(quantize));
+    }
+
+    /// moves the playhead by a number of beats, negative ones jump backwards. The position
+    /// within the beat stays, so a playing deck stays in phase. Returns false without a beatgrid.
+    pub fn beat_jump(&self, beats: f64) -> bool {
+        let beatgrid = self.track.as_ref().and_then(|track| track.beatgrid());
+        let (beatgrid, position) = match (beatgrid, self.position_in_seconds()) {
+            (Some(beatgrid), Some(position)) => (beatgrid, position),
+            _ => return false,
+        };
+        let target = beatgrid.beat_position(beatgrid.beat_index_at(position) + beats);
+        self.send(Message::JumpTo(Time::from(target.max(0.))));
+        true
+    }
+
+    /// moves the playhead to the start of the next phrase, or backwards to the start of the
+    /// current one. Within the first beat of a phrase, it goes back to the previous one, so
+    /// repeated jumps keep going back. Returns false without a beatgrid.
+    pub fn phrase_jump(&self, forward: bool) -> bool {
+        let beatgrid = self.track.as_ref().and_then(|track| track.beatgrid());
+        let (beatgrid, position) = match (beatgrid, self.position_in_seconds()) {
+            (Some(beatgrid), Some(position)) => (beatgrid, position),
+            _ => return false,
+        };
+        let phrase_beats = (BEATS_PER_BAR * BARS_PER_PHRASE) as f64;
+        let beat = beatgrid.beat_index_at(position);
+        let phrase_start = (beat / phrase_beats).floor() * phrase_beats;
+        let target = if forward {
+            phrase_start + phrase_beats
+        } else if beat - phrase_start < 1. {
+            phrase_start - phrase_beats
+        } else {
+            phrase_start
+        };
+        let target = beatgrid.beat_position(target);
+        self.send(Message::JumpTo(Time::from(target.max(0.))));
+        true
+    }
+
+    /// the playhead position in seconds
+    pub fn position_in_seconds(&self) -> Option<f64> {
+        (*self.position.lock().unwrap())
+            .as_ref()
+            .map(|tm| tm.get_time_in_seconds())
+    }
+
+    /// applies all events, that the player sent since the last call
+    pub fn handle_events(&mut self) {
+        let events: Vec<Event> = match &self.events_in {
+            Some(events_in) => events_in.try_iter().collect(),
+            None => return,
+        };
+        for event in events {
+            match event {
+                Event::TempoChanged(tempo) => self.tempo = tempo,
+                Event::KeyLockChanged(key_lock) => self.key_lock = key_lock,
+                Event::StateChanged(state) => {
+                    if state == PlayerState::Playing {
+                        self.aligned = false;
+                    }
+                    self.state = state;
+                }
+            }
+        }
+    }
+
+    //------------------------------------------------------------------//
+    //                               Sync                               //
+    //------------------------------------------------------------------//
+
+    /// the original tempo of the loaded track at the playhead in beats per minute
+    pub fn bpm(&self) -> Option<f64> {
+        let track = self.track.as_ref()?;
+        match (track.beatgrid(), self.position_in_seconds()) {
+            (Some(beatgrid), Some(position)) => Some(beatgrid.bpm_at(position)),
+            _ => track.meta.read().unwrap().bpm.map(|bpm| bpm.value),
+        }
+    }
+
+    /// the tempo at the current pitch in beats per minute
+    pub fn effective_bpm(&self) -> Option<f64> {
+        self.bpm().map(|bpm| bpm * (1. + self.tempo))
+    }
+
+    /// the fractional beat index at the playhead
+    pub fn beat_index(&self) -> Option<f64> {
+        let beatgrid = self.track.as_ref()?.beatgrid()?;
+        Some(beatgrid.beat_index_at(self.position_in_seconds()?))
+    }
+
+    /// the tempo and beat phase of this deck for decks, that sync to it
+    pub fn sync_master(&self) -> Option<SyncMaster> {
+        Some(SyncMaster {
+            bpm: self.effective_bpm()?,
+            beat: self.beat_index(),
+            playing: self.state == PlayerState::Playing,
+        })
+    }
+
+    /// the tempo, that matches the master's bpm, and the number of this deck's beats per master
+    /// beat. Tracks are matched at half or double time, if that is closer to their original
+    /// tempo, e.g. 87 BPM to 174 BPM.
+    pub fn matched_tempo(&self, master: &SyncMaster) -> Option<(f64, f64)> {
+        let bpm = self.bpm()?;
+        [0.5, 1., 2.]
+            .iter()
+            .map(|factor| (master.bpm * factor / bpm - 1., *factor))
+            .min_by(|(a, _), (b, _)| a.abs().total_cmp(&b.abs()))
+    }
+
+    /// offset of this deck's beat phase to the master's in beats, between -0.5 and 0.5.
+    /// Positive offsets mean, that this deck is ahead.
+    pub fn phase_offset(&self, master: &SyncMaster) -> Option<f64> {
+        let (_, factor) = self.matched_tempo(master)?;
+        let offset = (self.beat_index()? - master.beat? * factor).rem_euclid(1.);
+        Some(if offset >= 0.5 { offset - 1. } else { offset })
+    }
+
+    /// matches the tempo to the master. While both decks play, the beat phase is matched by a
+    /// jump once and then kept by bending the tempo.
+    pub fn follow(&mut self, master: &SyncMaster) {
+        let (tempo, _) = match self.matched_tempo(master) {
+            Some(matched) => matched,
+            None => return,
+        };
+        let offset = match (self.state, master.playing) {
+            (PlayerState::Playing, true) => self.phase_offset(master),
+            _ => None,
+        };
+        let correction = match offset {
+            Some(offset) if !self.aligned => {
+                self.aligned = true;
+                let beatgrid = self.track.as_ref().and_then(|track| track.beatgrid());
+                if let (Some(beatgrid), Some(position)) = (beatgrid, self.position_in_seconds()) {
+                    let target = position - offset * beatgrid.beat_length_at(position);
+                    self.send(Message::JumpTo(Time::from(target.max(0.))));
+                }
+                0.
+            }
+            Some(offset) => {
+                (-offset * PHASE_CORRECTION).clamp(-MAX_PHASE_CORRECTION, MAX_PHASE_CORRECTION)
+            }
+            None => 0.,
+        };
+        let tempo = tempo + correction;
+        let changed = self.sync_tempo.map_or(true, |sync_tempo| {
+            (sync_tempo - tempo).abs() > SYNC_TOLERANCE
+        });
+        if changed {
+            self.sync_tempo = Some(tempo);
+            self.send(Message::SetTempo(tempo));
+        }
+    }
+
+    /// enables or disables sync. Disabled decks keep the matched tempo without the phase
+    /// correction.
+    pub fn set_sync(&mut self, sync: bool, master: Option<&SyncMaster>) {
+        self.sync = sync;
+        self.aligned = false;
+        self.sync_tempo = None;
+        if !sync {
+            if let Some((tempo, _)) = master.and_then(|master| self.matched_tempo(master)) {
+                self.send(Message::SetTempo(tempo));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::view::model::beatgrid::Beatgrid;
+    use symphonia::core::{codecs::CodecParameters, units::TimeBase};
+
+    /// a deck with a track of constant tempo, that starts with a downbeat, at the given beat
+    fn deck(bpm: f64, beat: f64) -> Deck {
+        let mut codec_params = CodecParameters::new();
+        codec_params
+            .with_sample_rate(44100)
+            .with_time_base(TimeBase::new(1, 44100));
+        let track = Track::new(String::from("track.wav"), codec_params.clone());
+        track.set_beatgrid(Beatgrid::new(0., bpm));
+        let deck = Deck {
+            track: Some(Arc::new(track)),
+            ..Deck::default()
+        };
+        *deck.position.lock().unwrap() =
+            Some(TimeMarker::from_seconds(beat * 60. / bpm, codec_params));
+        deck
+    }
+
+    fn master(bpm: f64, beat: f64) -> SyncMaster {
+        SyncMaster {
+            bpm,
+            beat: Some(beat),
+            playing: true,
+        }
+    }
+
+    #[test]
+    fn matches_tempo_at_half_and_double_time() {
+        assert_eq!(
+            deck(87., 0.).matched_tempo(&master(174., 0.)),
+            Some((0., 0.5))
+        );
+        assert_eq!(
+            deck(174., 0.).matched_tempo(&master(87., 0.)),
+            Some((0., 2.))
+        );
+        let (tempo, factor) = deck(128., 0.).matched_tempo(&master(130., 0.)).unwrap();
+        assert!((tempo - (130. / 128. - 1.)).abs() < 1e-9);
+        assert_eq!(factor, 1.);
+        // 100 BPM is closer to 90 BPM than to 180 BPM
+        let (tempo, factor) = deck(100., 0.).matched_tempo(&master(90., 0.)).unwrap();
+        assert!((tempo + 0.1).abs() < 1e-9);
+        assert_eq!(factor, 1.);
+    }
+
+    #[test]
+    fn phase_offset_stays_within_half_a_beat() {
+        let offset = |deck_beat: f64, master_beat: f64| {
+            deck(120., deck_beat)
+                .phase_offset(&master(120., master_beat))
+                .unwrap()
+        };
+        assert!((offset(10.45, 10.) - 0.45).abs() < 1e-3);
+        assert!((offset(10.55, 10.) + 0.45).abs() < 1e-3);
+        // half a beat ahead and half a beat behind are the same phase
+        assert!((offset(10.5, 10.) + 0.5).abs() < 1e-3);
+        assert!((offset(10.95, 11.) + 0.05).abs() < 1e-3);
+        assert!((offset(11.05, 10.) - 0.05).abs() < 1e-3);
+    }
+
+    #[test]
+    fn phase_offset_at_half_and_double_time() {
+        // the deck plays half a beat per master beat
+        let offset = deck(87., 20.25).phase_offset(&master(174., 40.)).unwrap();
+        assert!((offset - 0.25).abs() < 1e-3);
+        // the deck plays two beats per master beat
+        let offset = deck(174., 40.2).phase_offset(&master(87., 20.)).unwrap();
+        assert!((offset - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn no_phase_offset_without_beatgrid() {
+        let deck = Deck::default();
+        assert_eq!(deck.phase_offset(&master(120., 1.)), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+//------------------------------------------------------------------//
+//                               Key                                //
+//------------------------------------------------------------------//
+
+/// names of the twelve pitch classes, starting at C
+const PITCH_CLASS_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// The musical key of a track
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Key {
+    /// pitch class of the tonic, 0 is C, 1 is C#/Db, ... 11 is B
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+/// The notations, in which a key can be displayed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyNotation {
+    /// e.g. "Am" or "F#"
+    Classical,
+    /// e.g. "8A" or "2B"
+    Camelot,
+    /// e.g. "1m" or "3d"
+    OpenKey,
+}
+
+impl Default for KeyNotation {
+    fn default() -> Self {
+        KeyNotation::Camelot
+    }
+}
+
+impl KeyNotation {
+    /// the next notation, used for cycling through notations in the UI
+    pub fn next(self) -> Self {
+        match self {
+            KeyNotation::Classical => KeyNotation::Camelot,
+            KeyNotation::Camelot => KeyNotation::OpenKey,
+            KeyNotation::OpenKey => KeyNotation::Classical,
+        }
+    }
+}
+
+impl Key {
+    pub fn new(tonic: u8, mode: Mode) -> Self {
+        Self {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    /// the number on the Camelot wheel, from 1 to 12
+    pub fn camelot_number(&self) -> u8 {
+        // minor keys share the number with their relative major, which is 3 semitones up
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        // C major is 8B and every fifth (7 semitones) moves one step on the wheel
+        (major_tonic as u16 * 7 + 7) as u8 % 12 + 1
+    }
+
+    /// Camelot notation, e.g. "8A" for A minor
+    pub fn camelot(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'B',
+            Mode::Minor => 'A',
+        };
+        format!("{}{}", self.camelot_number(), letter)
+    }
+
+    /// Open Key notation, e.g. "1m" for A minor
+    pub fn open_key(&self) -> String {
+        let number = (self.camelot_number() + 4) % 12 + 1;
+        let letter = match self.mode {
+            Mode::Major => 'd',
+            Mode::Minor => 'm',
+        };
+        format!("{}{}", number, letter)
+    }
+
+    /// classical notation, e.g. "Am" for A minor
+    pub fn classical(&self) -> String {
+        let suffix = match self.mode {
+            Mode::Major => "",
+            Mode::Minor => "m",
+        };
+        format!("{}{}", PITCH_CLASS_NAMES[self.tonic as usize], suffix)
+    }
+
+    pub fn to_notation(&self, notation: KeyNotation) -> String {
+        match notation {
+            KeyNotation::Classical => self.classical(),
+            KeyNotation::Camelot => self.camelot(),
+            KeyNotation::OpenKey => self.open_key(),
+        }
+    }
+
+    /// parses a key in any of the supported notations, as found in tags of DJ software. Accepts
+    /// e.g. "8A", "08A", "1m", "Am", "A minor", "F#", "Gbmin" or "Ebm".
+    pub fn parse(s: &str) -> Option<Key> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            Key::parse_wheel(s)
+        } else {
+            Key::parse_classical(s)
+        }
+    }
+
+    /// parses Camelot or Open Key notation
+    fn parse_wheel(s: &str) -> Option<Key> {
+        let split = s.find(|c: char| !c.is_ascii_digit())?;
+        let (number, letter) = s.split_at(split);
+        let number: u8 = number.parse().ok()?;
+        if !(1..=12).contains(&number) {
+            return None;
+        }
+        let (camelot_number, mode) = match letter {
+            "A" | "a" => (number, Mode::Minor),
+            "B" | "b" => (number, Mode::Major),
+            // open key is shifted by 7 steps against camelot
+            "m" => ((number + 6) % 12 + 1, Mode::Minor),
+            "d" => ((number + 6) % 12 + 1, Mode::Major),
+            _ => return None,
+        };
+        // invert camelot_number(): the major tonic is 7 semitones per step away from C (8B)
+        let major_tonic = ((camelot_number as u16 + 12 - 8) * 7 % 12) as u8;
+        let tonic = match mode {
+            Mode::Major => major_tonic,
+            Mode::Minor => (major_tonic + 9) % 12,
+        };
+        Some(Key::new(tonic, mode))
+    }
+
+    /// parses classical notation
+    fn parse_classical(s: &str) -> Option<Key> {
+        let mut chars = s.chars();
+        let tonic: i8 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        let rest = chars.as_str();
+        let (tonic, rest) = if let Some(rest) = rest.strip_prefix(|c| c == '#' || c == '♯') {
+            (tonic + 1, rest)
+        } else if let Some(rest) = rest.strip_prefix(|c| c == 'b' || c == '♭') {
+            (tonic - 1, rest)
+        } else {
+            (tonic, rest)
+        };
+        let mode = match rest.trim().to_lowercase().as_str() {
+            "" | "maj" | "major" | "dur" => Mode::Major,
+            "m" | "min" | "minor" | "moll" => Mode::Minor,
+            _ => return None,
+        };
+        Some(Key::new(tonic.rem_euclid(12) as u8, mode))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.classical())
+    }
+}
@@ -1 +1,6 @@
+pub mod beatgrid;
+pub mod cue;
+pub mod deck;
+pub mod key;
+pub mod playlist;
 pub mod track;
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+//------------------------------------------------------------------//
+//                             Playlist                             //
+//------------------------------------------------------------------//
+
+/// A node in the playlist tree. Folders group playlists and other folders, playlists reference
+/// tracks by their file path.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PlaylistNode {
+    Folder {
+        name: String,
+        children: Vec<PlaylistNode>,
+    },
+    Playlist {
+        name: String,
+        /// file paths of the tracks in playback order
+        tracks: Vec<String>,
+    },
+}
+
+impl PlaylistNode {
+    pub fn folder(name: String, children: Vec<PlaylistNode>) -> Self {
+        PlaylistNode::Folder { name, children }
+    }
+
+    pub fn playlist(name: String, tracks: Vec<String>) -> Self {
+        PlaylistNode::Playlist { name, tracks }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            PlaylistNode::Folder { name, .. } => name,
+            PlaylistNode::Playlist { name, .. } => name,
+        }
+    }
+}
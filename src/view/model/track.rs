@@ -1,15 +1,25 @@
-use bounded_vec_deque::BoundedVecDeque;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::hash::Hash;
 use std::path::Path;
-use std::sync::{Mutex, RwLock};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
 use symphonia::core::formats::Track as SymphoniaTrack;
 use symphonia::core::meta::{Metadata, StandardTagKey, Tag, Value};
 
 use itertools::Itertools;
+use symphonia::core::audio::Channels;
 use symphonia::core::codecs::CodecParameters;
+use symphonia::core::units::TimeBase;
 
+use super::beatgrid::Beatgrid;
+use super::cue::{HotCue, Loop, MemCue, SuggestedCue, HOT_CUE_SLOTS, MEM_CUE_LIMIT};
+use super::key::Key;
 use crate::core::{
     analyzer::{PreviewSample, PREVIEW_SAMPLE_RATE},
+    library::{Fingerprint, LIBRARY_VERSION},
     player::TimeMarker,
 };
 
@@ -29,8 +39,18 @@ pub struct Track {
     pub codec_params: CodecParameters,
     /// downsampled version of decoded frames for preview
     preview_buffer: RwLock<Vec<PreviewSample>>,
-    /// list of memory cue markers
-    pub mem_cues: Mutex<BoundedVecDeque<TimeMarker>>,
+    /// memory cues in the order of the cue list
+    mem_cues: RwLock<Vec<MemCue>>,
+    /// cues, that the analyzer suggests, ordered by their position
+    suggested_cues: RwLock<Vec<SuggestedCue>>,
+    /// the numbered hot cue slots, None for empty slots
+    hot_cues: RwLock<Vec<Option<HotCue>>>,
+    /// saved loops, ordered by their start
+    loops: RwLock<Vec<Loop>>,
+    /// the detected beatgrid
+    beatgrid: RwLock<Option<Beatgrid>>,
+    /// whether the analysis of the whole file is finished
+    analyzed: AtomicBool,
 }
 
 impl Track {
@@ -41,19 +61,183 @@ impl Track {
             preview_buffer: RwLock::new(vec![]),
             file_path,
             file_name,
-            mem_cues: Mutex::new(BoundedVecDeque::new(10)),
+            mem_cues: RwLock::new(vec![]),
+            suggested_cues: RwLock::new(vec![]),
+            hot_cues: RwLock::new(vec![None; HOT_CUE_SLOTS]),
+            loops: RwLock::new(vec![]),
+            beatgrid: RwLock::new(None),
+            analyzed: AtomicBool::new(false),
             codec_params,
         }
     }
 
-    pub fn change_bpm(&self, bpm: u32) {
+    pub fn change_bpm(&self, bpm: Bpm) {
         let mut meta = self.meta.write().unwrap();
-        meta.bpm = bpm;
+        meta.bpm = Some(bpm);
+    }
+
+    /// returns a copy of the beatgrid, if one was detected
+    pub fn beatgrid(&self) -> Option<Beatgrid> {
+        self.beatgrid.read().unwrap().clone()
+    }
+
+    pub fn set_beatgrid(&self, beatgrid: Beatgrid) {
+        *self.beatgrid.write().unwrap() = Some(beatgrid);
+    }
+
+    /// the position, that is the given number of beats after start in seconds. Uses the
+    /// beatgrid, or the bpm, if there is no grid.
+    pub fn position_after_beats(&self, start: f64, beats: f64) -> Option<f64> {
+        if let Some(beatgrid) = self.beatgrid() {
+            return Some(beatgrid.beat_position(beatgrid.beat_index_at(start) + beats));
+        }
+        let bpm = self.meta.read().unwrap().bpm?;
+        Some(start + beats * 60. / bpm.value)
+    }
+
+    /// length of the track in seconds
+    pub fn duration(&self) -> Option<f64> {
+        match (self.codec_params.n_frames, self.codec_params.sample_rate) {
+            (Some(n_frames), Some(sample_rate)) => Some(n_frames as f64 / sample_rate as f64),
+            _ => None,
+        }
+    }
+
+    pub fn is_analyzed(&self) -> bool {
+        self.analyzed.load(Ordering::SeqCst)
+    }
+
+    pub fn set_analyzed(&self) {
+        self.analyzed.store(true, Ordering::SeqCst);
+    }
+
+    /// returns a copy of the memory cues
+    pub fn mem_cues(&self) -> Vec<MemCue> {
+        self.mem_cues.read().unwrap().clone()
+    }
+
+    /// appends a memory cue to the cue list. Returns false, if the track already has
+    /// MEM_CUE_LIMIT cues.
+    pub fn add_mem_cue(&self, cue: MemCue) -> bool {
+        let mut mem_cues = self.mem_cues.write().unwrap();
+        if mem_cues.len() >= MEM_CUE_LIMIT {
+            return false;
+        }
+        mem_cues.push(cue);
+        true
+    }
+
+    /// replaces the memory cue at an index of the cue list, e.g. after renaming it
+    pub fn set_mem_cue(&self, index: usize, cue: MemCue) {
+        if let Some(entry) = self.mem_cues.write().unwrap().get_mut(index) {
+            *entry = cue;
+        }
     }
 
-    /// add memory cue  
-    pub fn add_mem_cue(&self, tm: TimeMarker) {
-        self.mem_cues.lock().unwrap().push_back(tm);
+    /// removes the memory cue at an index of the cue list and returns it
+    pub fn delete_mem_cue(&self, index: usize) -> Option<MemCue> {
+        let mut mem_cues = self.mem_cues.write().unwrap();
+        if index < mem_cues.len() {
+            Some(mem_cues.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// moves a memory cue to another index of the cue list. Returns false, if either index is out
+    /// of bounds.
+    pub fn move_mem_cue(&self, from: usize, to: usize) -> bool {
+        let mut mem_cues = self.mem_cues.write().unwrap();
+        if from >= mem_cues.len() || to >= mem_cues.len() {
+            return false;
+        }
+        let cue = mem_cues.remove(from);
+        mem_cues.insert(to, cue);
+        true
+    }
+
+    /// returns a copy of the suggested cues
+    pub fn suggested_cues(&self) -> Vec<SuggestedCue> {
+        self.suggested_cues.read().unwrap().clone()
+    }
+
+    pub fn set_suggested_cues(&self, suggested_cues: Vec<SuggestedCue>) {
+        *self.suggested_cues.write().unwrap() = suggested_cues;
+    }
+
+    /// turns a suggested cue into a memory cue. Returns false and keeps the suggestion, if the
+    /// track already has MEM_CUE_LIMIT memory cues.
+    pub fn accept_suggested_cue(&self, index: usize) -> bool {
+        let suggestion = match self.suggested_cues.read().unwrap().get(index) {
+            Some(suggestion) => *suggestion,
+            None => return false,
+        };
+        if !self.add_mem_cue(suggestion.to_mem_cue()) {
+            return false;
+        }
+        self.discard_suggested_cue(index);
+        true
+    }
+
+    /// removes a suggested cue and returns it
+    pub fn discard_suggested_cue(&self, index: usize) -> Option<SuggestedCue> {
+        let mut suggested_cues = self.suggested_cues.write().unwrap();
+        if index < suggested_cues.len() {
+            Some(suggested_cues.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// the hot cue in a slot, if it is set
+    pub fn hot_cue(&self, slot: usize) -> Option<HotCue> {
+        self.hot_cues.read().unwrap().get(slot).cloned().flatten()
+    }
+
+    /// all set hot cues with their slots
+    pub fn hot_cues(&self) -> Vec<(usize, HotCue)> {
+        self.hot_cues
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, cue)| cue.clone().map(|cue| (slot, cue)))
+            .collect()
+    }
+
+    /// sets a hot cue, replacing the one in the slot. Slots beyond HOT_CUE_SLOTS are ignored.
+    pub fn set_hot_cue(&self, slot: usize, cue: HotCue) {
+        if let Some(entry) = self.hot_cues.write().unwrap().get_mut(slot) {
+            *entry = Some(cue);
+        }
+    }
+
+    /// empties a slot and returns the cue, that was in it
+    pub fn delete_hot_cue(&self, slot: usize) -> Option<HotCue> {
+        self.hot_cues
+            .write()
+            .unwrap()
+            .get_mut(slot)
+            .and_then(|entry| entry.take())
+    }
+
+    /// returns a copy of the saved loops
+    pub fn loops(&self) -> Vec<Loop> {
+        self.loops.read().unwrap().clone()
+    }
+
+    /// saves a loop. Returns false, if the same loop is already saved.
+    pub fn add_loop(&self, new_loop: Loop) -> bool {
+        let mut loops = self.loops.write().unwrap();
+        if loops.contains(&new_loop) {
+            return false;
+        }
+        let index = loops
+            .iter()
+            .position(|saved| saved.start > new_loop.start)
+            .unwrap_or(loops.len());
+        loops.insert(index, new_loop);
+        true
     }
 
     /// append preview samples to preview buffer
@@ -65,6 +249,11 @@ impl Track {
         self.preview_buffer.write().unwrap().append(preview_samples);
     }
 
+    /// returns a copy of all preview samples
+    pub fn preview_samples(&self) -> Vec<PreviewSample> {
+        self.preview_buffer.read().unwrap().clone()
+    }
+
     /// returns the analysis progress for this track.
     /// The result is a number between 0 and 100 (%).
     pub fn progress(&self) -> Option<u8> {
@@ -144,6 +333,44 @@ impl Track {
         scaled
     }
 
+    /// converts the track into a record for the library
+    pub fn to_record(&self, fingerprint: Fingerprint) -> TrackRecord {
+        let meta = self.meta.read().unwrap().clone();
+        let preview = StoredPreview::from_samples(&self.preview_buffer.read().unwrap());
+        TrackRecord {
+            version: LIBRARY_VERSION,
+            file_path: self.file_path.clone(),
+            fingerprint,
+            codec_params: StoredCodecParams::from(&self.codec_params),
+            meta,
+            preview,
+            mem_cues: self.mem_cues(),
+            suggested_cues: self.suggested_cues(),
+            hot_cues: self.hot_cues.read().unwrap().clone(),
+            loops: self.loops(),
+            beatgrid: self.beatgrid(),
+        }
+    }
+
+    /// restores a track from a library record
+    pub fn from_record(record: TrackRecord) -> Self {
+        let codec_params = record.codec_params.to_codec_params();
+        let track = Track::new(record.file_path, codec_params);
+        *track.meta.write().unwrap() = record.meta;
+        *track.preview_buffer.write().unwrap() = record.preview.to_samples();
+        *track.beatgrid.write().unwrap() = record.beatgrid;
+        *track.loops.write().unwrap() = record.loops;
+        *track.mem_cues.write().unwrap() = record.mem_cues;
+        *track.suggested_cues.write().unwrap() = record.suggested_cues;
+        for (slot, cue) in record.hot_cues.into_iter().enumerate() {
+            if let Some(cue) = cue {
+                track.set_hot_cue(slot, cue);
+            }
+        }
+        track.set_analyzed();
+        track
+    }
+
     /// computes a downsampled version of the full track that fits in a buffer of target_size
     pub fn preview(&self, target_size: usize) -> Vec<PreviewSample> {
         let preview_buffer = self.preview_buffer.read().unwrap().clone();
@@ -194,17 +421,22 @@ impl Hash for Track {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// tag keys, under which different formats and applications store the musical key
+const KEY_TAGS: [&str; 4] = ["TKEY", "INITIALKEY", "TXXX:INITIALKEY", "KEY"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TrackMeta {
     pub artist: String,
     pub title: String,
     pub album: String,
-    pub bpm: u32,
+    pub bpm: Option<Bpm>,
+    pub key: Option<Key>,
 }
 impl Default for TrackMeta {
     fn default() -> Self {
         Self {
-            bpm: 0,
+            bpm: None,
+            key: None,
             artist: String::from(""),
             title: String::from(""),
             album: String::from(""),
@@ -229,13 +461,162 @@ impl TrackMeta {
                         }
                     }
                     StandardTagKey::Bpm => {
-                        if let Value::UnsignedInt(bpm) = tag.value {
-                            self.bpm = bpm as u32;
+                        let bpm = match tag.value {
+                            Value::UnsignedInt(bpm) => Some(bpm as f64),
+                            Value::SignedInt(bpm) => Some(bpm as f64),
+                            Value::Float(bpm) => Some(bpm),
+                            Value::String(bpm) => bpm.trim().replace(',', ".").parse().ok(),
+                            _ => None,
+                        };
+                        if let Some(bpm) = bpm.filter(|bpm| *bpm > 0.) {
+                            self.bpm = Some(Bpm::new(bpm, 1.));
                         }
                     }
-                    _ => break,
+                    _ => {}
+                }
+            } else if KEY_TAGS.contains(&tag.key.to_uppercase().as_str()) {
+                // there is no standard tag key for the musical key
+                if let Value::String(key) = tag.value {
+                    if let Some(key) = Key::parse(&key) {
+                        self.key = Some(key);
+                    }
                 }
             }
         }
     }
 }
+
+//------------------------------------------------------------------//
+//                               Bpm                                //
+//------------------------------------------------------------------//
+
+/// Tempo of a track in beats per minute
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bpm {
+    pub value: f64,
+    /// how reliable the value is, from 0 (guess) to 1 (certain, e.g. read from a tag)
+    pub confidence: f32,
+}
+
+impl Bpm {
+    pub fn new(value: f64, confidence: f32) -> Self {
+        Self { value, confidence }
+    }
+}
+
+impl fmt::Display for Bpm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}", self.value)
+    }
+}
+
+//------------------------------------------------------------------//
+//                           TrackRecord                            //
+//------------------------------------------------------------------//
+
+/// Serializable snapshot of a track's analysis results, as it is stored in the library
+#[derive(Serialize, Deserialize)]
+pub struct TrackRecord {
+    /// version of the record format
+    pub version: u32,
+    /// the file path
+    pub file_path: String,
+    /// fingerprint of the file at the time of the analysis
+    pub fingerprint: Fingerprint,
+    codec_params: StoredCodecParams,
+    meta: TrackMeta,
+    preview: StoredPreview,
+    mem_cues: Vec<MemCue>,
+    suggested_cues: Vec<SuggestedCue>,
+    hot_cues: Vec<Option<HotCue>>,
+    loops: Vec<Loop>,
+    beatgrid: Option<Beatgrid>,
+}
+
+/// The parts of the codec parameters, that are needed for converting between timestamps and time
+#[derive(Serialize, Deserialize)]
+struct StoredCodecParams {
+    sample_rate: Option<u32>,
+    n_frames: Option<u64>,
+    channels: Option<u32>,
+    time_base: Option<(u32, u32)>,
+}
+
+impl From<&CodecParameters> for StoredCodecParams {
+    fn from(codec_params: &CodecParameters) -> Self {
+        Self {
+            sample_rate: codec_params.sample_rate,
+            n_frames: codec_params.n_frames,
+            channels: codec_params.channels.map(|c| c.bits()),
+            time_base: codec_params.time_base.map(|tb| (tb.numer, tb.denom)),
+        }
+    }
+}
+
+impl StoredCodecParams {
+    fn to_codec_params(&self) -> CodecParameters {
+        let mut codec_params = CodecParameters::new();
+        if let Some(sample_rate) = self.sample_rate {
+            codec_params.with_sample_rate(sample_rate);
+        }
+        if let Some(n_frames) = self.n_frames {
+            codec_params.with_n_frames(n_frames);
+        }
+        if let Some(channels) = self.channels.and_then(Channels::from_bits) {
+            codec_params.with_channels(channels);
+        }
+        if let Some((numer, denom)) = self.time_base {
+            codec_params.with_time_base(TimeBase::new(numer, denom));
+        }
+        codec_params
+    }
+}
+
+/// Preview samples quantized to one byte per band. Every band is scaled by its own maximum, so
+/// quiet bands keep their resolution.
+#[derive(Serialize, Deserialize)]
+struct StoredPreview {
+    scale: [f32; 3],
+    samples: Vec<[u8; 3]>,
+}
+
+impl StoredPreview {
+    fn from_samples(samples: &[PreviewSample]) -> Self {
+        let max = |band: fn(&PreviewSample) -> f32| {
+            samples
+                .iter()
+                .map(band)
+                .fold(0f32, |max, s| max.max(s.abs()))
+        };
+        let scale = [max(|s| s.lows), max(|s| s.mids), max(|s| s.highs)];
+        let quantize = |value: f32, scale: f32| {
+            if scale > 0. {
+                (value.abs() / scale * 255.).round() as u8
+            } else {
+                0
+            }
+        };
+        let samples = samples
+            .iter()
+            .map(|s| {
+                [
+                    quantize(s.lows, scale[0]),
+                    quantize(s.mids, scale[1]),
+                    quantize(s.highs, scale[2]),
+                ]
+            })
+            .collect();
+        Self { scale, samples }
+    }
+
+    fn to_samples(&self) -> Vec<PreviewSample> {
+        self.samples
+            .iter()
+            .map(|s| PreviewSample {
+                lows: s[0] as f32 / 255. * self.scale[0],
+                mids: s[1] as f32 / 255. * self.scale[1],
+                highs: s[2] as f32 / 255. * self.scale[2],
+            })
+            .collect()
+    }
+}
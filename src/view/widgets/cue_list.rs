@@ -0,0 +1,160 @@
+use tui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Row, Table, Widget},
+};
+
+use crate::view::model::cue::{MemCue, SuggestedCue, MEM_CUE_LIMIT};
+
+//------------------------------------------------------------------//
+//                          CueListWidget                           //
+//------------------------------------------------------------------//
+
+/// A Widget for editing the memory cues of a track in table form. Suggested cues are listed
+/// after the memory cues.
+pub struct CueListWidget<'a> {
+    cues: &'a [MemCue],
+    suggested_cues: &'a [SuggestedCue],
+    cue_list: &'a CueList,
+}
+
+impl<'a> CueListWidget<'a> {
+    pub fn new(
+        cues: &'a [MemCue],
+        suggested_cues: &'a [SuggestedCue],
+        cue_list: &'a CueList,
+    ) -> Self {
+        Self {
+            cues,
+            suggested_cues,
+            cue_list,
+        }
+    }
+
+    fn get_row(&self, index: usize, cue: &MemCue) -> Row<'_> {
+        let selected = index == self.cue_list.selected;
+        // the label under edit with a cursor
+        let label = match (&self.cue_list.renaming, selected) {
+            (Some(label), true) => format!("{}_", label),
+            _ => cue.label.clone(),
+        };
+        let style = if selected {
+            Style::default().fg(Color::Black).bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            Cell::from((index + 1).to_string()),
+            Cell::from(format_position(cue.position)),
+            Cell::from("■").style(Style::default().fg(Color::from(cue.color))),
+            Cell::from(label),
+        ])
+        .style(style)
+    }
+
+    /// a suggested cue in italics, the index is the one in the whole list
+    fn get_suggestion_row(&self, index: usize, suggestion: &SuggestedCue) -> Row<'_> {
+        let style = if index == self.cue_list.selected {
+            Style::default().fg(Color::Black).bg(Color::DarkGray)
+        } else {
+            Style::default().fg(Color::Gray)
+        };
+        Row::new(vec![
+            Cell::from("?"),
+            Cell::from(format_position(suggestion.position)),
+            Cell::from("■").style(Style::default().fg(Color::from(suggestion.kind.color()))),
+            Cell::from(suggestion.kind.label()),
+        ])
+        .style(style.add_modifier(Modifier::ITALIC))
+    }
+}
+
+impl<'a> Widget for CueListWidget<'a> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        let header = Row::new(vec!["#", "Time", "", "Label"])
+            .style(Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED))
+            .bottom_margin(1);
+        let rows: Vec<Row> = self
+            .cues
+            .iter()
+            .enumerate()
+            .map(|(index, cue)| self.get_row(index, cue))
+            .chain(
+                self.suggested_cues
+                    .iter()
+                    .enumerate()
+                    .map(|(index, suggestion)| {
+                        self.get_suggestion_row(self.cues.len() + index, suggestion)
+                    }),
+            )
+            .collect();
+        let title = format!(
+            "Memory Cues ({}/{}) - enter: jump, r: rename, c: color, J/K: move, d: delete, \
+             a/A: accept suggestion/all, esc: close",
+            self.cues.len(),
+            MEM_CUE_LIMIT
+        );
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Length(10),
+            Constraint::Length(2),
+            Constraint::Percentage(100),
+        ];
+        let table = Table::new(rows)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .header(header)
+            .style(Style::default().fg(Color::White))
+            .widths(&widths)
+            .column_spacing(1);
+        table.render(area, buf);
+    }
+}
+
+/// a position in seconds as minutes, seconds and milliseconds
+fn format_position(position: f64) -> String {
+    let millis = (position.max(0.) * 1000.).round() as u64;
+    format!(
+        "{:02}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+//------------------------------------------------------------------//
+//                             CueList                              //
+//------------------------------------------------------------------//
+
+/// State of the memory cue editor
+#[derive(Default)]
+pub struct CueList {
+    /// index of the selected cue
+    pub selected: usize,
+    /// the new label, while the selected cue is being renamed
+    pub renaming: Option<String>,
+}
+
+impl CueList {
+    /// select the next cue of a list with len cues, wrapping at the end
+    pub fn select_next(&mut self, len: usize) {
+        self.selected = if self.selected + 1 < len {
+            self.selected + 1
+        } else {
+            0
+        };
+    }
+
+    /// select the previous cue of a list with len cues, wrapping at the start
+    pub fn select_previous(&mut self, len: usize) {
+        self.selected = if self.selected > 0 {
+            self.selected - 1
+        } else {
+            len.saturating_sub(1)
+        };
+    }
+
+    /// keeps the selection within a list with len cues, e.g. after deleting the last cue
+    pub fn clamp(&mut self, len: usize) {
+        self.selected = self.selected.min(len.saturating_sub(1));
+    }
+}
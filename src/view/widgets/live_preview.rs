@@ -1,18 +1,20 @@
 use tui::buffer::Buffer;
 use tui::layout::Rect;
-use tui::style::Color;
+use tui::style::{Color, Style};
+use tui::text::Span;
 use tui::widgets::canvas::Context;
 use tui::widgets::{
     canvas::{Canvas, Line},
     Block, Widget,
 };
 
-use crate::core::player::TimeMarker;
-use crate::view::model::track::Track;
+use crate::core::player::{LoopMarker, TimeMarker};
+use crate::view::model::{cue::HotCue, track::Track};
 
 pub struct LivePreviewWidget<'a> {
     track: &'a Track,
     player_pos: &'a Option<TimeMarker>,
+    player_loop: &'a Option<LoopMarker>,
     zoom_level: u32,
 }
 
@@ -23,9 +25,15 @@ pub enum WaveFormLayer {
 }
 
 impl<'a> LivePreviewWidget<'a> {
-    pub fn new(track: &'a Track, player_pos: &'a Option<TimeMarker>, zoom_level: u32) -> Self {
+    pub fn new(
+        track: &'a Track,
+        player_pos: &'a Option<TimeMarker>,
+        player_loop: &'a Option<LoopMarker>,
+        zoom_level: u32,
+    ) -> Self {
         Self {
             player_pos,
+            player_loop,
             track,
             zoom_level,
         }
@@ -68,6 +76,122 @@ impl<'a> LivePreviewWidget<'a> {
             }
         }
     }
+
+    /// draws a tick at the top and bottom for every beat of the beatgrid, downbeats are
+    /// highlighted
+    fn draw_beatgrid(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        if let (Some(player_pos), Some(beatgrid)) = (self.player_pos, self.track.beatgrid()) {
+            let now = player_pos.get_time_in_seconds();
+            // one x unit is one sample of the live preview, which has zoom_level samples/s
+            let visible_seconds = x_max as f64 / self.zoom_level as f64;
+            let y_max = y_max as f64;
+            for beat in beatgrid.beats_between(now - visible_seconds, now + visible_seconds) {
+                let x = (beat.position - now) * self.zoom_level as f64;
+                let color = if beat.is_downbeat() {
+                    Color::Yellow
+                } else {
+                    Color::DarkGray
+                };
+                ctx.draw(&Line {
+                    x1: x,
+                    x2: x,
+                    y1: y_max,
+                    y2: y_max * 0.8,
+                    color,
+                });
+                ctx.draw(&Line {
+                    x1: x,
+                    x2: x,
+                    y1: -y_max,
+                    y2: -y_max * 0.8,
+                    color,
+                });
+            }
+        }
+    }
+
+    /// draws a line with the slot number for every visible hot cue
+    fn draw_hot_cues(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        if let Some(player_pos) = self.player_pos {
+            let now = player_pos.get_time_in_seconds();
+            let y_max = y_max as f64;
+            for (slot, cue) in self.track.hot_cues() {
+                let x = (cue.position - now) * self.zoom_level as f64;
+                if x.abs() > x_max as f64 {
+                    continue;
+                }
+                let color = Color::from(cue.color);
+                ctx.draw(&Line {
+                    x1: x,
+                    x2: x,
+                    y1: y_max,
+                    y2: -y_max,
+                    color,
+                });
+                ctx.print(
+                    x,
+                    y_max,
+                    Span::styled(HotCue::slot_name(slot), Style::default().fg(color)),
+                );
+            }
+        }
+    }
+
+    /// draws the saved loops and the loop of the player as brackets at the top. The loop of the
+    /// player is highlighted, while it is active.
+    fn draw_loops(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        if let Some(player_pos) = self.player_pos {
+            let now = player_pos.get_time_in_seconds();
+            let mut loops: Vec<(f64, Option<f64>, Color)> = self
+                .track
+                .loops()
+                .iter()
+                .map(|saved_loop| (saved_loop.start, Some(saved_loop.end), Color::Cyan))
+                .collect();
+            if let Some(player_loop) = self.player_loop {
+                let color = if player_loop.active {
+                    Color::LightGreen
+                } else {
+                    Color::Green
+                };
+                loops.push((
+                    player_loop.start.get_time_in_seconds(),
+                    player_loop
+                        .end
+                        .as_ref()
+                        .map(|end| end.get_time_in_seconds()),
+                    color,
+                ));
+            }
+            let x_max = x_max as f64;
+            let y_max = y_max as f64;
+            for (start, end, color) in loops {
+                let x1 = ((start - now) * self.zoom_level as f64).max(-x_max);
+                let x2 = end.map(|end| ((end - now) * self.zoom_level as f64).min(x_max));
+                if x1 > x_max || x2.map_or(false, |x2| x2 < -x_max) {
+                    continue;
+                }
+                for x in [Some(x1), x2].into_iter().flatten() {
+                    ctx.draw(&Line {
+                        x1: x,
+                        x2: x,
+                        y1: y_max,
+                        y2: y_max * 0.5,
+                        color,
+                    });
+                }
+                if let Some(x2) = x2 {
+                    ctx.draw(&Line {
+                        x1,
+                        x2,
+                        y1: y_max,
+                        y2: y_max,
+                        color,
+                    });
+                }
+            }
+        }
+    }
 }
 
 impl<'a> Widget for LivePreviewWidget<'a> {
@@ -95,6 +219,9 @@ impl<'a> Widget for LivePreviewWidget<'a> {
                 });
                 self.draw_waveform(ctx, WaveFormLayer::Lows, target_size, y_max);
                 self.draw_waveform(ctx, WaveFormLayer::Mids, target_size, y_max);
+                self.draw_beatgrid(ctx, x_max, y_max);
+                self.draw_loops(ctx, x_max, y_max);
+                self.draw_hot_cues(ctx, x_max, y_max);
                 // self.draw_waveform(ctx, WaveFormLayer::Highs, target_size, y_max);
             });
         canvas.render(area, buf);
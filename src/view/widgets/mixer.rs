@@ -0,0 +1,127 @@
+use tui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Row, Table, Widget},
+};
+
+use crate::core::mixer::{ChannelSettings, CrossfaderCurve, CrossfaderSide, Knob, MixerSettings};
+
+/// number of steps of the crossfader drawing
+const CROSSFADER_STEPS: usize = 10;
+
+//------------------------------------------------------------------//
+//                           MixerWidget                            //
+//------------------------------------------------------------------//
+
+/// A Widget for the mixer, with a row for each channel and the crossfader in its title
+pub struct MixerWidget<'a> {
+    settings: &'a MixerSettings,
+    /// the channel of the deck, that receives the key events
+    active_channel: usize,
+    /// the knob, that gets turned by key events
+    selected_knob: Knob,
+}
+
+impl<'a> MixerWidget<'a> {
+    pub fn new(settings: &'a MixerSettings, active_channel: usize, selected_knob: Knob) -> Self {
+        Self {
+            settings,
+            active_channel,
+            selected_knob,
+        }
+    }
+
+    fn get_row(&self, index: usize, channel: &ChannelSettings) -> Row<'_> {
+        let style = if index == self.active_channel {
+            Style::default().fg(Color::Black).bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        let side = match channel.side {
+            CrossfaderSide::A => "A",
+            CrossfaderSide::Thru => "-",
+            CrossfaderSide::B => "B",
+        };
+        let cue = if channel.cue { "CUE" } else { "" };
+        Row::new(vec![
+            Cell::from((index + 1).to_string()),
+            Cell::from(format!("{:+.0}", channel.knob(Knob::Trim))),
+            Cell::from(format!("{:+.0}", channel.knob(Knob::High))),
+            Cell::from(format!("{:+.0}", channel.knob(Knob::Mid))),
+            Cell::from(format!("{:+.0}", channel.knob(Knob::Low))),
+            Cell::from(format!("{:.0}%", channel.fader * 100.)),
+            Cell::from(side),
+            Cell::from(cue).style(Style::default().fg(Color::Yellow)),
+        ])
+        .style(style)
+    }
+
+    /// the header, in which the selected knob is highlighted
+    fn get_header(&self) -> Row<'_> {
+        let knobs = [
+            ("Trim", Knob::Trim),
+            ("Hi", Knob::High),
+            ("Mid", Knob::Mid),
+            ("Low", Knob::Low),
+        ];
+        let mut cells = vec![Cell::from("#")];
+        cells.extend(knobs.iter().map(|(name, knob)| {
+            let cell = Cell::from(*name);
+            if *knob == self.selected_knob {
+                cell.style(Style::default().fg(Color::Yellow))
+            } else {
+                cell
+            }
+        }));
+        cells.extend(["Fader", "X", "Cue"].iter().map(|name| Cell::from(*name)));
+        Row::new(cells)
+            .style(Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED))
+            .bottom_margin(1)
+    }
+}
+
+impl<'a> Widget for MixerWidget<'a> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        let rows: Vec<Row> = self
+            .settings
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(index, channel)| self.get_row(index, channel))
+            .collect();
+        let title = format!(
+            "Mixer A {} B ({})",
+            crossfader(self.settings.crossfader),
+            match self.settings.curve {
+                CrossfaderCurve::Smooth => "smooth",
+                CrossfaderCurve::Linear => "linear",
+                CrossfaderCurve::Sharp => "sharp",
+            }
+        );
+        let widths = [
+            Constraint::Length(2),
+            Constraint::Length(4),
+            Constraint::Length(4),
+            Constraint::Length(4),
+            Constraint::Length(4),
+            Constraint::Length(5),
+            Constraint::Length(2),
+            Constraint::Length(3),
+        ];
+        let table = Table::new(rows)
+            .block(Block::default().title(title).borders(Borders::TOP))
+            .header(self.get_header())
+            .style(Style::default().fg(Color::White))
+            .widths(&widths)
+            .column_spacing(1);
+        table.render(area, buf);
+    }
+}
+
+/// draws the crossfader at a position between 0 and 1, e.g. "━━━━━●━━━━━"
+fn crossfader(position: f32) -> String {
+    let knob = (position.clamp(0., 1.) * CROSSFADER_STEPS as f32).round() as usize;
+    (0..=CROSSFADER_STEPS)
+        .map(|step| if step == knob { '●' } else { '━' })
+        .collect()
+}
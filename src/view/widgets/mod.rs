@@ -1,4 +1,8 @@
+pub mod cue_list;
 pub mod live_preview;
+pub mod mixer;
+pub mod phase_meter;
 pub mod popup;
 pub mod preview;
+pub mod root_list;
 pub mod track_table;
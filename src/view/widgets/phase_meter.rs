@@ -0,0 +1,116 @@
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Widget},
+};
+
+use crate::view::model::beatgrid::BEATS_PER_BAR;
+
+/// number of characters on each side of the center of the phase offset drawing
+const OFFSET_STEPS: usize = 8;
+
+/// What the meter shows about a deck
+pub struct DeckPhase {
+    /// whether the other decks sync to this one
+    pub master: bool,
+    /// whether the deck follows the master deck
+    pub sync: bool,
+    /// tempo at the current pitch in beats per minute
+    pub bpm: Option<f64>,
+    /// fractional beat index at the playhead
+    pub beat: Option<f64>,
+    /// offset of the beat phase to the master deck in beats
+    pub offset: Option<f64>,
+}
+
+//------------------------------------------------------------------//
+//                         PhaseMeterWidget                         //
+//------------------------------------------------------------------//
+
+/// A Widget, that compares the tempo and the beat phase of the decks with the master deck. Each
+/// deck gets a line with its beat within the bar and its phase offset to the master.
+pub struct PhaseMeterWidget<'a> {
+    decks: &'a [DeckPhase],
+}
+
+impl<'a> PhaseMeterWidget<'a> {
+    pub fn new(decks: &'a [DeckPhase]) -> Self {
+        Self { decks }
+    }
+
+    fn get_line(&self, index: usize, deck: &DeckPhase) -> Spans<'_> {
+        let role = if deck.master {
+            Span::styled(
+                "MASTER",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            )
+        } else if deck.sync {
+            Span::styled("SYNC  ", Style::default().fg(Color::LightGreen))
+        } else {
+            Span::raw("      ")
+        };
+        let bpm = deck
+            .bpm
+            .map_or(String::from("   ---.--"), |bpm| format!("{:>9.2}", bpm));
+        let offset = match deck.offset {
+            Some(offset) => Span::styled(
+                format!(" {} {:+.2}", offset_gauge(offset), offset),
+                Style::default().fg(if offset.abs() < 0.05 {
+                    Color::LightGreen
+                } else {
+                    Color::LightRed
+                }),
+            ),
+            None => Span::raw(""),
+        };
+        Spans::from(vec![
+            Span::raw(format!("{} ", index + 1)),
+            role,
+            Span::raw(format!("{} ", bpm)),
+            Span::styled(beats_in_bar(deck.beat), Style::default().fg(Color::Yellow)),
+            offset,
+        ])
+    }
+}
+
+impl<'a> Widget for PhaseMeterWidget<'a> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        let lines: Vec<Spans> = self
+            .decks
+            .iter()
+            .enumerate()
+            .map(|(index, deck)| self.get_line(index, deck))
+            .collect();
+        Paragraph::new(lines)
+            .block(Block::default().title("Sync").borders(Borders::TOP))
+            .style(Style::default().fg(Color::White))
+            .render(area, buf);
+    }
+}
+
+/// the beats of a bar, with the current one filled, e.g. "□■□□"
+fn beats_in_bar(beat: Option<f64>) -> String {
+    let current = beat.map(|beat| (beat.floor() as i64).rem_euclid(BEATS_PER_BAR));
+    (0..BEATS_PER_BAR)
+        .map(|index| if Some(index) == current { '■' } else { '□' })
+        .collect()
+}
+
+/// draws a phase offset between -0.5 and 0.5 beats around a center line, e.g. "───┼─●──"
+fn offset_gauge(offset: f64) -> String {
+    let marker = OFFSET_STEPS as f64 + (offset.clamp(-0.5, 0.5) * 2. * OFFSET_STEPS as f64);
+    let marker = marker.round() as usize;
+    (0..=2 * OFFSET_STEPS)
+        .map(|step| {
+            if step == marker {
+                '●'
+            } else if step == OFFSET_STEPS {
+                '┼'
+            } else {
+                '─'
+            }
+        })
+        .collect()
+}
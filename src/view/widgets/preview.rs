@@ -1,23 +1,201 @@
 use tui::{
-    style::Color,
+    style::{Color, Style},
+    text::Span,
     widgets::{
-        canvas::{Canvas, Line},
+        canvas::{Canvas, Context, Line},
         Block, Widget,
     },
 };
 
-use crate::{core::player::TimeMarker, view::model::track::Track};
+use crate::{
+    core::{
+        analyzer::PreviewSample,
+        player::{LoopMarker, TimeMarker},
+    },
+    view::model::{
+        cue::{HotCue, Loop},
+        track::Track,
+    },
+};
 
+/// brightness of the waveform before the playhead
+const PLAYED_BRIGHTNESS: f32 = 0.4;
+
+/// An overview of the whole track. The waveform is colored by its bands, red for the lows, green
+/// for the mids and blue for the highs, like the RGB waveforms of Rekordbox.
 pub struct PreviewWidget<'a> {
     track: &'a Track,
     player_position: &'a Option<TimeMarker>,
+    player_loop: &'a Option<LoopMarker>,
 }
 
 impl<'a> PreviewWidget<'a> {
-    pub fn new(track: &'a Track, player_position: &'a Option<TimeMarker>) -> Self {
+    pub fn new(
+        track: &'a Track,
+        player_position: &'a Option<TimeMarker>,
+        player_loop: &'a Option<LoopMarker>,
+    ) -> Self {
         Self {
             track,
             player_position,
+            player_loop,
+        }
+    }
+
+    /// the x coordinate of a position in seconds, None if the length of the track is unknown
+    fn x_at(&self, seconds: f64, x_max: usize) -> Option<f64> {
+        let duration = self.track.duration()?;
+        Some((seconds / duration * x_max as f64 * 2.0).floor() - x_max as f64)
+    }
+
+    /// draws one line per preview sample. The part of the track, that was already played, is
+    /// dimmed. While the track is analyzed, the missing part is a flat line with the progress.
+    fn draw_waveform(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        let preview_buffer = self.track.preview(x_max * 2);
+        let playhead = self
+            .player_position
+            .as_ref()
+            .map(|player_position| player_position.get_progress() * x_max as f64 * 2.0);
+        let samples = preview_buffer.iter().take(x_max * 2);
+        let drawn = samples.len();
+        for (i, sample) in samples.enumerate() {
+            let x = (-(x_max as i16) + i as i16) as f64;
+            let y = (sample.lows.max(sample.mids).max(sample.highs * 2.) * (y_max as f32)) as f64;
+            let played = playhead.map_or(false, |playhead| (i as f64) < playhead);
+            ctx.draw(&Line {
+                x1: x,
+                x2: x,
+                y1: y,
+                y2: -y,
+                color: band_color(sample, played),
+            });
+        }
+        if !self.track.is_analyzed() && drawn < x_max * 2 {
+            let x = drawn as f64 - x_max as f64;
+            ctx.draw(&Line {
+                x1: x,
+                x2: x_max as f64,
+                y1: 0.,
+                y2: 0.,
+                color: Color::DarkGray,
+            });
+            if let Some(progress) = self.track.progress() {
+                ctx.print(
+                    x,
+                    y_max as f64 * 0.5,
+                    Span::styled(
+                        format!("Analyzing {}%", progress.min(100)),
+                        Style::default().fg(Color::Gray),
+                    ),
+                );
+            }
+        }
+    }
+
+    /// draws the memory cues with their labels at the bottom. Suggested cues are dimmed until
+    /// they are accepted.
+    fn draw_mem_cues(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        let y_max = y_max as f64;
+        for suggestion in self.track.suggested_cues() {
+            if let Some(x) = self.x_at(suggestion.position, x_max) {
+                ctx.draw(&Line {
+                    x1: x,
+                    x2: x,
+                    y1: y_max,
+                    y2: -y_max,
+                    color: Color::DarkGray,
+                });
+            }
+        }
+        for cue in self.track.mem_cues() {
+            if let Some(x) = self.x_at(cue.position, x_max) {
+                let color = Color::from(cue.color);
+                ctx.draw(&Line {
+                    x1: x,
+                    x2: x,
+                    y1: y_max,
+                    y2: -y_max,
+                    color,
+                });
+                if !cue.label.is_empty() {
+                    ctx.print(
+                        x,
+                        -y_max,
+                        Span::styled(cue.label, Style::default().fg(color)),
+                    );
+                }
+            }
+        }
+    }
+
+    /// draws the saved loops and the loop of the player as bars at the top, labeled with their
+    /// length in beats
+    fn draw_loops(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        let mut loops: Vec<(f64, f64, Color)> = self
+            .track
+            .loops()
+            .iter()
+            .map(|saved_loop| (saved_loop.start, saved_loop.end, Color::Cyan))
+            .collect();
+        if let Some(LoopMarker {
+            start,
+            end: Some(end),
+            active,
+        }) = self.player_loop
+        {
+            let color = if *active {
+                Color::LightGreen
+            } else {
+                Color::Green
+            };
+            loops.push((
+                start.get_time_in_seconds(),
+                end.get_time_in_seconds(),
+                color,
+            ));
+        }
+        let beatgrid = self.track.beatgrid();
+        let y_max = y_max as f64;
+        for (start, end, color) in loops {
+            if let (Some(x1), Some(x2)) = (self.x_at(start, x_max), self.x_at(end, x_max)) {
+                ctx.draw(&Line {
+                    x1,
+                    x2,
+                    y1: y_max,
+                    y2: y_max,
+                    color,
+                });
+                if let Some(beatgrid) = &beatgrid {
+                    let beats = beatgrid.beat_index_at(end) - beatgrid.beat_index_at(start);
+                    ctx.print(
+                        x1,
+                        y_max * 0.5,
+                        Span::styled(Loop::beats_name(beats), Style::default().fg(color)),
+                    );
+                }
+            }
+        }
+    }
+
+    /// draws the numbered hot cue markers
+    fn draw_hot_cues(&self, ctx: &mut Context, x_max: usize, y_max: usize) {
+        let y_max = y_max as f64;
+        for (slot, cue) in self.track.hot_cues() {
+            if let Some(x) = self.x_at(cue.position, x_max) {
+                let color = Color::from(cue.color);
+                ctx.draw(&Line {
+                    x1: x,
+                    x2: x,
+                    y1: y_max,
+                    y2: -y_max,
+                    color,
+                });
+                ctx.print(
+                    x,
+                    y_max,
+                    Span::styled(HotCue::slot_name(slot), Style::default().fg(color)),
+                );
+            }
         }
     }
 }
@@ -26,29 +204,13 @@ impl<'a> Widget for PreviewWidget<'a> {
     fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
         let x_max = area.width as usize;
         let y_max = area.height as usize;
-        let preview_buffer = &self.track.preview(x_max * 2);
 
         let canvas = Canvas::default()
             .block(Block::default())
             .x_bounds([-(x_max as f64), x_max as f64])
             .y_bounds([-(y_max as f64), y_max as f64])
             .paint(|ctx| {
-                //
-                for (i, sample) in preview_buffer.iter().take((x_max * 2) as usize).enumerate() {
-                    //
-                    let x = (-(x_max as i16) + i as i16) as f64;
-                    let y = (sample.lows * (y_max as f32)) as f64;
-                    // let y = 1. * 20.;
-                    // // clip the signal if too hight
-                    // let y = if y > (y_max as f64) { y_max as f64 } else { y };
-                    ctx.draw(&Line {
-                        x1: x,
-                        x2: x,
-                        y1: y,
-                        y2: -y,
-                        color: Color::Gray,
-                    });
-                }
+                self.draw_waveform(ctx, x_max, y_max);
                 ctx.layer();
 
                 if let Some(player_position) = self.player_position {
@@ -63,19 +225,20 @@ impl<'a> Widget for PreviewWidget<'a> {
                         color: Color::Red,
                     })
                 }
-                for marker in &(*self.track.mem_cues.lock().unwrap()) {
-                    let relative_pos = marker.get_progress();
-                    let x = relative_pos * x_max as f64 * 2.0;
-                    let x = x.floor() as isize - x_max as isize;
-                    ctx.draw(&Line {
-                        x1: x as f64,
-                        x2: x as f64,
-                        y1: y_max as f64,
-                        y2: -(y_max as f64),
-                        color: Color::Green,
-                    });
-                }
+                self.draw_mem_cues(ctx, x_max, y_max);
+                self.draw_loops(ctx, x_max, y_max);
+                self.draw_hot_cues(ctx, x_max, y_max);
             });
         canvas.render(area, buf);
     }
 }
+
+/// blends the bands of a sample into one color, so bass heavy parts are red and hi-hats blue.
+/// The loudest band gets full brightness.
+fn band_color(sample: &PreviewSample, played: bool) -> Color {
+    let bands = [sample.lows, sample.mids, sample.highs * 2.];
+    let loudest = bands.iter().cloned().fold(f32::EPSILON, f32::max);
+    let brightness = if played { PLAYED_BRIGHTNESS } else { 1. };
+    let [r, g, b] = bands.map(|band| (band / loudest * brightness * 255.).round() as u8);
+    Color::Rgb(r, g, b)
+}
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use tui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Row, Table, Widget},
+};
+
+//------------------------------------------------------------------//
+//                          RootListWidget                          //
+//------------------------------------------------------------------//
+
+/// A Widget for adding and removing the directories, that are scanned for tracks. Each root is
+/// listed with the number of its tracks.
+pub struct RootListWidget<'a> {
+    /// the roots with their number of listed tracks
+    roots: &'a [(PathBuf, usize)],
+    root_list: &'a RootList,
+}
+
+impl<'a> RootListWidget<'a> {
+    pub fn new(roots: &'a [(PathBuf, usize)], root_list: &'a RootList) -> Self {
+        Self { roots, root_list }
+    }
+
+    fn get_row(&self, index: usize, root: &PathBuf, tracks: usize) -> Row<'_> {
+        let style = if index == self.root_list.selected && self.root_list.adding.is_none() {
+            Style::default().fg(Color::Black).bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        // e.g. an external drive, that is not mounted
+        let status = if root.is_dir() {
+            Cell::from(tracks.to_string())
+        } else {
+            Cell::from("missing").style(Style::default().fg(Color::Red))
+        };
+        Row::new(vec![Cell::from(root.display().to_string()), status]).style(style)
+    }
+}
+
+impl<'a> Widget for RootListWidget<'a> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        let header = Row::new(vec!["Directory", "Tracks"])
+            .style(Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED))
+            .bottom_margin(1);
+        let mut rows: Vec<Row> = self
+            .roots
+            .iter()
+            .enumerate()
+            .map(|(index, (root, tracks))| self.get_row(index, root, *tracks))
+            .collect();
+        // the path under edit with a cursor
+        if let Some(path) = &self.root_list.adding {
+            rows.push(
+                Row::new(vec![Cell::from(format!("{}_", path)), Cell::from("new")])
+                    .style(Style::default().fg(Color::Black).bg(Color::DarkGray)),
+            );
+        }
+        let title = "Library Roots - a: add, d: remove, esc: close";
+        let widths = [Constraint::Percentage(100), Constraint::Length(8)];
+        let table = Table::new(rows)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .header(header)
+            .style(Style::default().fg(Color::White))
+            .widths(&widths)
+            .column_spacing(1);
+        table.render(area, buf);
+    }
+}
+
+//------------------------------------------------------------------//
+//                             RootList                             //
+//------------------------------------------------------------------//
+
+/// State of the library root editor
+#[derive(Default)]
+pub struct RootList {
+    /// index of the selected root
+    pub selected: usize,
+    /// the path of the new root, while it is being typed
+    pub adding: Option<String>,
+}
+
+impl RootList {
+    /// select the next root of a list with len roots, wrapping at the end
+    pub fn select_next(&mut self, len: usize) {
+        self.selected = if self.selected + 1 < len {
+            self.selected + 1
+        } else {
+            0
+        };
+    }
+
+    /// select the previous root of a list with len roots, wrapping at the start
+    pub fn select_previous(&mut self, len: usize) {
+        self.selected = if self.selected > 0 {
+            self.selected - 1
+        } else {
+            len.saturating_sub(1)
+        };
+    }
+
+    /// keeps the selection within a list with len roots, e.g. after removing the last root
+    pub fn clamp(&mut self, len: usize) {
+        self.selected = self.selected.min(len.saturating_sub(1));
+    }
+}
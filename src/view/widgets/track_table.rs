@@ -4,7 +4,7 @@ use std::sync::Arc;
 use indexmap::IndexSet;
 use tui::{layout::Constraint, style::{Color, Modifier, Style}, widgets::{Block, Borders, Cell, Row, Table, Widget}};
 
-use crate::view::model::track::Track;
+use crate::view::model::{key::KeyNotation, track::Track};
 
 //------------------------------------------------------------------//
 //                         TrackTableWidget                         //
@@ -14,10 +14,15 @@ use crate::view::model::track::Track;
 pub struct TrackTableWidget<'a> {
     tracks: &'a TrackList,
     focused: bool,
+    key_notation: KeyNotation,
 }
 impl<'a> TrackTableWidget<'a> {
-    pub fn new(tracks: &'a TrackList, focused: bool) -> Self {
-        Self { tracks, focused }
+    pub fn new(tracks: &'a TrackList, focused: bool, key_notation: KeyNotation) -> Self {
+        Self {
+            tracks,
+            focused,
+            key_notation,
+        }
     }
 
     /// returns a TUI Row objed, with specific styling based on, whether the row is focused or an
@@ -28,23 +33,44 @@ impl<'a> TrackTableWidget<'a> {
         // if progress could be computed return it in formatted form, else return string "NaN"
         let progress_string = track.progress().map_or(String::from("Nan"),|progress|{ format!("{}%", progress) });
         let meta = track.meta.read().unwrap();
-        let bpm = format!("{}",meta.bpm);
+        let bpm = meta.bpm.map_or(String::from(""), |bpm| bpm.to_string());
+        let key = meta
+            .key
+            .map_or(String::from(""), |key| key.to_notation(self.key_notation));
         let title = meta.title.clone();
         let artist = meta.artist.clone();
         let style = if focused {Style::default().fg(Color::Black).bg(Color::DarkGray)}else {Style::default()};
-        Row::new(vec![Cell::from(track.file_name.to_string()), Cell::from(title), Cell::from(artist) , Cell::from(progress_string), Cell::from(bpm)]).style(style)
+        Row::new(vec![
+            Cell::from(track.file_name.to_string()),
+            Cell::from(title),
+            Cell::from(artist),
+            Cell::from(progress_string),
+            Cell::from(bpm),
+            Cell::from(key),
+        ])
+        .style(style)
     }
 
     fn get_header(&self) -> Row {
         // || filename || analyzed_percentage
         let style = Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
-        Row::new(vec!["File Name","Title","Artist", "Analysis", "BPM"]).bottom_margin(0).style(style).bottom_margin(1)
+        Row::new(vec![
+            "File Name",
+            "Title",
+            "Artist",
+            "Analysis",
+            "BPM",
+            "Key",
+        ])
+        .bottom_margin(0)
+        .style(style)
+        .bottom_margin(1)
     }
 }
 impl<'a> Widget for TrackTableWidget<'a> {
     fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
         let header = self.get_header();
-        let num_colums = 5 as usize;
+        let num_colums = 6 as usize;
         let auto_widths = vec![Constraint::Percentage(100/num_colums as u16);num_colums];
         let rows: Vec<Row> = self
             .tracks
@@ -69,7 +95,6 @@ impl<'a> Widget for TrackTableWidget<'a> {
 pub struct TrackList {
     tracks: IndexSet<Arc<Track>>,
     focused_track: Option<usize>,
-    loaded_track: Option<usize>,
 }
 
 impl TrackList {
@@ -86,6 +111,14 @@ impl TrackList {
         todo!();
     }
 
+    /// returns the track with the given file path
+    pub fn get(&self, file_path: &str) -> Option<Arc<Track>> {
+        self.tracks
+            .iter()
+            .find(|track| track.file_path == file_path)
+            .map(Arc::clone)
+    }
+
     /// returns the currently focused track
     pub fn get_focused(&self) -> Option<Arc<Track>> {
         self.focused_track.map(|i| { 
@@ -93,13 +126,6 @@ impl TrackList {
             Arc::clone(track) })
     }
 
-    /// returns the currently loaded track
-    pub fn get_loaded(&self) -> Option<Arc<Track>> {
-        self.loaded_track.map(|i| { 
-            let track = &self.tracks[i];
-            Arc::clone(track) })
-    }
-
     /// focus next track and return it
     pub fn focus_next(&mut self) -> Option<Arc<Track>> {
         let new_index = self.focused_track.map(|i| {
@@ -134,12 +160,6 @@ impl TrackList {
         self.get_focused()
     }
 
-    /// mark a track as loaded and return reference of loaded track
-    pub fn load_focused(&mut self) -> Option<Arc<Track>> {
-        self.loaded_track = self.focused_track;
-        self.get_focused()
-    }
-
     /// push a single track to the list
     pub fn insert(&mut self, track: Arc<Track>) {
         if self.tracks.len() == 0 {
@@ -147,6 +167,18 @@ impl TrackList {
         }
         self.tracks.insert(Arc::clone(&track));
     }
+
+    /// removes all tracks, for which the predicate is false
+    pub fn retain(&mut self, mut keep: impl FnMut(&Arc<Track>) -> bool) {
+        self.tracks.retain(|track| keep(track));
+        self.focused_track = if self.tracks.is_empty() {
+            None
+        } else {
+            self.focused_track
+                .map(|i| i.min(self.tracks.len() - 1))
+                .or(Some(0))
+        };
+    }
 }
 
 impl<'a> Default for TrackList {
@@ -154,7 +186,6 @@ impl<'a> Default for TrackList {
         Self {
             tracks: IndexSet::default(),
             focused_track: None,
-            loaded_track: None,
         }
     }
 }